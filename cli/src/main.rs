@@ -1,7 +1,10 @@
-use bluepaper_core::MarkdownToLatex;
+use bluepaper_core::{
+    bibliography::{BibFile, BIBTEX_FILE_NAME},
+    ConversionOptions, Engine, InputDialect, Language, MarkdownToLatex,
+};
 
 use confy;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use structopt::StructOpt;
@@ -26,6 +29,42 @@ struct Opt {
     #[structopt(short, long)]
     input: Option<String>,
 
+    /// Markdown dialect of the input ("paper", "commonmark", "gfm", or "obsidian").
+    /// Defaults to "paper" for documents downloaded from Dropbox Paper and to
+    /// "commonmark" for documents read from a local file or from STDIN.
+    #[structopt(short, long)]
+    dialect: Option<InputDialect>,
+
+    /// LaTeX engine that the generated code targets ("pdflatex", "xelatex", or
+    /// "lualatex"). Defaults to "xelatex", which is the engine that the PDF file is
+    /// compiled with. For other engines, only the LaTeX file is written.
+    #[structopt(long)]
+    engine: Option<Engine>,
+
+    /// Language of the document (e.g., "english", "german", or "de"), used for
+    /// hyphenation and quotation marks. Set to "none" to turn off language specific
+    /// packages. Defaults to detecting the language from the text.
+    #[structopt(long)]
+    language: Option<String>,
+
+    /// Typeset links to DOIs, arXiv preprints, and ISBNs as citations.
+    /// The bibliography is written to a file "references.bib" next to the LaTeX file.
+    #[structopt(long)]
+    cite_links: bool,
+
+    /// Resolve pandoc-style citations ("[@key]" or "@key") with the given BibTeX file.
+    /// Turns on recognition of the citation syntax and warns about citation keys that
+    /// aren't defined in the file. Cited entries are copied to a file "references.bib"
+    /// next to the LaTeX file.
+    #[structopt(long)]
+    bibliography: Option<String>,
+
+    /// Guarantee that the Markdown can't execute arbitrary TeX code (e.g., for
+    /// untrusted documents). Math is restricted to a whitelist of harmless commands
+    /// and is typeset as code if it contains anything else.
+    #[structopt(long)]
+    safe_mode: bool,
+
     /// Specify custom output path for main LaTeX file.
     /// If not specified, a save output file name will be chosen based on the document
     /// title in such a way that no existing files are overwritten. This option
@@ -80,6 +119,47 @@ fn run_cli(opt: Opt) -> Result<(), String> {
         (markdown, Some(meta_data))
     };
 
+    let dialect = opt.dialect.unwrap_or(if meta_data.is_some() {
+        InputDialect::DropboxPaper
+    } else {
+        InputDialect::CommonMark
+    });
+    let bib_file = if let Some(path) = &opt.bibliography {
+        info!("Reading bibliography from file \"{}\" ...", path);
+        let bibtex = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading bibliography file: {}", e))?;
+        Some(BibFile::parse(&bibtex))
+    } else {
+        None
+    };
+
+    // Tectonic, which we use to compile the PDF file, is based on XeTeX.
+    let engine = opt.engine.unwrap_or(Engine::XeLatex);
+    let (language, detect_language) = match opt.language.as_deref() {
+        None | Some("auto") => (None, true),
+        Some("none") => (None, false),
+        Some(language) => (Some(language.parse::<Language>()?), false),
+    };
+    let options = ConversionOptions {
+        engine,
+        language,
+        detect_language,
+        cite_links: opt.cite_links,
+        citation_syntax: bib_file.is_some(),
+        safe_mode: opt.safe_mode,
+        ..ConversionOptions::for_dialect(dialect)
+    };
+    let mut converter = MarkdownToLatex::with_options(markdown, options);
+    if let Some(mut bib_file) = bib_file {
+        for key in converter.resolve_citations(&mut bib_file) {
+            warn!(
+                "Citation key \"{}\" is not defined in the bibliography file.",
+                key
+            );
+        }
+    }
+    let bibtex = converter.bibtex();
+
     // The dynamic dispatch shouldn't really hurt much here because we wrap `output`
     // in a BufWriter below, so writes to the inner `output` will be infrequent.
     let (mut latex_output, latex_path): (Box<dyn Write>, _) = if let Some(path) = opt.output {
@@ -99,6 +179,7 @@ fn run_cli(opt: Opt) -> Result<(), String> {
         let title = meta_data
             .as_ref()
             .map(|m| &m.title[..])
+            .or_else(|| converter.metadata().title.as_deref())
             .unwrap_or_else(|| "Bluepaper");
 
         let title_beginning = title
@@ -127,7 +208,7 @@ fn run_cli(opt: Opt) -> Result<(), String> {
     };
 
     if let Some(latex_path) = latex_path {
-        let latex = MarkdownToLatex::from_string(markdown).into_string();
+        let latex = converter.into_string();
         latex_output
             .write_all(latex.as_bytes())
             .map_err(|e| format!("IO error when writing LaTeX file: {}", e))?;
@@ -137,6 +218,45 @@ fn run_cli(opt: Opt) -> Result<(), String> {
         } else {
             &latex_path
         };
+
+        if let Some(bibtex) = bibtex {
+            let bib_path = std::path::Path::new(&latex_path).with_file_name(BIBTEX_FILE_NAME);
+            let is_input = opt.bibliography.as_ref().is_some_and(|input| {
+                match (
+                    std::fs::canonicalize(input),
+                    std::fs::canonicalize(&bib_path),
+                ) {
+                    (Ok(input), Ok(output)) => input == output,
+                    _ => false,
+                }
+            });
+            if is_input {
+                warn!(
+                    "Not overwriting the bibliography file \"{}\". Any works that are cited \
+                     by links are missing from it.",
+                    bib_path.display()
+                );
+            } else {
+                info!(
+                    "Writing bibliography to file \"{}\" ...",
+                    bib_path.display()
+                );
+                std::fs::write(&bib_path, bibtex)
+                    .map_err(|e| format!("IO error when writing bibliography file: {}", e))?;
+            }
+            warn!(
+                "The PDF file contains unresolved citations. Compile the LaTeX file with \
+                 bibtex to resolve them."
+            );
+        }
+        if engine != Engine::XeLatex {
+            info!(
+                "Not generating a PDF file because the LaTeX code targets {:?}, which is \
+                 not supported by the built-in XeTeX engine.",
+                engine
+            );
+            return Ok(());
+        }
         let pdf_path = format!("{}.pdf", basename);
 
         info!(
@@ -150,9 +270,15 @@ fn run_cli(opt: Opt) -> Result<(), String> {
             .write_all(&pdf_data)
             .map_err(|e| format!("IO error when writing PDF file: {}", e))?;
     } else {
-        MarkdownToLatex::from_string(markdown)
+        converter
             .write_to(BufWriter::new(latex_output))
             .map_err(|e| format!("IO Error on terminal output: {}", e))?;
+        if bibtex.is_some() {
+            warn!(
+                "Not writing the bibliography because the LaTeX code is printed to STDOUT. \
+                 Use the \"--output\" option to write to a file instead."
+            );
+        }
     }
 
     Ok(())
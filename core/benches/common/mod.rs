@@ -0,0 +1,206 @@
+//! Synthetic documents in the style of Dropbox Paper exports, shared by the
+//! benchmarks.
+
+#![allow(dead_code)]
+
+const WORDS: &[&str] = &[
+    "the",
+    "model",
+    "of",
+    "we",
+    "data",
+    "and",
+    "posterior",
+    "is",
+    "a",
+    "variational",
+    "to",
+    "distribution",
+    "in",
+    "inference",
+    "that",
+    "gradient",
+    "for",
+    "with",
+    "latent",
+    "this",
+    "estimate",
+    "on",
+    "by",
+    "objective",
+    "are",
+    "as",
+    "sample",
+    "which",
+    "loss",
+    "bound",
+    "not",
+    "parameters",
+    "be",
+    "training",
+    "an",
+    "expected",
+    "it",
+    "method",
+];
+
+const INLINE_MATH: &[&str] = &[
+    "$$x$$",
+    "$$\\theta_t$$",
+    "$$p(z \\mid x)$$",
+    "$$\\mathbb{E}_{q}[\\log p(x, z)]$$",
+    "$$\\nabla_\\phi \\mathcal{L}$$",
+    "$$\\sum_{i=1}^N x_i^2$$",
+];
+
+const DISPLAY_MATH: &[&str] = &[
+    "$$\\mathcal{L}(\\phi) = \\mathbb{E}_{q_\\phi(z)}\\left[\\log p(x, z) - \\log q_\\phi(z)\\right]$$",
+    "$$\\frac{\\partial f}{\\partial x} = \\lim_{h \\to 0} \\frac{f(x + h) - f(x)}{h}$$",
+    "$$\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}^{-1} = \\frac{1}{ad - bc} \\begin{pmatrix} d & -b \\\\ -c & a \\end{pmatrix}$$",
+];
+
+/// A deterministic pseudo-random number generator, so that benchmarks are
+/// reproducible.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        ((self.0 >> 33) % bound as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.next(items.len())]
+    }
+}
+
+/// Appends a sentence of about 15 words with occasional emphasis, quotes, and
+/// punctuation that has to be escaped. About `math_density` out of 40 words are inline math.
+fn sentence(rng: &mut Lcg, math_density: usize, out: &mut String) {
+    let len = 8 + rng.next(15);
+    for i in 0..len {
+        if i != 0 {
+            out.push(' ');
+        }
+        match rng.next(40) {
+            n if n < math_density => out.push_str(rng.pick(INLINE_MATH)),
+            0 => {
+                out.push('*');
+                out.push_str(rng.pick(WORDS));
+                out.push('*');
+            }
+            1 => {
+                out.push_str(rng.pick(WORDS));
+                out.push_str(" & ");
+                out.push_str(rng.pick(WORDS));
+            }
+            2 => out.push_str("50%"),
+            3 => {
+                out.push('"');
+                out.push_str(rng.pick(WORDS));
+                out.push('"');
+            }
+            4 => out.push_str("it's"),
+            5 => {
+                out.push_str("**");
+                out.push_str(rng.pick(WORDS));
+                out.push_str("**");
+            }
+            _ => out.push_str(rng.pick(WORDS)),
+        }
+    }
+    out.push_str(". ");
+}
+
+fn paragraph(rng: &mut Lcg, math_density: usize, out: &mut String) {
+    for _ in 0..3 + rng.next(5) {
+        sentence(rng, math_density, out);
+    }
+    out.push_str("\n\n");
+}
+
+fn list(rng: &mut Lcg, depth: usize, out: &mut String) {
+    let ordered = rng.next(2) == 0;
+    for i in 0..2 + rng.next(5) {
+        for _ in 0..depth {
+            out.push_str("    ");
+        }
+        if ordered {
+            out.push_str(&format!("{}. ", i + 1));
+        } else {
+            out.push_str("- ");
+        }
+        sentence(rng, 2, out);
+        out.push('\n');
+        if depth < 3 && rng.next(3) == 0 {
+            list(rng, depth + 1, out);
+        }
+    }
+}
+
+/// A document with a mix of headings, prose, inline and display math, and lists
+/// that has `sections` sections (about 4 KB each).
+pub fn mixed(sections: usize) -> String {
+    let mut rng = Lcg(42);
+    let mut out = String::from("# A Synthetic Document\n\n");
+    for section in 0..sections {
+        out.push_str(&format!("## Section {}\n\n", section + 1));
+        for _ in 0..4 {
+            match rng.next(4) {
+                0 => {
+                    out.push_str(rng.pick(DISPLAY_MATH));
+                    out.push_str("\n\n");
+                }
+                1 => {
+                    list(&mut rng, 0, &mut out);
+                    out.push('\n');
+                }
+                _ => paragraph(&mut rng, 3, &mut out),
+            }
+        }
+    }
+    out
+}
+
+/// A document of about `size` bytes that consists mostly of inline and display
+/// math.
+pub fn math_heavy(size: usize) -> String {
+    let mut rng = Lcg(1);
+    let mut out = String::from("# Derivations\n\n");
+    while out.len() < size {
+        paragraph(&mut rng, 20, &mut out);
+        out.push_str(rng.pick(DISPLAY_MATH));
+        out.push_str("\n\n");
+    }
+    out
+}
+
+/// A document of about `size` bytes that consists mostly of nested lists.
+pub fn list_heavy(size: usize) -> String {
+    let mut rng = Lcg(2);
+    let mut out = String::from("# Meeting Notes\n\n");
+    while out.len() < size {
+        out.push_str("Action items:\n\n");
+        list(&mut rng, 0, &mut out);
+        out.push('\n');
+    }
+    out
+}
+
+/// A document of about `size` bytes of long prose with headings but without math.
+pub fn prose(size: usize) -> String {
+    let mut rng = Lcg(3);
+    let mut out = String::from("# An Essay\n\n");
+    let mut section = 0;
+    while out.len() < size {
+        section += 1;
+        out.push_str(&format!("## Chapter {}\n\n", section));
+        for _ in 0..8 {
+            paragraph(&mut rng, 0, &mut out);
+        }
+    }
+    out
+}
@@ -0,0 +1,52 @@
+//! Benchmarks of the conversion of synthetic documents of 1 MB each and of
+//! escaping text.
+//!
+//! Run with `cargo bench --bench convert`. The converter makes several passes of the
+//! markdown parser over a document, so new features should skip their pass if the
+//! document can't contain the relevant syntax.
+
+mod common;
+
+use bluepaper_core::latex_escape::escape_str;
+use bluepaper_core::{ConversionOptions, InputDialect, MarkdownToLatex};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const SIZE: usize = 1_000_000;
+
+fn convert(c: &mut Criterion) {
+    let documents = [
+        ("math_heavy", common::math_heavy(SIZE)),
+        ("list_heavy", common::list_heavy(SIZE)),
+        ("prose", common::prose(SIZE)),
+    ];
+
+    let mut group = c.benchmark_group("convert");
+    group.sample_size(20);
+    for (name, markdown) in documents.iter() {
+        group.throughput(Throughput::Bytes(markdown.len() as u64));
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                let options = ConversionOptions::for_dialect(InputDialect::DropboxPaper);
+                MarkdownToLatex::with_options(markdown.clone(), options)
+                    .write_to(std::io::sink())
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn escape(c: &mut Criterion) {
+    let text = common::prose(SIZE);
+
+    let mut group = c.benchmark_group("escape_str");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_function("prose", |b| {
+        b.iter(|| escape_str(black_box(&text), &mut std::io::sink()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, convert, escape);
+criterion_main!(benches);
@@ -0,0 +1,100 @@
+//! Measures the peak heap memory and the time needed to convert a large document
+//! from a file, relative to the size of the file.
+//!
+//! Run with `cargo bench --bench memory`.
+
+mod common;
+
+use bluepaper_core::MarkdownToLatex;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// An allocator that keeps track of the current and the peak heap usage.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+// SAFETY: Delegates all allocations to the system allocator and only counts bytes.
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOC: PeakAlloc = PeakAlloc;
+
+/// Runs `f` and returns the additional peak heap usage in bytes and the time in
+/// seconds.
+fn measure(f: impl FnOnce()) -> (usize, f64) {
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let start = Instant::now();
+    f();
+    let seconds = start.elapsed().as_secs_f64();
+    (PEAK.load(Ordering::Relaxed) - baseline, seconds)
+}
+
+fn main() {
+    let path = std::env::temp_dir().join("bluepaper_memory_benchmark.md");
+    let markdown = common::mixed(2500);
+    std::fs::write(&path, &markdown).unwrap();
+    let input_size = markdown.len();
+    drop(markdown);
+
+    println!(
+        "Converting {:.1} MB of markdown (peak heap relative to input size):",
+        input_size as f64 / 1e6
+    );
+    let report = |name: &str, (peak, seconds): (usize, f64)| {
+        println!(
+            "{:<40} {:>5.2} x input, {:>6.3} s",
+            name,
+            peak as f64 / input_size as f64,
+            seconds
+        );
+    };
+
+    report(
+        "from_reader + write_to(sink)",
+        measure(|| {
+            let file = std::fs::File::open(&path).unwrap();
+            let converter = MarkdownToLatex::from_reader(file).unwrap();
+            converter.write_to(std::io::sink()).unwrap();
+        }),
+    );
+
+    report(
+        "read_to_string + from_string + write_to",
+        measure(|| {
+            let markdown = std::fs::read_to_string(&path).unwrap();
+            let converter = MarkdownToLatex::from_string(markdown);
+            converter.write_to(std::io::sink()).unwrap();
+        }),
+    );
+
+    report(
+        "from_reader + into_string",
+        measure(|| {
+            let file = std::fs::File::open(&path).unwrap();
+            let latex = MarkdownToLatex::from_reader(file).unwrap().into_string();
+            std::io::sink().write_all(latex.as_bytes()).unwrap();
+        }),
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
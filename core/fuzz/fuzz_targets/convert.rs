@@ -0,0 +1,40 @@
+//! Converts arbitrary UTF-8 input with each input dialect and checks that the
+//! output is valid UTF-8.
+//!
+//! Run with `cargo fuzz run convert` from the `core` directory.
+
+#![no_main]
+
+use bluepaper_core::{ConversionOptions, InputDialect, MarkdownToLatex};
+use libfuzzer_sys::fuzz_target;
+
+const DIALECTS: [InputDialect; 4] = [
+    InputDialect::DropboxPaper,
+    InputDialect::CommonMark,
+    InputDialect::Gfm,
+    InputDialect::Obsidian,
+];
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte selects the options, the rest is the markdown source.
+    let (selector, markdown) = match data.split_first() {
+        Some((&selector, markdown)) => (selector, markdown),
+        None => return,
+    };
+    let markdown = match std::str::from_utf8(markdown) {
+        Ok(markdown) => markdown.to_string(),
+        Err(_) => return,
+    };
+
+    let mut options = ConversionOptions::for_dialect(DIALECTS[selector as usize % 4]);
+    options.broken_math_as_code = selector & 0x04 != 0;
+    options.smart_quotes = selector & 0x08 != 0;
+    options.safe_mode = selector & 0x10 != 0;
+    options.description_lists = selector & 0x20 != 0;
+    options.todo_summary = selector & 0x40 != 0;
+
+    let latex = MarkdownToLatex::with_options(markdown, options)
+        .write_to(Vec::new())
+        .unwrap();
+    assert!(std::str::from_utf8(&latex).is_ok());
+});
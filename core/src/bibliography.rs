@@ -0,0 +1,904 @@
+//! Citations and BibTeX bibliographies.
+//!
+//! If [`ConversionOptions::cite_links`] is set then links to a DOI, an arXiv
+//! preprint, or an ISBN are typeset as citations (`\cite{key}`) rather than as
+//! hyperlinks, and [`MarkdownToLatex::bibtex`] returns the contents of a BibTeX file
+//! with one entry per cited work. The generated LaTeX expects this file to be saved
+//! as [`BIBTEX_FILE_NAME`] next to the LaTeX file.
+//!
+//! The bibliographic data (authors, title, ...) of cited works is looked up in a
+//! [`MetadataSource`], see [`MarkdownToLatex::resolve_citations`]. Works that are
+//! not found in the metadata source still get a (minimal) BibTeX entry that contains
+//! only the identifier.
+//!
+//! If [`ConversionOptions::citation_syntax`] is set then pandoc-style citations
+//! (`[@key]` or `@key`) are typeset with `natbib` commands. Their citation keys are
+//! looked up with [`MetadataSource::lookup_key`], e.g., in a user supplied
+//! [`BibFile`]. Entries that were found are copied to the generated BibTeX file.
+//!
+//! [`ConversionOptions::cite_links`]: ../struct.ConversionOptions.html#structfield.cite_links
+//! [`MarkdownToLatex::bibtex`]: ../struct.MarkdownToLatex.html#method.bibtex
+//! [`MarkdownToLatex::resolve_citations`]: ../struct.MarkdownToLatex.html#method.resolve_citations
+//! [`BIBTEX_FILE_NAME`]: constant.BIBTEX_FILE_NAME.html
+//! [`MetadataSource`]: trait.MetadataSource.html
+//! [`ConversionOptions::citation_syntax`]: ../struct.ConversionOptions.html#structfield.citation_syntax
+//! [`MetadataSource::lookup_key`]: trait.MetadataSource.html#method.lookup_key
+//! [`BibFile`]: struct.BibFile.html
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use log::warn;
+
+/// The file name under which the output of
+/// [`MarkdownToLatex::bibtex`](../struct.MarkdownToLatex.html#method.bibtex) has to
+/// be saved (in the same directory as the LaTeX file).
+pub const BIBTEX_FILE_NAME: &str = "references.bib";
+
+/// A persistent identifier of a cited work.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CitationId {
+    /// A digital object identifier without any prefix, e.g., `"10.1000/xyz123"`.
+    Doi(String),
+
+    /// An arXiv identifier without version, e.g., `"2001.01234"` or
+    /// `"hep-th/9901001"`.
+    Arxiv(String),
+
+    /// An ISBN-10 or ISBN-13 without hyphens, e.g., `"9780262035613"`.
+    Isbn(String),
+}
+
+impl CitationId {
+    /// Recognizes a URL that points to a DOI, an arXiv preprint, or an ISBN.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bluepaper_core::bibliography::CitationId;
+    ///
+    /// assert_eq!(
+    ///     CitationId::from_url("https://doi.org/10.1000/xyz123"),
+    ///     Some(CitationId::Doi("10.1000/xyz123".to_string()))
+    /// );
+    /// assert_eq!(
+    ///     CitationId::from_url("https://arxiv.org/pdf/2001.01234v2.pdf"),
+    ///     Some(CitationId::Arxiv("2001.01234".to_string()))
+    /// );
+    /// assert_eq!(
+    ///     CitationId::from_url("urn:isbn:978-0-262-03561-3"),
+    ///     Some(CitationId::Isbn("9780262035613".to_string()))
+    /// );
+    /// assert_eq!(CitationId::from_url("https://example.com/10.1000/xyz123"), None);
+    /// ```
+    pub fn from_url(url: &str) -> Option<Self> {
+        let url = url.trim();
+        let lowercase = url.to_ascii_lowercase();
+
+        if let Some(doi) = strip_prefix_ignore_case(url, &lowercase, &["doi:"]) {
+            return Self::doi(doi);
+        }
+        if let Some(arxiv) = strip_prefix_ignore_case(url, &lowercase, &["arxiv:"]) {
+            return Self::arxiv(arxiv);
+        }
+        if let Some(isbn) = strip_prefix_ignore_case(url, &lowercase, &["urn:isbn:", "isbn:"]) {
+            return Self::isbn(isbn);
+        }
+
+        let without_scheme = strip_prefix_ignore_case(url, &lowercase, &["https://", "http://"])?;
+        let lowercase = without_scheme.to_ascii_lowercase();
+        let (host, path) = match lowercase.find('/') {
+            Some(slash) => (&lowercase[..slash], &without_scheme[slash + 1..]),
+            None => return None,
+        };
+        let host = host.strip_prefix("www.").unwrap_or(host);
+
+        match host {
+            "doi.org" | "dx.doi.org" => Self::doi(&path.replace("%2F", "/").replace("%2f", "/")),
+            "arxiv.org" | "export.arxiv.org" => {
+                let id = path
+                    .strip_prefix("abs/")
+                    .or_else(|| path.strip_prefix("pdf/"))?;
+                Self::arxiv(id.strip_suffix(".pdf").unwrap_or(id))
+            }
+            _ => {
+                let (_, isbn) = path.split_at(path.to_ascii_lowercase().find("isbn/")? + 5);
+                Self::isbn(isbn)
+            }
+        }
+    }
+
+    /// Accepts DOIs whose suffix consists of the characters recommended by Crossref,
+    /// so that a DOI never contains braces or backslashes.
+    fn doi(doi: &str) -> Option<Self> {
+        let doi = doi.split(['?', '#']).next()?;
+        let rest = doi.strip_prefix("10.")?;
+        let slash = rest.find('/')?;
+        let (prefix, suffix) = (&rest[..slash], &rest[slash + 1..]);
+        let valid = !prefix.is_empty()
+            && prefix.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+            && !suffix.is_empty()
+            && suffix
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._;()/:".contains(&b));
+
+        if valid {
+            Some(CitationId::Doi(doi.to_string()))
+        } else {
+            None
+        }
+    }
+
+    fn arxiv(id: &str) -> Option<Self> {
+        let id = id.split(['?', '#']).next()?.trim_end_matches('/');
+        let id = match id.rfind('v') {
+            Some(v) if v + 1 < id.len() && id[v + 1..].bytes().all(|b| b.is_ascii_digit()) => {
+                &id[..v]
+            }
+            _ => id,
+        };
+
+        let is_new_style = |id: &str| {
+            let mut parts = id.splitn(2, '.');
+            let (yymm, number) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            yymm.len() == 4
+                && (4..=5).contains(&number.len())
+                && yymm
+                    .bytes()
+                    .chain(number.bytes())
+                    .all(|b| b.is_ascii_digit())
+        };
+        let is_old_style = |id: &str| {
+            let mut parts = id.splitn(2, '/');
+            let (archive, number) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            !archive.is_empty()
+                && archive
+                    .bytes()
+                    .all(|b| b.is_ascii_alphabetic() || b == b'-' || b == b'.')
+                && number.len() == 7
+                && number.bytes().all(|b| b.is_ascii_digit())
+        };
+
+        if is_new_style(id) || is_old_style(id) {
+            Some(CitationId::Arxiv(id.to_string()))
+        } else {
+            None
+        }
+    }
+
+    fn isbn(isbn: &str) -> Option<Self> {
+        let isbn = isbn
+            .split(['?', '#', '/'])
+            .next()?
+            .chars()
+            .filter(|&c| c != '-' && c != ' ')
+            .map(|c| c.to_ascii_uppercase())
+            .collect::<String>();
+
+        let digit = |c: char| c.to_digit(10);
+        let valid = match isbn.len() {
+            10 => isbn
+                .chars()
+                .enumerate()
+                .map(|(i, c)| match (i, c) {
+                    (9, 'X') => Some(10),
+                    (_, c) => digit(c),
+                })
+                .enumerate()
+                .try_fold(0, |sum, (i, d)| d.map(|d| sum + (10 - i as u32) * d))
+                .is_some_and(|sum| sum.is_multiple_of(11)),
+            13 => isbn
+                .chars()
+                .map(digit)
+                .enumerate()
+                .try_fold(0, |sum, (i, d)| {
+                    d.map(|d| sum + if i % 2 == 0 { d } else { 3 * d })
+                })
+                .is_some_and(|sum| sum.is_multiple_of(10)),
+            _ => false,
+        };
+
+        if valid {
+            Some(CitationId::Isbn(isbn))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a citation key that is derived only from the identifier.
+    fn fallback_key(&self) -> String {
+        let (prefix, id) = match self {
+            CitationId::Doi(doi) => ("doi:", doi),
+            CitationId::Arxiv(id) => ("arxiv:", id),
+            CitationId::Isbn(isbn) => ("isbn:", isbn),
+        };
+        prefix
+            .chars()
+            .chain(id.chars().map(|c| {
+                if c.is_ascii_alphanumeric() || "-._:/".contains(c) {
+                    c
+                } else {
+                    '-'
+                }
+            }))
+            .collect()
+    }
+
+    /// Returns a BibTeX entry that contains only the identifier.
+    fn fallback_entry(&self) -> BibEntry {
+        let (entry_type, fields) = match self {
+            CitationId::Doi(doi) => (
+                "misc",
+                vec![
+                    ("doi", doi.clone()),
+                    ("howpublished", format!(r"\url{{https://doi.org/{}}}", doi)),
+                ],
+            ),
+            CitationId::Arxiv(id) => (
+                "misc",
+                vec![
+                    ("eprint", id.clone()),
+                    ("archiveprefix", "arXiv".to_string()),
+                    (
+                        "howpublished",
+                        format!(r"\url{{https://arxiv.org/abs/{}}}", id),
+                    ),
+                ],
+            ),
+            CitationId::Isbn(isbn) => ("book", vec![("isbn", isbn.clone())]),
+        };
+
+        BibEntry {
+            entry_type: entry_type.to_string(),
+            key: String::new(),
+            fields: fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        }
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, lowercase: &str, prefixes: &[&str]) -> Option<&'a str> {
+    prefixes
+        .iter()
+        .find(|prefix| lowercase.starts_with(*prefix))
+        .map(|prefix| &s[prefix.len()..])
+}
+
+/// An entry of a BibTeX file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BibEntry {
+    /// The entry type in lower case, e.g., `"article"`.
+    pub entry_type: String,
+
+    /// The citation key. May be empty in entries returned from a
+    /// [`MetadataSource`](trait.MetadataSource.html), in which case a key is
+    /// generated from the author, year, and title.
+    pub key: String,
+
+    /// The fields of the entry as pairs of a lower case field name and the field
+    /// value (in BibTeX syntax but without surrounding braces or quotes).
+    pub fields: Vec<(String, String)>,
+}
+
+impl BibEntry {
+    /// Returns the value of the field with the given (lower case) name.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field_name, _)| field_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Generates a citation key of the form "smith2020deep" from the author, year,
+    /// and title of the entry.
+    fn generate_key(&self) -> Option<String> {
+        let first_author = self
+            .field("author")
+            .or_else(|| self.field("editor"))?
+            .split(" and ")
+            .next()?
+            .trim();
+        let last_name = match first_author.find(',') {
+            Some(comma) => &first_author[..comma],
+            None => first_author.rsplit(' ').next()?,
+        };
+        let year = self.field("year").unwrap_or("");
+        let title_word = self
+            .field("title")
+            .unwrap_or("")
+            .split_whitespace()
+            .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+            .find(|word| {
+                word.len() > 3
+                    && !["with", "from", "that", "this", "into", "over"]
+                        .contains(&word.to_lowercase().as_str())
+            })
+            .unwrap_or("");
+
+        let key = last_name
+            .chars()
+            .chain(year.chars())
+            .chain(title_word.chars())
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<String>();
+        if key.is_empty() {
+            None
+        } else {
+            Some(key)
+        }
+    }
+
+    /// Appends the entry in BibTeX syntax to `out`.
+    ///
+    /// Skips fields whose values don't have balanced braces, since they would end
+    /// the entry early.
+    pub fn write_bibtex(&self, out: &mut String) {
+        writeln!(out, "@{}{{{},", self.entry_type, self.key).unwrap();
+        for (name, value) in &self.fields {
+            if has_balanced_braces(value) {
+                writeln!(out, "  {} = {{{}}},", name, value).unwrap();
+            } else {
+                warn!(
+                    "Skipping field \"{}\" of BibTeX entry \"{}\" with unbalanced braces.",
+                    name, self.key
+                );
+            }
+        }
+        out.push_str("}\n");
+    }
+}
+
+/// Returns `true` if every `}` in `s` closes a preceding `{` and vice versa. Like
+/// BibTeX, this also counts braces that are preceded by a backslash.
+fn has_balanced_braces(s: &str) -> bool {
+    let mut depth = 0usize;
+    for b in s.bytes() {
+        match b {
+            b'{' => depth += 1,
+            b'}' => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// A source of bibliographic data, e.g., a local BibTeX file or a web service.
+///
+/// Implemented by [`BibFile`](struct.BibFile.html), which looks up works in a local
+/// BibTeX file, by [`NoMetadata`](struct.NoMetadata.html), which never finds
+/// anything, and by `HashMap<CitationId, BibEntry>`, which is useful as a fixture in
+/// tests.
+pub trait MetadataSource {
+    /// Returns bibliographic data for the work with the given identifier, if known.
+    fn lookup(&mut self, id: &CitationId) -> Option<BibEntry>;
+
+    /// Returns bibliographic data for the work with the given citation key, if known.
+    ///
+    /// Used for pandoc-style citations (`[@key]`), see
+    /// [`ConversionOptions::citation_syntax`](../struct.ConversionOptions.html#structfield.citation_syntax).
+    /// The default implementation never finds anything.
+    fn lookup_key(&mut self, _key: &str) -> Option<BibEntry> {
+        None
+    }
+}
+
+/// A [`MetadataSource`](trait.MetadataSource.html) that never finds anything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoMetadata;
+
+impl MetadataSource for NoMetadata {
+    fn lookup(&mut self, _id: &CitationId) -> Option<BibEntry> {
+        None
+    }
+}
+
+impl MetadataSource for HashMap<CitationId, BibEntry> {
+    fn lookup(&mut self, id: &CitationId) -> Option<BibEntry> {
+        self.get(id).cloned()
+    }
+}
+
+/// The entries of a BibTeX file.
+///
+/// Serves as a [`MetadataSource`](trait.MetadataSource.html) that finds works by the
+/// fields `doi`, `eprint`, and `isbn`. Entries that were found keep their original
+/// citation key.
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::bibliography::{BibFile, CitationId, MetadataSource};
+///
+/// let mut bib_file = BibFile::parse(
+///     "@Article{smith2020,\n  author = {Smith, Jane},\n  doi = {10.1000/XYZ},\n}",
+/// );
+///
+/// let entry = bib_file.lookup(&CitationId::Doi("10.1000/xyz".to_string())).unwrap();
+/// assert_eq!(entry.entry_type, "article");
+/// assert_eq!(entry.key, "smith2020");
+/// assert_eq!(entry.field("author"), Some("Smith, Jane"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BibFile {
+    entries: Vec<BibEntry>,
+}
+
+impl BibFile {
+    /// Parses the contents of a BibTeX file.
+    ///
+    /// Skips `@comment`, `@preamble`, and `@string` entries, and anything outside of
+    /// entries. Parsing is lenient: a malformed entry ends the file.
+    pub fn parse(bibtex: &str) -> Self {
+        let mut entries = Vec::new();
+        let mut rest = bibtex;
+
+        while let Some(at) = rest.find('@') {
+            rest = &rest[at + 1..];
+            let open = match rest.find(['{', '(']) {
+                Some(open) => open,
+                None => break,
+            };
+            let entry_type = rest[..open].trim().to_ascii_lowercase();
+            let (body, remainder) = match split_group(&rest[open..]) {
+                Some(split) => split,
+                None => break,
+            };
+            rest = remainder;
+
+            if ["comment", "preamble", "string"].contains(&entry_type.as_str()) {
+                continue;
+            }
+            if let Some(entry) = parse_entry(entry_type, body) {
+                entries.push(entry);
+            }
+        }
+
+        Self { entries }
+    }
+
+    /// Returns the entries in the order in which they appear in the file.
+    pub fn entries(&self) -> &[BibEntry] {
+        &self.entries
+    }
+
+    /// Returns the entry with the given citation key.
+    pub fn get(&self, key: &str) -> Option<&BibEntry> {
+        self.entries.iter().find(|entry| entry.key == key)
+    }
+}
+
+impl MetadataSource for BibFile {
+    fn lookup(&mut self, id: &CitationId) -> Option<BibEntry> {
+        self.entries
+            .iter()
+            .find(|entry| match id {
+                CitationId::Doi(doi) => entry
+                    .field("doi")
+                    .is_some_and(|d| d.eq_ignore_ascii_case(doi)),
+                CitationId::Arxiv(arxiv) => entry.field("eprint").is_some_and(|e| {
+                    let e = e.strip_prefix("arXiv:").unwrap_or(e);
+                    e == arxiv
+                        || e.strip_prefix(arxiv.as_str())
+                            .is_some_and(|v| v.starts_with('v'))
+                }),
+                CitationId::Isbn(isbn) => entry
+                    .field("isbn")
+                    .is_some_and(|i| i.chars().filter(|&c| c != '-' && c != ' ').eq(isbn.chars())),
+            })
+            .cloned()
+    }
+
+    fn lookup_key(&mut self, key: &str) -> Option<BibEntry> {
+        self.get(key).cloned()
+    }
+}
+
+/// Splits `s`, which must start with `{` or `(`, into the contents of the group and
+/// the remainder after the matching closing delimiter.
+fn split_group(s: &str) -> Option<(&str, &str)> {
+    let closing = if s.starts_with('(') { b')' } else { b'}' };
+    let mut depth = 0;
+    for (i, b) in s.bytes().enumerate() {
+        match b {
+            b'{' | b'(' if i == 0 => {}
+            b'{' => depth += 1,
+            b'}' if depth > 0 => depth -= 1,
+            _ if b == closing && depth == 0 => return Some((&s[1..i], &s[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_entry(entry_type: String, body: &str) -> Option<BibEntry> {
+    let comma = body.find(',').unwrap_or(body.len());
+    let key = body[..comma].trim().to_string();
+    let mut rest = body.get(comma + 1..).unwrap_or("");
+    let mut fields = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let equals = rest.find('=')?;
+        let name = rest[..equals].trim().to_ascii_lowercase();
+        rest = rest[equals + 1..].trim_start();
+
+        let value = if rest.starts_with('{') {
+            let (value, remainder) = split_group(rest)?;
+            rest = remainder;
+            value.to_string()
+        } else if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            rest = &quoted[end + 1..];
+            quoted[..end].to_string()
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+            value
+        };
+        fields.push((name, value));
+    }
+
+    Some(BibEntry {
+        entry_type,
+        key,
+        fields,
+    })
+}
+
+/// The works cited in a document, with unique citation keys.
+#[derive(Debug, Clone, Default)]
+pub struct Bibliography {
+    entries: Vec<(Option<CitationId>, BibEntry)>,
+    unresolved_keys: Vec<String>,
+}
+
+/// Returns the `n`-th suffix in the sequence "a", "b", ..., "z", "aa", "ab", ...
+/// (starting at `n = 1`), which distinguishes citation keys that would be equal.
+fn letter_suffix(mut n: usize) -> String {
+    let mut letters = Vec::new();
+    while n > 0 {
+        n -= 1;
+        letters.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).expect("ASCII letters")
+}
+
+impl Bibliography {
+    /// Creates an empty bibliography.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a work to the bibliography if it isn't cited yet and returns its key.
+    ///
+    /// Looks up bibliographic data in `source` when a work is cited for the first
+    /// time.
+    pub fn cite(&mut self, id: CitationId, source: &mut dyn MetadataSource) -> &str {
+        if let Some(index) = self
+            .entries
+            .iter()
+            .position(|(cited, _)| cited.as_ref() == Some(&id))
+        {
+            return &self.entries[index].1.key;
+        }
+
+        let mut entry = source.lookup(&id).unwrap_or_else(|| id.fallback_entry());
+        let mut key = if entry.key.is_empty() {
+            entry.generate_key().unwrap_or_else(|| id.fallback_key())
+        } else {
+            std::mem::take(&mut entry.key)
+        };
+        if self.contains_key(&key) {
+            let base = key.clone();
+            let mut n = 2;
+            while self.contains_key(&key) {
+                key = format!("{}{}", base, letter_suffix(n));
+                n += 1;
+            }
+        }
+        entry.key = key;
+
+        self.entries.push((Some(id), entry));
+        &self.entries.last().expect("just pushed").1.key
+    }
+
+    /// Adds a work with a fixed citation key to the bibliography.
+    ///
+    /// Looks up bibliographic data in `source` when a key is cited for the first
+    /// time. Returns `false` if the key was not found, in which case the work is
+    /// cited without a BibTeX entry (see [`unresolved_keys`](#method.unresolved_keys)).
+    pub fn cite_key(&mut self, key: &str, source: &mut dyn MetadataSource) -> bool {
+        if self
+            .unresolved_keys
+            .iter()
+            .any(|unresolved| unresolved == key)
+        {
+            return false;
+        } else if self.contains_key(key) {
+            return true;
+        }
+
+        if let Some(mut entry) = source.lookup_key(key) {
+            entry.key = key.to_string();
+            self.entries.push((None, entry));
+            true
+        } else {
+            self.unresolved_keys.push(key.to_string());
+            false
+        }
+    }
+
+    /// Returns the citation keys that were cited with
+    /// [`cite_key`](#method.cite_key) but not found in the metadata source.
+    pub fn unresolved_keys(&self) -> &[String] {
+        &self.unresolved_keys
+    }
+
+    /// Returns the key of a work if it has been cited.
+    pub fn key(&self, id: &CitationId) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(cited, _)| cited.as_ref() == Some(id))
+            .map(|(_, entry)| entry.key.as_str())
+    }
+
+    /// Returns the cited works in the order in which they were first cited.
+    pub fn entries(&self) -> impl Iterator<Item = &BibEntry> {
+        self.entries.iter().map(|(_, entry)| entry)
+    }
+
+    /// Returns `true` if no works are cited.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.unresolved_keys.is_empty()
+    }
+
+    /// Returns the contents of a BibTeX file with all cited works.
+    pub fn to_bibtex(&self) -> String {
+        let mut bibtex = String::new();
+        for (i, entry) in self.entries().enumerate() {
+            if i != 0 {
+                bibtex.push('\n');
+            }
+            entry.write_bibtex(&mut bibtex);
+        }
+        bibtex
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.entries.iter().any(|(_, entry)| entry.key == key)
+            || self
+                .unresolved_keys
+                .iter()
+                .any(|unresolved| unresolved == key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::latex_escape::is_safe_key;
+
+    #[test]
+    fn citation_ids() {
+        let doi = |s: &str| Some(CitationId::Doi(s.to_string()));
+        let arxiv = |s: &str| Some(CitationId::Arxiv(s.to_string()));
+        let isbn = |s: &str| Some(CitationId::Isbn(s.to_string()));
+
+        assert_eq!(
+            CitationId::from_url("https://doi.org/10.1000/a.b(c)"),
+            doi("10.1000/a.b(c)")
+        );
+        assert_eq!(
+            CitationId::from_url("http://dx.doi.org/10.1000%2Fxyz"),
+            doi("10.1000/xyz")
+        );
+        assert_eq!(CitationId::from_url("DOI:10.1000/xyz"), doi("10.1000/xyz"));
+        assert_eq!(CitationId::from_url("https://doi.org/11.1000/xyz"), None);
+        assert_eq!(
+            CitationId::from_url("https://doi.org/10.1000/x}\\input{y}"),
+            None
+        );
+        assert_eq!(CitationId::from_url("doi:10.1000/a b"), None);
+        assert_eq!(CitationId::from_url("doi:10.x/abc"), None);
+
+        assert_eq!(
+            CitationId::from_url("https://arxiv.org/abs/2001.01234"),
+            arxiv("2001.01234")
+        );
+        assert_eq!(
+            CitationId::from_url("https://www.arxiv.org/abs/1501.0123v3"),
+            arxiv("1501.0123")
+        );
+        assert_eq!(
+            CitationId::from_url("arxiv:hep-th/9901001v1"),
+            arxiv("hep-th/9901001")
+        );
+        assert_eq!(
+            CitationId::from_url("https://arxiv.org/abs/20011.01234"),
+            None
+        );
+        assert_eq!(
+            CitationId::from_url("https://arxiv.org/list/cs.LG/recent"),
+            None
+        );
+
+        assert_eq!(
+            CitationId::from_url("isbn:0-306-40615-2"),
+            isbn("0306406152")
+        );
+        assert_eq!(
+            CitationId::from_url("https://openlibrary.org/isbn/9780262035613"),
+            isbn("9780262035613")
+        );
+        assert_eq!(CitationId::from_url("isbn:0-306-40615-3"), None);
+        assert_eq!(CitationId::from_url("https://example.com/"), None);
+        assert_eq!(CitationId::from_url("https://example.com"), None);
+    }
+
+    #[test]
+    fn unbalanced_fields() {
+        let entry = BibEntry {
+            entry_type: "misc".to_string(),
+            key: "key".to_string(),
+            fields: vec![
+                ("title".to_string(), "A {Nested} Title".to_string()),
+                ("note".to_string(), "x}\\input{y".to_string()),
+                ("author".to_string(), "M{\\\"u}ller".to_string()),
+            ],
+        };
+        let mut bibtex = String::new();
+        entry.write_bibtex(&mut bibtex);
+
+        assert_eq!(
+            bibtex,
+            "@misc{key,\n  title = {A {Nested} Title},\n  author = {M{\\\"u}ller},\n}\n"
+        );
+    }
+
+    #[test]
+    fn parse_bib_file() {
+        let bib_file = BibFile::parse(
+            "Comment outside of entries.\n\
+             @string{jan = \"January\"}\n\
+             @ARTICLE{ smith2020,\n  \
+               Title = {A {Nested} Title},\n  \
+               author = \"Smith, Jane and Doe, John\",\n  \
+               year = 2020\n\
+             }\n\
+             @book(roe2019, title = {Book}, isbn = {978-0-262-03561-3},)",
+        );
+
+        assert_eq!(
+            bib_file.entries(),
+            &[
+                BibEntry {
+                    entry_type: "article".to_string(),
+                    key: "smith2020".to_string(),
+                    fields: vec![
+                        ("title".to_string(), "A {Nested} Title".to_string()),
+                        (
+                            "author".to_string(),
+                            "Smith, Jane and Doe, John".to_string()
+                        ),
+                        ("year".to_string(), "2020".to_string()),
+                    ],
+                },
+                BibEntry {
+                    entry_type: "book".to_string(),
+                    key: "roe2019".to_string(),
+                    fields: vec![
+                        ("title".to_string(), "Book".to_string()),
+                        ("isbn".to_string(), "978-0-262-03561-3".to_string()),
+                    ],
+                },
+            ]
+        );
+
+        let mut bib_file = bib_file;
+        let id = CitationId::Isbn("9780262035613".to_string());
+        assert_eq!(bib_file.lookup(&id).unwrap().key, "roe2019");
+    }
+
+    #[test]
+    fn bibliography() {
+        let entry = |author: &str, title: &str| BibEntry {
+            entry_type: "article".to_string(),
+            key: String::new(),
+            fields: vec![
+                ("author".to_string(), author.to_string()),
+                ("title".to_string(), title.to_string()),
+                ("year".to_string(), "2020".to_string()),
+            ],
+        };
+        let doi = |s: &str| CitationId::Doi(s.to_string());
+
+        let mut fixture = HashMap::new();
+        fixture.insert(
+            doi("10.1/a"),
+            entry("Jane Smith and John Doe", "On the Theory"),
+        );
+        fixture.insert(doi("10.1/b"), entry("Smith, Jane", "Theory of Things"));
+        fixture.insert(doi("10.1/c"), entry("M{\\\"u}ller, Max", "A New Theory"));
+
+        let mut bibliography = Bibliography::new();
+        assert_eq!(
+            bibliography.cite(doi("10.1/a"), &mut fixture),
+            "smith2020theory"
+        );
+        assert_eq!(
+            bibliography.cite(doi("10.1/b"), &mut fixture),
+            "smith2020theoryb"
+        );
+        assert_eq!(
+            bibliography.cite(doi("10.1/a"), &mut fixture),
+            "smith2020theory"
+        );
+        assert_eq!(
+            bibliography.cite(doi("10.1/c"), &mut fixture),
+            "muller2020theory"
+        );
+        assert_eq!(bibliography.cite(doi("10.1/d"), &mut fixture), "doi:10.1/d");
+
+        assert_eq!(
+            bibliography
+                .to_bibtex()
+                .lines()
+                .skip(18)
+                .collect::<Vec<_>>(),
+            vec![
+                "@misc{doi:10.1/d,",
+                "  doi = {10.1/d},",
+                "  howpublished = {\\url{https://doi.org/10.1/d}},",
+                "}",
+            ]
+        );
+
+        let mut bib_file = BibFile::parse("@book{smith2020theory, title = {Other}}");
+        assert!(bibliography.cite_key("smith2020theory", &mut bib_file));
+        assert!(!bibliography.cite_key("missing", &mut bib_file));
+        assert!(!bibliography.cite_key("missing", &mut bib_file));
+        assert_eq!(bibliography.unresolved_keys(), ["missing"]);
+        assert_eq!(bibliography.entries().count(), 4);
+    }
+
+    #[test]
+    fn colliding_keys() {
+        assert_eq!(letter_suffix(1), "a");
+        assert_eq!(letter_suffix(26), "z");
+        assert_eq!(letter_suffix(27), "aa");
+        assert_eq!(letter_suffix(28), "ab");
+        assert_eq!(letter_suffix(26 * 27 + 1), "aaa");
+
+        let mut fixture = HashMap::new();
+        for i in 0..60 {
+            let entry = BibEntry {
+                entry_type: "misc".to_string(),
+                key: "same".to_string(),
+                fields: Vec::new(),
+            };
+            fixture.insert(CitationId::Doi(format!("10.1/{}", i)), entry);
+        }
+        let mut bibliography = Bibliography::new();
+        let keys = (0..60)
+            .map(|i| {
+                let id = CitationId::Doi(format!("10.1/{}", i));
+                bibliography.cite(id, &mut fixture).to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys[..3], ["same", "sameb", "samec"]);
+        assert_eq!(keys[25..28], ["samez", "sameaa", "sameab"]);
+        assert!(keys.iter().all(|key| is_safe_key(key)));
+    }
+}
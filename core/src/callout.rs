@@ -0,0 +1,164 @@
+//! Callout blocks, i.e., blockquotes that start with `**Note:**` or `[!NOTE]`.
+
+use crate::latex_escape::escape_str_and_unicode;
+use crate::CalloutStyle;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// A blockquote that is typeset as a callout box.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Callout {
+    /// Index into the list of callout styles.
+    pub style: usize,
+
+    /// Byte offset in the markdown source where the content of the callout starts
+    /// (i.e., after the marker).
+    pub end: usize,
+}
+
+/// Finds all blockquotes that start with a callout marker.
+///
+/// Returns the callouts together with the byte offset of their blockquote, sorted by
+/// position.
+pub fn find_callouts(
+    markdown: &str,
+    parser_options: Options,
+    styles: &[CalloutStyle],
+) -> Vec<(usize, Callout)> {
+    let mut callouts = Vec::new();
+    if styles.is_empty() || !markdown.contains('>') {
+        // No styles or no blockquotes.
+        return callouts;
+    }
+    let find_style = |name: &str| {
+        styles
+            .iter()
+            .position(|style| style.name.eq_ignore_ascii_case(name.trim()))
+    };
+
+    let mut parser = Parser::new_ext(markdown, parser_options)
+        .into_offset_iter()
+        .peekable();
+    while let Some((event, range)) = parser.next() {
+        if Event::Start(Tag::BlockQuote) != event {
+            continue;
+        }
+        let paragraph_start = match parser.next() {
+            Some((Event::Start(Tag::Paragraph), paragraph_range)) => paragraph_range.start,
+            _ => continue,
+        };
+
+        // GitHub style alert, e.g., "[!WARNING]" on a line of its own.
+        let first_line = markdown[paragraph_start..]
+            .split('\n')
+            .next()
+            .expect("split never returns an empty iterator");
+        if let Some(name) = first_line
+            .trim_end()
+            .strip_prefix("[!")
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            if let Some(style) = find_style(name) {
+                let end = std::cmp::min(paragraph_start + first_line.len() + 1, markdown.len());
+                callouts.push((range.start, Callout { style, end }));
+            }
+            continue;
+        }
+
+        // Bold label, e.g., "**Note:**" or "**Note**:".
+        let strong_end = match parser.next() {
+            Some((Event::Start(Tag::Strong), strong_range)) => strong_range.end,
+            _ => continue,
+        };
+        let mut label = String::new();
+        for (event, _) in parser.by_ref() {
+            match event {
+                Event::Text(text) => label.push_str(&text),
+                _ => break,
+            }
+        }
+        let following = match parser.peek() {
+            Some((Event::Text(text), text_range)) if text.len() == text_range.len() => {
+                Some((text.as_ref(), text_range.start))
+            }
+            _ => None,
+        };
+
+        let label = label.trim();
+        let (name, after_colon) = match (label.strip_suffix(':'), following) {
+            (Some(name), Some((text, start))) => (name, Some((text, start))),
+            (Some(name), None) => (name, None),
+            (None, Some((text, start))) if text.starts_with(':') => {
+                (label, Some((&text[1..], start + 1)))
+            }
+            _ => continue,
+        };
+        if let Some(style) = find_style(name) {
+            let end = after_colon.map_or(strong_end, |(text, start)| {
+                start + (text.len() - text.trim_start().len())
+            });
+            callouts.push((range.start, Callout { style, end }));
+        }
+    }
+
+    callouts
+}
+
+/// Returns the name of the LaTeX environment for a callout style, e.g., "notebox".
+pub fn environment(style: &CalloutStyle) -> String {
+    let mut environment = style
+        .name
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    environment.push_str("box");
+    environment
+}
+
+/// Returns the `\newtcolorbox` declaration of a callout style for the preamble.
+pub fn declaration(style: &CalloutStyle) -> String {
+    let mut title = Vec::new();
+    escape_str_and_unicode(&style.title, &mut title).expect("writing to a Vec can't fail");
+    format!(
+        r"\newtcolorbox{{{}}}{{colback={color}!5!white,colframe={color}!75!black,fonttitle=\bfseries,title={{{}}}}}",
+        environment(style),
+        String::from_utf8(title).expect("escaping preserves UTF-8"),
+        color = style.color
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn callouts() {
+        fn find(markdown: &str) -> Vec<(usize, String, &str)> {
+            let styles = CalloutStyle::default_styles();
+            find_callouts(markdown, Options::empty(), &styles)
+                .into_iter()
+                .map(|(start, callout)| {
+                    (
+                        start,
+                        styles[callout.style].name.clone(),
+                        &markdown[callout.end..],
+                    )
+                })
+                .collect()
+        }
+        let s = |s: &str| s.to_string();
+
+        assert_eq!(find("> **Note:** Text"), [(0, s("Note"), "Text")]);
+        assert_eq!(find("> **Tip**: Text"), [(0, s("Tip"), "Text")]);
+        assert_eq!(find("> **WARNING:**"), [(0, s("Warning"), "")]);
+        assert_eq!(
+            find("Para\n\n> [!CAUTION]\n> Text"),
+            [(6, s("Caution"), "> Text")]
+        );
+        assert!(find("> **Note** Text").is_empty());
+        assert!(find("> **Hint:** Text").is_empty());
+        assert!(find("> [!NOTE] Text").is_empty());
+        assert!(find("> Quote with **Note:**").is_empty());
+    }
+}
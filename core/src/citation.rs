@@ -0,0 +1,342 @@
+//! Pandoc-style citation syntax, e.g., `[see @smith2020, p. 3; @doe2019]`.
+
+use crate::latex_escape::escape_str_and_unicode;
+
+use std::io::Write;
+use std::ops::Range;
+
+/// Characters that may appear inside (but not at the end of) a citation key.
+const INTERNAL_PUNCTUATION: [char; 13] = [
+    ':', '.', '#', '$', '%', '&', '-', '+', '?', '<', '>', '~', '/',
+];
+
+/// Characters that are allowed in Pandoc citation keys but can't be written into the
+/// argument of a `\cite` command.
+const NON_LATEX_KEY_CHARS: &str = "\\{}%#~,";
+
+/// A citation of one or more works.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Citation {
+    pub items: Vec<CitationItem>,
+
+    /// `true` for a citation in running text (`@key`), `false` for a bracketed
+    /// citation (`[@key]`).
+    pub in_text: bool,
+}
+
+/// A single cited work within a `Citation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CitationItem {
+    pub key: String,
+    pub prefix: String,
+    pub locator: String,
+    pub suppress_author: bool,
+}
+
+/// Finds all citations in the given runs of text (see `preprocess::text_runs`).
+///
+/// Returns the citations together with their byte ranges in `markdown`, sorted by
+/// position.
+pub fn find_citations(markdown: &str, runs: &[Range<usize>]) -> Vec<(Range<usize>, Citation)> {
+    let mut citations = Vec::new();
+    for run in runs {
+        scan_run(&markdown[run.clone()], run.start, &mut citations);
+    }
+    citations
+}
+
+fn scan_run(source: &str, offset: usize, citations: &mut Vec<(Range<usize>, Citation)>) {
+    let bytes = source.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let found = match bytes[i] {
+            b'\\' => {
+                // Skip the escaped character.
+                i += 2;
+                continue;
+            }
+            b'[' => parse_bracketed(&source[i..]),
+            b'@' if at_word_start(source, i) => {
+                parse_in_text(&source[i + 1..]).map(|(len, citation)| (len + 1, citation))
+            }
+            _ => None,
+        };
+
+        if let Some((len, citation)) = found {
+            citations.push((offset + i..offset + i + len, citation));
+            i += len;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Returns `true` if the character at byte offset `pos` does not continue a word
+/// (e.g., the "@" in an email address does).
+fn at_word_start(s: &str, pos: usize) -> bool {
+    s[..pos]
+        .chars()
+        .next_back()
+        .is_none_or(|c| !c.is_alphanumeric() && !"_@\\".contains(c))
+}
+
+/// Parses a bracketed citation at the start of `s` and returns its length in bytes.
+fn parse_bracketed(s: &str) -> Option<(usize, Citation)> {
+    let close = s.find(']')?;
+    let content = &s[1..close];
+    if content.contains('[') {
+        return None;
+    }
+
+    let items = content
+        .split(';')
+        .map(parse_item)
+        .collect::<Option<Vec<_>>>()?;
+    Some((
+        close + 1,
+        Citation {
+            items,
+            in_text: false,
+        },
+    ))
+}
+
+/// Parses one semicolon separated part of a bracketed citation.
+fn parse_item(item: &str) -> Option<CitationItem> {
+    let at = item.match_indices('@').map(|(pos, _)| pos).find(|&pos| {
+        let before = &item[..pos];
+        let before = before.strip_suffix('-').unwrap_or(before);
+        before.is_empty() || before.ends_with(char::is_whitespace)
+    })?;
+    let suppress_author = item[..at].ends_with('-');
+    let prefix_end = if suppress_author { at - 1 } else { at };
+
+    let (key_len, key) = parse_key(&item[at + 1..])?;
+    let locator = item[at + 1 + key_len..].trim();
+    let locator = locator.strip_prefix(',').unwrap_or(locator).trim();
+
+    Some(CitationItem {
+        key: key.to_string(),
+        prefix: item[..prefix_end].trim().to_string(),
+        locator: locator.to_string(),
+        suppress_author,
+    })
+}
+
+/// Parses a citation in running text, starting after the "@".
+fn parse_in_text(s: &str) -> Option<(usize, Citation)> {
+    let (mut len, key) = parse_key(s)?;
+    let mut locator = "";
+
+    // An optional locator in brackets, e.g., "@smith2020 [p. 3]".
+    if let Some(bracket) = s[len..].strip_prefix(" [") {
+        if let Some(close) = bracket.find(']') {
+            let content = &bracket[..close];
+            if !content.contains(['@', '[']) {
+                locator = content.trim();
+                len += 2 + close + 1;
+            }
+        }
+    }
+
+    Some((
+        len,
+        Citation {
+            items: vec![CitationItem {
+                key: key.to_string(),
+                prefix: String::new(),
+                locator: locator.to_string(),
+                suppress_author: false,
+            }],
+            in_text: true,
+        },
+    ))
+}
+
+/// Parses a citation key, either in braces or as a sequence of alphanumeric
+/// characters with internal punctuation. Returns the length of the key in `s`.
+///
+/// Keys that can't be used in LaTeX (see [`NON_LATEX_KEY_CHARS`]) are rejected.
+fn parse_key(s: &str) -> Option<(usize, &str)> {
+    let (len, key) = parse_pandoc_key(s)?;
+    if key.contains(|c| NON_LATEX_KEY_CHARS.contains(c)) {
+        None
+    } else {
+        Some((len, key))
+    }
+}
+
+fn parse_pandoc_key(s: &str) -> Option<(usize, &str)> {
+    if let Some(braced) = s.strip_prefix('{') {
+        let close = braced.find('}')?;
+        return if close == 0 {
+            None
+        } else {
+            Some((close + 2, &braced[..close]))
+        };
+    }
+
+    let mut end = 0;
+    let mut chars = s.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            end = pos + c.len_utf8();
+        } else if end == 0
+            || !INTERNAL_PUNCTUATION.contains(&c)
+            || !chars
+                .peek()
+                .is_some_and(|&(_, next)| next.is_alphanumeric() || next == '_')
+        {
+            break;
+        }
+    }
+
+    if end == 0 {
+        None
+    } else {
+        Some((end, &s[..end]))
+    }
+}
+
+/// Writes a citation as a `natbib` command.
+pub fn write_citation(citation: &Citation, writer: &mut impl Write) -> std::io::Result<()> {
+    let items = &citation.items;
+    let last = items.len() - 1;
+    let simple = items.iter().enumerate().all(|(i, item)| {
+        (i == 0 || item.prefix.is_empty())
+            && (i == last || item.locator.is_empty())
+            && item.suppress_author == items[0].suppress_author
+    });
+
+    if simple {
+        let command = if citation.in_text {
+            "citet"
+        } else if items[0].suppress_author {
+            "citeyearpar"
+        } else {
+            "citep"
+        };
+        let keys = items.iter().map(|item| item.key.as_str());
+        write_command(
+            command,
+            &items[0].prefix,
+            &items[last].locator,
+            keys,
+            writer,
+        )
+    } else {
+        writer.write_all(br"\citetext{")?;
+        for (i, item) in items.iter().enumerate() {
+            if i != 0 {
+                writer.write_all(b"; ")?;
+            }
+            if !item.prefix.is_empty() {
+                write_note(&item.prefix, writer)?;
+                writer.write_all(b" ")?;
+            }
+            let command = if item.suppress_author {
+                "citeyear"
+            } else {
+                "citealp"
+            };
+            let key = std::iter::once(item.key.as_str());
+            write_command(command, "", &item.locator, key, writer)?;
+        }
+        writer.write_all(b"}")
+    }
+}
+
+fn write_command<'a>(
+    command: &str,
+    prefix: &str,
+    locator: &str,
+    keys: impl Iterator<Item = &'a str>,
+    writer: &mut impl Write,
+) -> std::io::Result<()> {
+    write!(writer, r"\{}", command)?;
+    if !prefix.is_empty() {
+        writer.write_all(b"[")?;
+        write_note(prefix, writer)?;
+        writer.write_all(b"][")?;
+        write_note(locator, writer)?;
+        writer.write_all(b"]")?;
+    } else if !locator.is_empty() {
+        writer.write_all(b"[")?;
+        write_note(locator, writer)?;
+        writer.write_all(b"]")?;
+    }
+
+    writer.write_all(b"{")?;
+    for (i, key) in keys.enumerate() {
+        if i != 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(key.as_bytes())?;
+    }
+    writer.write_all(b"}")
+}
+
+/// Writes a prefix or locator, with a non-breaking space after a leading
+/// abbreviation (e.g., "p.~3" or "chap.~2").
+fn write_note(note: &str, writer: &mut impl Write) -> std::io::Result<()> {
+    match note.split_once(' ') {
+        Some((abbreviation, rest)) if abbreviation.len() > 1 && abbreviation.ends_with('.') => {
+            escape_str_and_unicode(abbreviation, writer)?;
+            writer.write_all(b"~")?;
+            escape_str_and_unicode(rest.trim_start(), writer)
+        }
+        _ => escape_str_and_unicode(note, writer),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::preprocess::text_runs;
+    use pulldown_cmark::Options;
+
+    fn latex(markdown: &str) -> Vec<String> {
+        find_citations(markdown, &text_runs(markdown, Options::empty()))
+            .into_iter()
+            .map(|(range, citation)| {
+                let mut latex = Vec::new();
+                write_citation(&citation, &mut latex).unwrap();
+                format!("{:?} {}", range, String::from_utf8(latex).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bracketed() {
+        assert_eq!(
+            latex("As shown [@smith2020]."),
+            ["9..21 \\citep{smith2020}"]
+        );
+        assert_eq!(latex("Shown [@a; @b, p. 3]."), ["6..20 \\citep[p.~3]{a,b}"]);
+        assert_eq!(
+            latex("[see @a, chap. 2; also @b]"),
+            ["0..26 \\citetext{see \\citealp[chap.~2]{a}; also \\citealp{b}}"]
+        );
+        assert_eq!(latex("[see @a]"), ["0..8 \\citep[see][]{a}"]);
+        assert_eq!(
+            latex("Smith [-@smith2020]"),
+            ["6..19 \\citeyearpar{smith2020}"]
+        );
+        assert_eq!(latex("[@{weird key}]"), ["0..14 \\citep{weird key}"]);
+        assert!(latex("[no citation] and [no key]").is_empty());
+        assert!(latex("[link with @key](https://example.com)").is_empty());
+        assert!(latex("```\n[@a]\n```").is_empty());
+    }
+
+    #[test]
+    fn in_text() {
+        assert_eq!(latex("@smith2020 says"), ["0..10 \\citet{smith2020}"]);
+        assert_eq!(
+            latex("As @smith2020 [p. 33] says."),
+            ["3..21 \\citet[p.~33]{smith2020}"]
+        );
+        assert_eq!(latex("See @doe:2019."), ["4..13 \\citet{doe:2019}"]);
+        assert!(latex("Mail jane@example.com or \\@jane.").is_empty());
+        assert!(latex("@a%b, @a#b, @{a\\input}, and @{a,b}").is_empty());
+    }
+}
@@ -0,0 +1,402 @@
+//! Description lists, i.e., bullet lists whose items start with a bold term (e.g.,
+//! "- **Term:** description") and pandoc-style definition lists.
+
+use crate::lists::is_list_item;
+use crate::preprocess::{code_fence, find_line_end, is_blank_line, skip_fenced_code_block};
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// A bullet list item that is typeset as `\item[{term}] description`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptionItem {
+    /// Byte offset of the list item in the markdown source.
+    pub pos: usize,
+
+    /// Byte offset where the term ends.
+    pub term_end: usize,
+
+    /// Byte offset where the description starts (e.g., after the colon that follows a
+    /// bold term).
+    pub end: usize,
+}
+
+/// Rewrites pandoc-style definition lists as bullet lists whose items start with the
+/// term on a line of its own, e.g.:
+///
+/// ```text
+/// Term            - Term
+/// :   Definition    Definition
+/// ```
+///
+/// A term is a single unindented line that follows a blank line (or the start of the
+/// document) and is followed by a line that starts with a colon or tilde (optionally
+/// indented by up to two spaces) and a space, possibly after a single blank line.
+/// Further definitions of the same term become separate paragraphs of the list item.
+/// The bullet character is chosen such that the list doesn't merge with an adjacent
+/// bullet list. Fenced code blocks are skipped.
+///
+/// Unlike most other preprocessing steps, this changes byte offsets (and, for terms
+/// with several definitions that aren't separated by blank lines, line numbers), so it
+/// has to run first. Returns the items of the resulting lists sorted by position.
+pub fn replace_definition_lists(input: &mut String) -> Vec<DescriptionItem> {
+    let bytes = input.as_bytes();
+    let mut output = String::new();
+    let mut copied = 0;
+    let mut items = Vec::new();
+    let mut list_start = None;
+    let mut previous_bullet = None;
+    let mut previous_blank = true;
+    let mut previous_term = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let line_end = find_line_end(bytes, i);
+        let line = &bytes[i..line_end];
+        let indented = matches!(line.first(), Some(b' ') | Some(b'\t'));
+
+        if is_blank_line(line) {
+            previous_blank = true;
+            previous_term = false;
+            i = line_end + 1;
+            continue;
+        }
+
+        if let (Some(_), Some(content_start)) = (list_start, definition_marker(line)) {
+            // A definition.
+            output.push_str(&input[copied..i]);
+            if !previous_blank && !previous_term {
+                output.push('\n');
+            }
+            output.push_str("  ");
+            copied = i + content_start;
+        } else if previous_blank && !indented && is_term(line) && has_definition(bytes, line_end) {
+            output.push_str(&input[copied..i]);
+            list_start.get_or_insert(items.len());
+            let pos = output.len();
+            output.push_str("- ");
+            copied = i;
+            let term_len = line
+                .iter()
+                .rposition(|b| !b.is_ascii_whitespace())
+                .map_or(0, |last| last + 1);
+            let term_end = pos + 2 + term_len;
+            items.push(DescriptionItem {
+                pos,
+                term_end,
+                end: term_end,
+            });
+            previous_blank = false;
+            previous_term = true;
+            i = line_end + 1;
+            continue;
+        } else if previous_blank && !indented {
+            if let Some(start) = list_start.take() {
+                set_bullet(&mut output, &items[start..], previous_bullet, bullet(line));
+            }
+            previous_bullet = bullet(line);
+        }
+
+        if let Some(fence) = code_fence(line) {
+            i = skip_fenced_code_block(bytes, line_end, fence);
+        } else {
+            i = line_end + 1;
+        }
+        previous_blank = false;
+        previous_term = false;
+    }
+
+    if let Some(start) = list_start {
+        set_bullet(&mut output, &items[start..], previous_bullet, None);
+    }
+    if !items.is_empty() {
+        output.push_str(&input[copied..]);
+        *input = output;
+    }
+
+    items
+}
+
+/// Checks if `line` can be the term of a definition list item.
+fn is_term(line: &[u8]) -> bool {
+    !matches!(
+        line.first(),
+        Some(b'#') | Some(b'>') | Some(b'|') | Some(b':') | Some(b'~')
+    ) && !is_list_item(line)
+        && code_fence(line).is_none()
+}
+
+/// Checks if the line after `line_end` (or the one after it, if the former is blank)
+/// starts a definition.
+fn has_definition(bytes: &[u8], line_end: usize) -> bool {
+    let mut start = line_end + 1;
+    for _ in 0..2 {
+        if start >= bytes.len() {
+            return false;
+        }
+        let end = find_line_end(bytes, start);
+        let line = &bytes[start..end];
+        if !is_blank_line(line) {
+            return definition_marker(line).is_some();
+        }
+        start = end + 1;
+    }
+    false
+}
+
+/// Returns the position of the content of a line that starts a definition (e.g.,
+/// ":   Definition"), or `None` if the line doesn't start a definition.
+fn definition_marker(line: &[u8]) -> Option<usize> {
+    let indent = line.iter().take_while(|&&b| b == b' ').count();
+    if indent > 2 || !matches!(line.get(indent), Some(b':') | Some(b'~')) {
+        return None;
+    }
+    let spaces = line[indent + 1..]
+        .iter()
+        .take_while(|&&b| b == b' ' || b == b'\t')
+        .count();
+    let content_start = indent + 1 + spaces;
+    if spaces == 0 || line[content_start..].iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        Some(content_start)
+    }
+}
+
+/// Returns the bullet character if `line` is an item of a bullet list.
+fn bullet(line: &[u8]) -> Option<u8> {
+    line.first()
+        .copied()
+        .filter(|&b| matches!(b, b'-' | b'*' | b'+') && is_list_item(line))
+}
+
+/// Replaces the bullets of `items` by a character that differs from the bullets of
+/// the adjacent lists.
+fn set_bullet(
+    output: &mut String,
+    items: &[DescriptionItem],
+    before: Option<u8>,
+    after: Option<u8>,
+) {
+    let bullet = [b'-', b'*', b'+']
+        .iter()
+        .copied()
+        .find(|&b| Some(b) != before && Some(b) != after)
+        .expect("at most two bullets are excluded");
+    let bullet = (bullet as char).to_string();
+    for item in items {
+        output.replace_range(item.pos..item.pos + 1, &bullet);
+    }
+}
+
+/// Finds bullet lists in which every item starts with a bold term followed by a
+/// colon (e.g., "- **Term:** description" or "- **Term**: description").
+///
+/// Moves a colon at the end of a bold term behind the closing delimiter (i.e.,
+/// "**Term:**" becomes "**Term**:") so that it's not part of the term. Byte offsets
+/// don't change.
+///
+/// Returns the items of such lists sorted by position.
+pub fn find_bold_terms(markdown: &mut String, parser_options: Options) -> Vec<DescriptionItem> {
+    let mut items = Vec::new();
+    let mut swaps = Vec::new();
+
+    // For each open list: the items and swaps found so far, or `None` if the list
+    // can't be typeset as a description list.
+    let mut lists = Vec::new();
+    let mut parser = Parser::new_ext(markdown, parser_options)
+        .into_offset_iter()
+        .peekable();
+    while let Some((event, range)) = parser.next() {
+        match event {
+            Event::Start(Tag::List(first_number)) => {
+                lists.push(first_number.map_or(Some((Vec::new(), Vec::new())), |_| None));
+            }
+            Event::End(Tag::List(_)) => {
+                if let Some(Some((list_items, list_swaps))) = lists.pop() {
+                    items.extend(list_items);
+                    swaps.extend(list_swaps);
+                }
+            }
+            Event::Start(Tag::Item) => {
+                if let Some(list) = lists.last_mut() {
+                    if let Some((list_items, list_swaps)) = list {
+                        if let Some((item, swap)) = bold_term(markdown, range.start, &mut parser) {
+                            list_items.push(item);
+                            list_swaps.extend(swap);
+                        } else {
+                            *list = None;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for pos in swaps {
+        // Replace ":**" by "**:" (or ":__" by "__:").
+        let delimiter = markdown[pos + 1..pos + 3].to_string();
+        markdown.replace_range(pos..pos + 3, &format!("{}:", delimiter));
+    }
+    items.sort_by_key(|item| item.pos);
+    items
+}
+
+/// Parses a bold term at the start of a list item and returns the item together with
+/// the position of a colon that has to be moved behind the closing delimiter, if any.
+///
+/// Consumes the events of the term (but not the events after it).
+fn bold_term<'a>(
+    markdown: &str,
+    pos: usize,
+    parser: &mut std::iter::Peekable<impl Iterator<Item = (Event<'a>, std::ops::Range<usize>)>>,
+) -> Option<(DescriptionItem, Option<usize>)> {
+    parser.next_if(|(event, _)| *event == Event::Start(Tag::Paragraph));
+    let strong = match parser.peek() {
+        Some((Event::Start(Tag::Strong), range)) => range.clone(),
+        _ => return None,
+    };
+    parser.next();
+    let mut term = String::new();
+    while let Some((event, _)) = parser.next_if(|(event, _)| *event != Event::End(Tag::Strong)) {
+        match event {
+            Event::Text(text) | Event::Code(text) => term.push_str(&text),
+            _ => {}
+        }
+    }
+
+    let bytes = markdown.as_bytes();
+    let closing = &bytes[strong.end - 2..strong.end];
+    let (colon, swap) = if term.trim_end().ends_with(':')
+        && bytes[strong.end - 3] == b':'
+        && closing == &bytes[strong.start..strong.start + 2]
+    {
+        (strong.end - 1, Some(strong.end - 3))
+    } else if bytes.get(strong.end) == Some(&b':') {
+        (strong.end, None)
+    } else {
+        return None;
+    };
+    if term.trim_end().trim_end_matches(':').trim().is_empty()
+        || !bytes.get(colon + 1).is_none_or(u8::is_ascii_whitespace)
+    {
+        return None;
+    }
+
+    let spaces = bytes[colon + 1..]
+        .iter()
+        .take_while(|&&b| b == b' ' || b == b'\t')
+        .count();
+    Some((
+        DescriptionItem {
+            pos,
+            term_end: colon,
+            end: colon + 1 + spaces,
+        },
+        swap,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn definition_lists() {
+        fn replace(markdown: &str) -> (String, Vec<String>) {
+            let mut markdown = markdown.to_string();
+            let terms = replace_definition_lists(&mut markdown)
+                .into_iter()
+                .map(|item| markdown[item.pos..item.term_end].to_string())
+                .collect();
+            (markdown, terms)
+        }
+        let s = |s: &str| s.to_string();
+
+        assert_eq!(
+            replace("Term 1\n:   Def 1\n\nTerm 2\n\n~ Def 2a\n~ Def 2b\n\n    More 2b\n"),
+            (
+                s("- Term 1\n  Def 1\n\n- Term 2\n\n  Def 2a\n\n  Def 2b\n\n    More 2b\n"),
+                vec![s("- Term 1"), s("- Term 2")]
+            )
+        );
+        assert_eq!(
+            replace("- item\n\nTerm\n  : Def\n\nText\n"),
+            (s("- item\n\n* Term\n  Def\n\nText\n"), vec![s("* Term")])
+        );
+        assert_eq!(
+            replace("Term\n: Def\n\n* item\n- item\n"),
+            (s("- Term\n  Def\n\n* item\n- item\n"), vec![s("- Term")])
+        );
+
+        let unchanged = [
+            "Text\nTerm\n: Def\n",
+            "Term\n:Def\n",
+            "Term\n\n\n: Def\n",
+            "- Term\n: Def\n",
+            "```\nTerm\n: Def\n```\n",
+        ];
+        for &markdown in unchanged.iter() {
+            assert_eq!(replace(markdown), (s(markdown), vec![]));
+        }
+    }
+
+    #[test]
+    fn bold_terms() {
+        fn find(markdown: &str) -> (String, Vec<(String, String)>) {
+            let mut markdown = markdown.to_string();
+            let terms = find_bold_terms(&mut markdown, Options::empty())
+                .into_iter()
+                .map(|item| {
+                    (
+                        markdown[item.pos..item.term_end].to_string(),
+                        markdown[item.end..].to_string(),
+                    )
+                })
+                .collect();
+            (markdown, terms)
+        }
+        let s = |s: &str| s.to_string();
+
+        assert_eq!(
+            find("- **One:** first\n- __Two__: second"),
+            (
+                s("- **One**: first\n- __Two__: second"),
+                vec![
+                    (s("- **One**"), s("first\n- __Two__: second")),
+                    (s("- __Two__"), s("second"))
+                ]
+            )
+        );
+        assert_eq!(
+            find("- **A:**\n  - **B**: b\n- **C:** c"),
+            (
+                s("- **A**:\n  - **B**: b\n- **C**: c"),
+                vec![
+                    (s("- **A**"), s("\n  - **B**: b\n- **C**: c")),
+                    (s("- **B**"), s("b\n- **C**: c")),
+                    (s("- **C**"), s("c")),
+                ]
+            )
+        );
+        assert_eq!(
+            find("- **A:** a\n  - B\n"),
+            (
+                s("- **A**: a\n  - B\n"),
+                vec![(s("- **A**"), s("a\n  - B\n"))]
+            )
+        );
+
+        let unchanged = [
+            "- **A:** a\n- B: b",
+            "- **A** a",
+            "- **:** a",
+            "- **A**:b",
+            "1. **A:** a",
+            "**A:** a",
+        ];
+        for &markdown in unchanged.iter() {
+            assert_eq!(find(markdown), (s(markdown), vec![]));
+        }
+    }
+}
@@ -0,0 +1,667 @@
+//! A typed model of the structure of a markdown document.
+//!
+//! A [`Document`](struct.Document.html) is a tree of [`Node`](struct.Node.html)s that
+//! mirrors the events of the markdown parser, with math spans as
+//! [`NodeKind::Math`](enum.NodeKind.html#variant.Math) nodes of their own. It lets
+//! tools inspect a document (e.g., its headings, math, images, and links) without
+//! generating LaTeX. The LaTeX writer of
+//! [`MarkdownToLatex`](../struct.MarkdownToLatex.html) consumes the same model.
+//!
+//! # Example
+//!
+//! ```
+//! use bluepaper_core::document::{Document, NodeKind};
+//!
+//! let markdown = concat!(
+//!     "# Title\n\n## Intro\n\nLet $$x > 0$$. See [this](https://example.com).\n\n",
+//!     "## Results\n\n![A plot](plot.png)\n",
+//! );
+//! let document = Document::from_string(markdown.to_string());
+//!
+//! let outline = document.outline();
+//! assert_eq!(outline[0].title, "Title");
+//! let sections: Vec<_> = outline[0].subsections.iter().map(|s| &s.title).collect();
+//! assert_eq!(sections, ["Intro", "Results"]);
+//!
+//! let math: Vec<_> = document
+//!     .nodes()
+//!     .filter_map(|node| match &node.kind {
+//!         NodeKind::Math(math) => Some(math.content.as_str()),
+//!         _ => None,
+//!     })
+//!     .collect();
+//! assert_eq!(math, ["x > 0"]);
+//!
+//! let urls: Vec<_> = document
+//!     .nodes()
+//!     .filter_map(|node| match &node.kind {
+//!         NodeKind::Link { url, .. } | NodeKind::Image { url, .. } => Some(url.as_str()),
+//!         _ => None,
+//!     })
+//!     .collect();
+//! assert_eq!(urls, ["https://example.com", "plot.png"]);
+//!
+//! assert_eq!(document.word_count(), 6); // Title Intro Let See this Results
+//! ```
+
+use crate::preprocess::Replacer;
+use crate::{ConversionOptions, MarkdownToLatex};
+
+use pulldown_cmark::{CodeBlockKind, Event, LinkType, Options, Parser, Tag};
+use std::ops::Range;
+
+/// The structure of a markdown document.
+///
+/// Byte ranges of nodes refer to the markdown after the converter's preprocessing,
+/// which keeps byte offsets, except that front matter is replaced by empty lines and
+/// that definition lists (if enabled) are rewritten as bullet lists.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    blocks: Vec<Node>,
+}
+
+/// A block or inline element of a [`Document`](struct.Document.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+
+    /// The byte range of the element in the (preprocessed) markdown.
+    pub range: Range<usize>,
+
+    /// The child elements. Always empty for leaf kinds (see
+    /// [`NodeKind::is_leaf`](enum.NodeKind.html#method.is_leaf)).
+    pub children: Vec<Node>,
+}
+
+/// The type of a [`Node`](struct.Node.html).
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Paragraph,
+    Heading {
+        level: u32,
+    },
+    BlockQuote,
+    /// A code block. `info` is the info string of a fenced code block (e.g., the
+    /// language), or `None` for an indented code block.
+    CodeBlock {
+        info: Option<String>,
+        code: String,
+    },
+    /// A list. `start` is the number of the first item of an ordered list, or `None`
+    /// for a bullet list.
+    List {
+        start: Option<u64>,
+    },
+    Item,
+    FootnoteDefinition {
+        label: String,
+    },
+    Table {
+        alignments: Vec<Alignment>,
+    },
+    TableHead,
+    TableRow,
+    TableCell,
+    Rule,
+
+    Emphasis,
+    Strong,
+    Strikethrough,
+    /// A link. The children are the link text.
+    Link {
+        kind: LinkKind,
+        url: String,
+        title: String,
+    },
+    /// An image. The children are the alt text.
+    Image {
+        kind: LinkKind,
+        url: String,
+        title: String,
+    },
+    Text(String),
+    /// An inline code span (that is not a math span).
+    Code(String),
+    Math(Math),
+    /// Raw HTML, either a block or inline.
+    Html(String),
+    FootnoteReference {
+        label: String,
+    },
+    /// The checkbox of a task list item.
+    TaskListMarker {
+        checked: bool,
+    },
+    SoftBreak,
+    HardBreak,
+}
+
+/// A math span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Math {
+    /// The LaTeX code, without the delimiters.
+    pub content: String,
+
+    /// Whether the math span is typeset as display math.
+    pub display: bool,
+
+    /// The opening and closing delimiters (e.g., `("$$", "$$")` or `(r"\(", r"\)")`).
+    pub delimiters: (&'static str, &'static str),
+}
+
+/// The syntax of a link or an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// `[text](url)`
+    Inline,
+    /// `[text][label]`
+    Reference,
+    /// `[label][]`
+    Collapsed,
+    /// `[label]`
+    Shortcut,
+    /// `<https://example.com>`
+    Autolink,
+    /// `<jane@example.com>`
+    Email,
+}
+
+/// The alignment of a table column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+/// A section of a document's outline (see
+/// [`Document::outline`](struct.Document.html#method.outline)).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub level: u32,
+
+    /// The plain text of the heading (see
+    /// [`Node::plain_text`](struct.Node.html#method.plain_text)).
+    pub title: String,
+
+    /// The byte range of the heading.
+    pub range: Range<usize>,
+
+    /// The sections with a higher level that follow the heading (up to the next
+    /// heading with the same or a lower level).
+    pub subsections: Vec<Section>,
+}
+
+/// How a [`Walk`](struct.Walk.html) visits a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// Before the children of a node that isn't a leaf.
+    Enter,
+    /// After the children of a node that isn't a leaf.
+    Leave,
+    /// A leaf node, which is visited only once.
+    Leaf,
+}
+
+/// A depth-first traversal of a document (see
+/// [`Document::walk`](struct.Document.html#method.walk)).
+pub struct Walk<'a> {
+    siblings: Vec<std::slice::Iter<'a, Node>>,
+    entered: Vec<&'a Node>,
+}
+
+impl Document {
+    /// Creates the model of a `String` of markdown.
+    pub fn from_string(markdown: String) -> Self {
+        Self::with_options(markdown, ConversionOptions::default())
+    }
+
+    /// Creates the model of a `String` of markdown with custom options.
+    ///
+    /// This preprocesses the markdown like
+    /// [`MarkdownToLatex::with_options`](../struct.MarkdownToLatex.html#method.with_options)
+    /// (e.g., the math delimiters and extensions in `options` and in the front matter
+    /// apply), so the model is exactly what the LaTeX writer sees.
+    pub fn with_options(markdown: String, options: ConversionOptions) -> Self {
+        MarkdownToLatex::with_options(markdown, options).document()
+    }
+
+    /// Builds the model from preprocessed markdown.
+    pub(crate) fn parse(markdown: &str, mut replacer: Replacer, parser_options: Options) -> Self {
+        let mut blocks = Vec::new();
+        let mut open: Vec<Node> = Vec::new();
+        for (event, range) in Parser::new_ext(markdown, parser_options).into_offset_iter() {
+            let kind = match event {
+                Event::Start(tag) => {
+                    open.push(Node {
+                        kind: NodeKind::from_tag(tag),
+                        range,
+                        children: Vec::new(),
+                    });
+                    continue;
+                }
+                Event::End(_) => {
+                    if let Some(node) = open.pop() {
+                        append(node, &mut open, &mut blocks);
+                    }
+                    continue;
+                }
+                Event::Text(text) => {
+                    let text = replacer.un_replace(text, range.clone());
+                    if let Some(Node {
+                        kind: NodeKind::CodeBlock { code, .. },
+                        ..
+                    }) = open.last_mut()
+                    {
+                        // The contents of a code block are a sequence of text events.
+                        code.push_str(&text);
+                        continue;
+                    }
+                    NodeKind::Text(text.into_string())
+                }
+                Event::Code(code) => match replacer.math_span(range.clone()) {
+                    Some(span) => NodeKind::Math(Math {
+                        content: markdown[span.content].to_string(),
+                        display: span.display,
+                        delimiters: span.delimiters,
+                    }),
+                    None => NodeKind::Code(code.into_string()),
+                },
+                Event::Html(html) => {
+                    NodeKind::Html(replacer.un_replace(html, range.clone()).into_string())
+                }
+                Event::FootnoteReference(label) => NodeKind::FootnoteReference {
+                    label: label.into_string(),
+                },
+                Event::SoftBreak => NodeKind::SoftBreak,
+                Event::HardBreak => NodeKind::HardBreak,
+                Event::Rule => NodeKind::Rule,
+                Event::TaskListMarker(checked) => NodeKind::TaskListMarker { checked },
+            };
+            let node = Node {
+                kind,
+                range,
+                children: Vec::new(),
+            };
+            append(node, &mut open, &mut blocks);
+        }
+        while let Some(node) = open.pop() {
+            append(node, &mut open, &mut blocks);
+        }
+
+        Self { blocks }
+    }
+
+    /// Returns the top-level blocks.
+    pub fn blocks(&self) -> &[Node] {
+        &self.blocks
+    }
+
+    /// Returns a depth-first traversal of all nodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bluepaper_core::document::{Document, NodeKind, Visit};
+    ///
+    /// let document = Document::from_string("Some *text*.".to_string());
+    /// let visits: Vec<_> = document
+    ///     .walk()
+    ///     .map(|(visit, node)| match &node.kind {
+    ///         NodeKind::Text(text) => format!("{:?} {}", visit, text),
+    ///         kind => format!("{:?} {:?}", visit, kind),
+    ///     })
+    ///     .collect();
+    /// assert_eq!(
+    ///     visits,
+    ///     [
+    ///         "Enter Paragraph",
+    ///         "Leaf Some ",
+    ///         "Enter Emphasis",
+    ///         "Leaf text",
+    ///         "Leave Emphasis",
+    ///         "Leaf .",
+    ///         "Leave Paragraph",
+    ///     ]
+    /// );
+    /// ```
+    pub fn walk(&self) -> Walk<'_> {
+        Walk {
+            siblings: vec![self.blocks.iter()],
+            entered: Vec::new(),
+        }
+    }
+
+    /// Returns an iterator over all nodes in document order (i.e., each node comes
+    /// before its children).
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.walk()
+            .filter(|(visit, _)| *visit != Visit::Leave)
+            .map(|(_, node)| node)
+    }
+
+    /// Returns the tree of sections, as given by the headings.
+    ///
+    /// A document usually has a single top-level section whose title is the title
+    /// of the document.
+    pub fn outline(&self) -> Vec<Section> {
+        let mut outline = Vec::new();
+        let mut open: Vec<Section> = Vec::new();
+        for node in self.nodes() {
+            if let NodeKind::Heading { level } = node.kind {
+                while open.last().is_some_and(|section| section.level >= level) {
+                    close_section(&mut open, &mut outline);
+                }
+                open.push(Section {
+                    level,
+                    title: node.plain_text(),
+                    range: node.range.clone(),
+                    subsections: Vec::new(),
+                });
+            }
+        }
+        while !open.is_empty() {
+            close_section(&mut open, &mut outline);
+        }
+        outline
+    }
+
+    /// Counts the words of the text, including headings, link texts, and inline code.
+    ///
+    /// Math, code blocks, raw HTML, and the alt text of images aren't counted. A word
+    /// is a sequence of non-whitespace characters that contains a letter or a digit.
+    pub fn word_count(&self) -> usize {
+        let mut text = String::new();
+        for block in &self.blocks {
+            block.write_plain_text(&mut text, false);
+            text.push('\n');
+        }
+        text.split_whitespace()
+            .filter(|word| word.chars().any(char::is_alphanumeric))
+            .count()
+    }
+}
+
+impl Node {
+    /// Returns the text of the node and its descendants without markup, e.g., the
+    /// title of a heading.
+    ///
+    /// Math spans are included with their delimiters. Line breaks become spaces,
+    /// raw HTML and the alt text of images are left out.
+    pub fn plain_text(&self) -> String {
+        let mut text = String::new();
+        self.write_plain_text(&mut text, true);
+        text
+    }
+
+    fn write_plain_text(&self, text: &mut String, math: bool) {
+        match &self.kind {
+            NodeKind::Text(s) | NodeKind::Code(s) => text.push_str(s),
+            NodeKind::Math(m) if math => {
+                text.push_str(m.delimiters.0);
+                text.push_str(&m.content);
+                text.push_str(m.delimiters.1);
+            }
+            NodeKind::Math(_) | NodeKind::SoftBreak | NodeKind::HardBreak => text.push(' '),
+            NodeKind::Image { .. } | NodeKind::CodeBlock { .. } | NodeKind::Html(_) => {}
+            _ => {
+                for child in &self.children {
+                    child.write_plain_text(text, math);
+                    if !child.kind.is_inline() {
+                        text.push('\n');
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl NodeKind {
+    /// Checks if nodes of this kind never have children (e.g., text, math, and code
+    /// blocks).
+    pub fn is_leaf(&self) -> bool {
+        matches!(
+            self,
+            NodeKind::CodeBlock { .. }
+                | NodeKind::Rule
+                | NodeKind::Text(_)
+                | NodeKind::Code(_)
+                | NodeKind::Math(_)
+                | NodeKind::Html(_)
+                | NodeKind::FootnoteReference { .. }
+                | NodeKind::TaskListMarker { .. }
+                | NodeKind::SoftBreak
+                | NodeKind::HardBreak
+        )
+    }
+
+    /// Checks if this is an inline element (e.g., emphasis, a link, or a math span).
+    /// Raw HTML counts as inline because it can be either.
+    pub fn is_inline(&self) -> bool {
+        matches!(
+            self,
+            NodeKind::Emphasis
+                | NodeKind::Strong
+                | NodeKind::Strikethrough
+                | NodeKind::Link { .. }
+                | NodeKind::Image { .. }
+                | NodeKind::Text(_)
+                | NodeKind::Code(_)
+                | NodeKind::Math(_)
+                | NodeKind::Html(_)
+                | NodeKind::FootnoteReference { .. }
+                | NodeKind::TaskListMarker { .. }
+                | NodeKind::SoftBreak
+                | NodeKind::HardBreak
+        )
+    }
+
+    fn from_tag(tag: Tag) -> Self {
+        match tag {
+            Tag::Paragraph => NodeKind::Paragraph,
+            Tag::Heading(level) => NodeKind::Heading { level },
+            Tag::BlockQuote => NodeKind::BlockQuote,
+            Tag::CodeBlock(kind) => NodeKind::CodeBlock {
+                info: match kind {
+                    CodeBlockKind::Indented => None,
+                    CodeBlockKind::Fenced(info) => Some(info.into_string()),
+                },
+                code: String::new(),
+            },
+            Tag::List(start) => NodeKind::List { start },
+            Tag::Item => NodeKind::Item,
+            Tag::FootnoteDefinition(label) => NodeKind::FootnoteDefinition {
+                label: label.into_string(),
+            },
+            Tag::Table(alignments) => NodeKind::Table {
+                alignments: alignments.into_iter().map(Alignment::from).collect(),
+            },
+            Tag::TableHead => NodeKind::TableHead,
+            Tag::TableRow => NodeKind::TableRow,
+            Tag::TableCell => NodeKind::TableCell,
+            Tag::Emphasis => NodeKind::Emphasis,
+            Tag::Strong => NodeKind::Strong,
+            Tag::Strikethrough => NodeKind::Strikethrough,
+            Tag::Link(kind, url, title) => NodeKind::Link {
+                kind: kind.into(),
+                url: url.into_string(),
+                title: title.into_string(),
+            },
+            Tag::Image(kind, url, title) => NodeKind::Image {
+                kind: kind.into(),
+                url: url.into_string(),
+                title: title.into_string(),
+            },
+        }
+    }
+}
+
+impl From<LinkType> for LinkKind {
+    fn from(link_type: LinkType) -> Self {
+        match link_type {
+            LinkType::Inline => LinkKind::Inline,
+            LinkType::Reference | LinkType::ReferenceUnknown => LinkKind::Reference,
+            LinkType::Collapsed | LinkType::CollapsedUnknown => LinkKind::Collapsed,
+            LinkType::Shortcut | LinkType::ShortcutUnknown => LinkKind::Shortcut,
+            LinkType::Autolink => LinkKind::Autolink,
+            LinkType::Email => LinkKind::Email,
+        }
+    }
+}
+
+impl From<pulldown_cmark::Alignment> for Alignment {
+    fn from(alignment: pulldown_cmark::Alignment) -> Self {
+        match alignment {
+            pulldown_cmark::Alignment::None => Alignment::None,
+            pulldown_cmark::Alignment::Left => Alignment::Left,
+            pulldown_cmark::Alignment::Center => Alignment::Center,
+            pulldown_cmark::Alignment::Right => Alignment::Right,
+        }
+    }
+}
+
+impl<'a> Walk<'a> {
+    /// Skips the remaining descendants of the innermost node that was entered and
+    /// the visit that leaves it.
+    pub fn skip_subtree(&mut self) {
+        if self.entered.pop().is_some() {
+            self.siblings.pop();
+        }
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (Visit, &'a Node);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.siblings.last_mut()?.next() {
+            Some(node) if node.kind.is_leaf() => Some((Visit::Leaf, node)),
+            Some(node) => {
+                self.siblings.push(node.children.iter());
+                self.entered.push(node);
+                Some((Visit::Enter, node))
+            }
+            None => {
+                self.siblings.pop();
+                self.entered.pop().map(|node| (Visit::Leave, node))
+            }
+        }
+    }
+}
+
+/// Appends a complete node to the innermost open node or to the top-level blocks.
+fn append(node: Node, open: &mut [Node], blocks: &mut Vec<Node>) {
+    match open.last_mut() {
+        Some(parent) => parent.children.push(node),
+        None => blocks.push(node),
+    }
+}
+
+/// Closes the innermost open section of an outline.
+fn close_section(open: &mut Vec<Section>, outline: &mut Vec<Section>) {
+    let section = open.pop().expect("a section is open");
+    match open.last_mut() {
+        Some(parent) => parent.subsections.push(section),
+        None => outline.push(section),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn document(markdown: &str) -> Document {
+        Document::from_string(markdown.to_string())
+    }
+
+    #[test]
+    fn math_nodes() {
+        let document = document("Inline $$a_1$$, `code`, and\n\n$$\n\\sum_i x_i\n$$\n");
+        let kinds: Vec<_> = document.nodes().map(|node| &node.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                &NodeKind::Paragraph,
+                &NodeKind::Text("Inline ".to_string()),
+                &NodeKind::Math(Math {
+                    content: "a_1".to_string(),
+                    display: false,
+                    delimiters: ("$$", "$$"),
+                }),
+                &NodeKind::Text(", ".to_string()),
+                &NodeKind::Code("code".to_string()),
+                &NodeKind::Text(", and".to_string()),
+                &NodeKind::Paragraph,
+                &NodeKind::Math(Math {
+                    content: "\n\\sum_i x_i\n".to_string(),
+                    display: false,
+                    delimiters: ("$$", "$$"),
+                }),
+            ]
+        );
+        assert_eq!(document.blocks()[0].children[1].range, 7..14);
+    }
+
+    #[test]
+    fn unreplaced_html() {
+        // The preprocessing doesn't know about HTML blocks, so it replaces the math
+        // delimiters, which have to be restored.
+        let document = document("<div>\n$$x$$\n</div>\n");
+        let html: Vec<_> = document.blocks().iter().map(|node| &node.kind).collect();
+        assert_eq!(
+            html,
+            [
+                &NodeKind::Html("<div>\n".to_string()),
+                &NodeKind::Html("$$x$$\n".to_string()),
+                &NodeKind::Html("</div>\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn outline() {
+        let document = document("## A\n\n#### B\n\n### C\n\n# D\n\n> ## E\n");
+        let titles = |sections: &[Section]| -> Vec<(u32, String, usize)> {
+            sections
+                .iter()
+                .map(|s| (s.level, s.title.clone(), s.subsections.len()))
+                .collect()
+        };
+        let outline = document.outline();
+        assert_eq!(
+            titles(&outline),
+            [(2, "A".to_string(), 2), (1, "D".to_string(), 1)]
+        );
+        assert_eq!(
+            titles(&outline[0].subsections),
+            [(4, "B".to_string(), 0), (3, "C".to_string(), 0)]
+        );
+        assert_eq!(outline[1].subsections[0].title, "E");
+    }
+
+    #[test]
+    fn word_count() {
+        let document = document(
+            "# A title\n\nSome *emphasized*text, `code`, $$x + y$$ -- and a \
+             [link](https://example.com).\n\n- An item\n\n```\nnot counted\n```\n\n\
+             ![alt text](image.png) <b>bold</b>\n",
+        );
+        assert_eq!(document.word_count(), 11);
+    }
+
+    #[test]
+    fn skip_subtree() {
+        let document = document("A *b **c** d* e\n\nf");
+        let mut walk = document.walk();
+        let mut texts = Vec::new();
+        while let Some((visit, node)) = walk.next() {
+            match &node.kind {
+                NodeKind::Strong => walk.skip_subtree(),
+                NodeKind::Text(text) => texts.push(text.as_str()),
+                _ => assert_ne!(visit, Visit::Leaf),
+            }
+        }
+        assert_eq!(texts, ["A ", "b ", " d", " e", "f"]);
+    }
+}
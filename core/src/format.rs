@@ -1,5 +1,8 @@
 //! Utility for writing cleanly formatted text files.
 
+use crate::preamble::Preamble;
+use crate::Engine;
+
 use std::cmp::{max, min};
 use std::io::prelude::*;
 
@@ -55,6 +58,15 @@ pub struct WhitespaceFormatter<W: Write> {
     current_newline_limit: u32,
 }
 
+/// The newlines that a `WhitespaceFormatter` will insert before the next text, see
+/// [`add_newlines`](struct.WhitespaceFormatter.html#method.add_newlines) and
+/// [`limit_newlines`](struct.WhitespaceFormatter.html#method.limit_newlines).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PendingNewlines {
+    newlines: u32,
+    limit: u32,
+}
+
 impl<W: Write> WhitespaceFormatter<W> {
     /// Creates a new `WhitespaceFormatter`.
     ///
@@ -73,8 +85,7 @@ impl<W: Write> WhitespaceFormatter<W> {
     }
 
     fn write_latex_preamble(&mut self) -> std::io::Result<()> {
-        self.inner.write_all(include_bytes!("preamble.tex"))?;
-        Ok(())
+        Preamble::new(Engine::PdfLatex, None).write_to(&mut self.inner)
     }
 
     /// Creates a new `WhitespaceFormatter` with a custom indentation width.
@@ -238,6 +249,24 @@ impl<W: Write> WhitespaceFormatter<W> {
         }
     }
 
+    /// Returns the newlines that will be inserted before the next text.
+    ///
+    /// Together with [`set_pending_newlines`](#method.set_pending_newlines), this
+    /// allows to continue the output of one formatter in another one, provided that
+    /// the indentation level is zero.
+    pub(crate) fn pending_newlines(&self) -> PendingNewlines {
+        PendingNewlines {
+            newlines: self.current_newlines,
+            limit: self.current_newline_limit,
+        }
+    }
+
+    /// Replaces the newlines that will be inserted before the next text.
+    pub(crate) fn set_pending_newlines(&mut self, pending: PendingNewlines) {
+        self.current_newlines = pending.newlines;
+        self.current_newline_limit = pending.limit;
+    }
+
     fn prepare_writing(&mut self) -> std::io::Result<()> {
         let newlines = min(self.current_newlines, self.current_newline_limit);
 
@@ -274,7 +303,7 @@ impl<W: Write> Write for WhitespaceFormatter<W> {
     /// limit.
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.prepare_writing()?;
-        Ok(self.inner.write(buf)?)
+        self.inner.write(buf)
     }
 
     /// Writes the full contents of `buf`, possibly preceded by newlines and
@@ -0,0 +1,303 @@
+//! Translation of common inline HTML tags (e.g., `<sub>` or `<br>`) into LaTeX.
+
+use crate::format::WhitespaceFormatter;
+use crate::latex_escape::escape_str_and_unicode;
+
+use log::warn;
+use pulldown_cmark::{Event, Options, Parser};
+use std::io::Write;
+
+/// HTML tags that are translated into LaTeX commands that take the content as an
+/// argument: (tag name, opening LaTeX, closing LaTeX).
+const TAGS: [(&str, &str, &str); 15] = [
+    ("b", r"\textbf{", "}"),
+    ("strong", r"\textbf{", "}"),
+    ("i", r"\emph{", "}"),
+    ("em", r"\emph{", "}"),
+    ("u", r"\underline{", "}"),
+    ("ins", r"\underline{", "}"),
+    ("s", r"\sout{", "}"),
+    ("del", r"\sout{", "}"),
+    ("strike", r"\sout{", "}"),
+    ("sub", r"\textsubscript{", "}"),
+    ("sup", r"\textsuperscript{", "}"),
+    ("mark", r"\hl{", "}"),
+    ("kbd", r"\fbox{\texttt{", "}}"),
+    ("code", r"\texttt{", "}"),
+    ("small", r"{\small ", "}"),
+];
+
+/// Checks if the document contains a `<mark>` tag, which requires the `soul` package.
+pub fn uses_highlighting(markdown: &str, parser_options: Options) -> bool {
+    // Avoids parsing documents that can't contain any `<mark>` tag.
+    let mark = b"<mark";
+    if !markdown
+        .as_bytes()
+        .windows(mark.len())
+        .any(|window| window.eq_ignore_ascii_case(mark))
+    {
+        return false;
+    }
+
+    Parser::new_ext(markdown, parser_options).any(|event| match event {
+        Event::Html(html) => html.to_ascii_lowercase().contains("<mark"),
+        _ => false,
+    })
+}
+
+/// Returns whether a comment is open after `html`, given whether one was open
+/// before. Follows the same rules as `HtmlTranslator::write` without writing
+/// anything.
+pub fn in_comment_after(html: &str, mut in_comment: bool) -> bool {
+    let mut rest = html;
+    while !rest.is_empty() {
+        if in_comment {
+            match rest.find("-->") {
+                Some(end) => {
+                    in_comment = false;
+                    rest = &rest[end + 3..];
+                }
+                None => break,
+            }
+        } else if let Some(comment) = rest.strip_prefix("<!--") {
+            in_comment = true;
+            rest = comment;
+        } else if rest.starts_with('<') {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+        } else {
+            rest = rest.find('<').map_or("", |end| &rest[end..]);
+        }
+    }
+    in_comment
+}
+
+/// Translates the HTML tags of a document one `Event::Html` at a time.
+///
+/// The tags in `TAGS`, `<br>`, and `<wbr>` are translated into LaTeX. Comments that
+/// are part of an HTML block (i.e., that start on a line of their own) become LaTeX
+/// comments, other comments are dropped. Other tags are dropped with a warning, and
+/// text within HTML blocks is escaped.
+#[derive(Debug, Default)]
+pub struct HtmlTranslator {
+    /// Names and closing LaTeX of the tags that are currently open.
+    open_tags: Vec<(String, &'static str)>,
+
+    /// If we are within a comment: whether the comment is kept as a LaTeX comment.
+    in_comment: Option<bool>,
+}
+
+impl HtmlTranslator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the translation of `html`.
+    ///
+    /// The argument `block` indicates whether `html` is part of an HTML block, and
+    /// `line` returns the line number for diagnostics.
+    pub fn write<W: Write>(
+        &mut self,
+        html: &str,
+        block: bool,
+        line: impl Fn() -> usize,
+        writer: &mut WhitespaceFormatter<W>,
+    ) -> std::io::Result<()> {
+        let mut rest = html;
+        while !rest.is_empty() {
+            if let Some(keep) = self.in_comment {
+                let end = match rest.find("-->") {
+                    Some(end) => {
+                        self.in_comment = None;
+                        end
+                    }
+                    None => rest.len(),
+                };
+                if keep {
+                    write_comment(&rest[..end], writer)?;
+                }
+                rest = rest.get(end + 3..).unwrap_or("");
+            } else if let Some(comment) = rest.strip_prefix("<!--") {
+                self.in_comment = Some(block);
+                rest = comment;
+            } else if rest.starts_with('<') {
+                let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+                self.write_tag(&rest[..end], &line, writer)?;
+                rest = &rest[end..];
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                write_text(&rest[..end], writer)?;
+                rest = &rest[end..];
+            }
+        }
+        Ok(())
+    }
+
+    fn write_tag<W: Write>(
+        &mut self,
+        tag: &str,
+        line: &impl Fn() -> usize,
+        writer: &mut WhitespaceFormatter<W>,
+    ) -> std::io::Result<()> {
+        let inner = tag.trim_start_matches('<').trim_end_matches('>');
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let name = inner
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        match (name.as_str(), closing) {
+            ("br", false) => {
+                writer.write_all(br" \\")?;
+                writer.add_newlines(1);
+            }
+            ("br", true) | ("wbr", _) => {}
+            (_, false) => {
+                if let Some(&(_, opening, closing)) = TAGS.iter().find(|(n, _, _)| *n == name) {
+                    writer.write_all(opening.as_bytes())?;
+                    if !inner.trim_end().ends_with('/') {
+                        self.open_tags.push((name, closing));
+                    } else {
+                        writer.write_all(closing.as_bytes())?;
+                    }
+                } else {
+                    warn!(
+                        "Ignoring unsupported HTML tag \"{}\" on line {}.",
+                        tag,
+                        line()
+                    );
+                }
+            }
+            (_, true) => {
+                if let Some(index) = self.open_tags.iter().rposition(|(n, _)| *n == name) {
+                    for (_, closing) in self.open_tags.drain(index..).rev() {
+                        writer.write_all(closing.as_bytes())?;
+                    }
+                } else if !TAGS.iter().any(|(n, _, _)| *n == name) {
+                    warn!(
+                        "Ignoring unsupported HTML tag \"{}\" on line {}.",
+                        tag,
+                        line()
+                    );
+                } else {
+                    warn!(
+                        "Ignoring unmatched HTML tag \"{}\" on line {}.",
+                        tag,
+                        line()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes all open tags, e.g., at the end of a paragraph.
+    pub fn close_all<W: Write>(
+        &mut self,
+        writer: &mut WhitespaceFormatter<W>,
+    ) -> std::io::Result<()> {
+        for (name, closing) in self.open_tags.drain(..).rev() {
+            warn!(
+                "Closing unclosed HTML tag \"<{}>\" at the end of a block.",
+                name
+            );
+            writer.write_all(closing.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes each line of a comment as a LaTeX comment on a line of its own.
+fn write_comment<W: Write>(
+    comment: &str,
+    writer: &mut WhitespaceFormatter<W>,
+) -> std::io::Result<()> {
+    for line in comment.lines().map(str::trim) {
+        if !line.is_empty() {
+            writer.write_on_single_line(&format!("% {}", line))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes text within an HTML block, keeping line breaks.
+fn write_text<W: Write>(text: &str, writer: &mut WhitespaceFormatter<W>) -> std::io::Result<()> {
+    let mut lines = text.split('\n').peekable();
+    let mut first = true;
+    while let Some(mut line) = lines.next() {
+        if !first {
+            writer.add_newlines(1);
+            line = line.trim_start();
+        }
+        if lines.peek().is_some() {
+            line = line.trim_end();
+        }
+        if !line.is_empty() {
+            escape_str_and_unicode(line, writer.get_mut()?)?;
+        }
+        first = false;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn translate(events: &[(&str, bool)]) -> String {
+        let mut writer = WhitespaceFormatter::new(Vec::new());
+        let mut translator = HtmlTranslator::new();
+        for &(html, block) in events {
+            translator.write(html, block, || 1, &mut writer).unwrap();
+        }
+        translator.close_all(&mut writer).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn inline_tags() {
+        assert_eq!(
+            translate(&[("<sub>", false), ("</sub>", false), ("<SUP>", false)]),
+            r"\textsubscript{}\textsuperscript{}"
+        );
+        assert_eq!(
+            translate(&[
+                ("<kbd>", false),
+                ("<mark class=\"x\">", false),
+                ("</kbd>", false)
+            ]),
+            r"\fbox{\texttt{\hl{}}}"
+        );
+        assert_eq!(translate(&[("<br/>", false)]), " \\\\\n");
+        assert_eq!(translate(&[("<span>", false), ("</u>", false)]), "");
+        assert_eq!(translate(&[("<!-- note -->", false)]), "");
+    }
+
+    #[test]
+    fn comment_state() {
+        assert!(in_comment_after("<!-- note\n", false));
+        assert!(!in_comment_after("<!-- note -->\n", false));
+        assert!(!in_comment_after("end -->", true));
+        assert!(in_comment_after("still <b> open", true));
+        assert!(!in_comment_after("<a title=\"<!--\">", false));
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            translate(&[("<!-- A note\n", true), ("  on two lines -->\n", true)]),
+            "% A note\n% on two lines\n"
+        );
+        assert_eq!(
+            translate(&[
+                ("<div>\n", true),
+                ("50% <u>off</u>\n", true),
+                ("</div>\n", true)
+            ]),
+            "50\\% \\underline{off}\n"
+        );
+    }
+}
@@ -0,0 +1,534 @@
+//! Incremental conversion for live editing, see
+//! [`IncrementalConverter`](../struct.IncrementalConverter.html).
+
+use crate::bibliography::NoMetadata;
+use crate::callout::Callout;
+use crate::description::replace_definition_lists;
+use crate::format::{PendingNewlines, WhitespaceFormatter};
+use crate::html;
+use crate::language::{count_stopwords, StopwordCounts};
+use crate::lists::{replace_list_markers, ListNesting};
+use crate::metadata::{extract_front_matter, DocumentMetadata};
+use crate::preprocess::{fenced_code_blocks, strip_heading_attributes, Replacer};
+use crate::theorem::TheoremHead;
+use crate::{heading_text, ConversionOptions, Language, MarkdownToLatex};
+
+use pulldown_cmark::{Event, Parser, Tag};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::ops::Range;
+
+/// A converter for a document that is being edited, which updates the LaTeX code
+/// after each edit by converting only the parts of the document that changed.
+///
+/// The converter splits the document into parts that consist of one or more
+/// top-level blocks (paragraphs, headings, lists, ...) and caches the LaTeX code of
+/// each part. After an edit, it converts only the parts whose markdown changed (and
+/// parts whose vertical spacing depends on a changed part). The LaTeX code is always
+/// identical to the output of [`MarkdownToLatex::into_string`] for the whole
+/// document, i.e., images are commented out.
+///
+/// Some features make parts of a document depend on other parts in ways that can't
+/// be tracked per part: link reference definitions, footnote definitions,
+/// definition lists, special sections (see [`SectionNames`]), citations, references
+/// to theorems, and the summary of open tasks. While a document uses any of these,
+/// each edit converts the whole document. Each edit also parses the whole document
+/// once to find the parts, which is much faster than converting it.
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::{ConversionOptions, IncrementalConverter, MarkdownToLatex};
+///
+/// let markdown = "# Title\n\nFirst paragraph.\n\nSecond paragraph.\n";
+/// let mut converter =
+///     IncrementalConverter::new(markdown.to_string(), ConversionOptions::default());
+/// assert!(converter.latex().contains("\n\nFirst paragraph.\n\nSecond paragraph.\n"));
+///
+/// // Replace "Second" with "*Last*".
+/// converter.edit(27..33, "*Last*");
+/// assert_eq!(converter.markdown(), "# Title\n\nFirst paragraph.\n\n*Last* paragraph.\n");
+/// assert!(converter.latex().contains("\n\nFirst paragraph.\n\n\\emph{Last} paragraph.\n"));
+///
+/// let full = MarkdownToLatex::from_string(converter.markdown().to_string()).into_string();
+/// assert_eq!(converter.latex(), full);
+/// ```
+///
+/// [`MarkdownToLatex::into_string`]: struct.MarkdownToLatex.html#method.into_string
+/// [`SectionNames`]: struct.SectionNames.html
+pub struct IncrementalConverter {
+    markdown: String,
+    options: ConversionOptions,
+
+    /// The markdown without front matter (see `extract_front_matter`).
+    body: String,
+
+    /// The options (with overrides from the front matter) and the metadata that the
+    /// cached parts were converted with.
+    front_matter: Option<(ConversionOptions, DocumentMetadata)>,
+
+    parts: Vec<Part>,
+    latex: String,
+
+    /// The number of parts that the last update converted, or `None` if it converted
+    /// the whole document.
+    converted_parts: Option<usize>,
+}
+
+/// A range of top-level blocks that is converted on its own.
+struct Part {
+    /// Byte range in `IncrementalConverter::body`.
+    range: Range<usize>,
+    analysis: Analysis,
+    conversion: Option<Conversion>,
+}
+
+/// The information about a part that affects the preamble or other parts.
+struct Analysis {
+    theorems: Vec<(usize, TheoremHead)>,
+    callouts: Vec<(usize, Callout)>,
+    highlighting: bool,
+    list_markers: bool,
+    list_nesting: ListNesting,
+    stopwords: StopwordCounts,
+
+    /// The hash of the events that the markdown parser emits for the part on its own
+    /// (see `hash_event`).
+    structure: u64,
+
+    /// Whether the part uses a feature that depends on the whole document.
+    global: bool,
+}
+
+/// The LaTeX code of a part and the context in which it was converted.
+struct Conversion {
+    newlines_before: PendingNewlines,
+    language: Option<Language>,
+    enumitem: bool,
+    latex: String,
+    newlines_after: PendingNewlines,
+}
+
+impl IncrementalConverter {
+    /// Creates a new converter and converts `markdown` with `options`.
+    ///
+    /// As with [`MarkdownToLatex::with_options`], options in the front matter of the
+    /// document take precedence over `options`.
+    ///
+    /// [`MarkdownToLatex::with_options`]: struct.MarkdownToLatex.html#method.with_options
+    pub fn new(markdown: String, options: ConversionOptions) -> Self {
+        let mut converter = Self {
+            markdown,
+            options,
+            body: String::new(),
+            front_matter: None,
+            parts: Vec::new(),
+            latex: String::new(),
+            converted_parts: None,
+        };
+        converter.update();
+        converter
+    }
+
+    /// Replaces the byte range `range` of the markdown with `replacement` and updates
+    /// the LaTeX code.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start or the end of `range` are out of bounds or don't lie on
+    /// a `char` boundary (like `String::replace_range`).
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) {
+        self.markdown.replace_range(range, replacement);
+        self.update();
+    }
+
+    /// Returns the current markdown.
+    pub fn markdown(&self) -> &str {
+        &self.markdown
+    }
+
+    /// Returns the LaTeX code of the current markdown.
+    pub fn latex(&self) -> &str {
+        &self.latex
+    }
+
+    fn update(&mut self) {
+        let mut body = self.markdown.clone();
+        let mut options = self.options.clone();
+        let metadata = extract_front_matter(&mut body, &mut options);
+        let old_body = std::mem::replace(&mut self.body, body);
+        let mut old_parts = std::mem::take(&mut self.parts);
+        let front_matter = Some((options.clone(), metadata.clone()));
+        if self.front_matter != front_matter {
+            old_parts.clear();
+            self.front_matter = front_matter;
+        }
+
+        let (ranges, structures): (Vec<_>, Vec<_>) = match split_into_parts(&self.body, &options) {
+            Some(parts) => parts.into_iter().unzip(),
+            None => {
+                self.convert_whole_document();
+                return;
+            }
+        };
+
+        // Reuse the analysis (and possibly the conversion) of unchanged parts.
+        let mut unchanged: HashMap<(&str, bool), Vec<Part>> = HashMap::new();
+        for part in old_parts.into_iter().rev() {
+            unchanged
+                .entry((&old_body[part.range.clone()], part.range.start == 0))
+                .or_default()
+                .push(part);
+        }
+        let mut parts = Vec::with_capacity(ranges.len());
+        let mut converters = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            let markdown = &self.body[range.clone()];
+            let first = range.start == 0;
+            if let Some(mut part) = unchanged.get_mut(&(markdown, first)).and_then(Vec::pop) {
+                part.range = range;
+                parts.push(part);
+                converters.push(None);
+            } else {
+                let converter = part_converter(markdown, first, &options, &metadata);
+                parts.push(Part {
+                    range,
+                    analysis: Analysis::of(markdown, &converter, &options),
+                    conversion: None,
+                });
+                converters.push(Some(converter));
+            }
+        }
+        drop(unchanged);
+
+        // The markdown parser occasionally parses a block differently depending on
+        // earlier blocks (e.g., whether an empty list item interrupts a paragraph).
+        let structure_changed = parts
+            .iter()
+            .zip(structures)
+            .any(|(part, structure)| part.analysis.structure != structure);
+        if structure_changed || parts.iter().any(|part| part.analysis.global) {
+            self.parts = parts;
+            self.convert_whole_document();
+            return;
+        }
+
+        // Combine the information that the full conversion gathers from the whole
+        // document.
+        if options.language.is_none() && options.detect_language {
+            let mut stopwords = StopwordCounts::default();
+            for part in &parts {
+                for (count, part_count) in stopwords.iter_mut().zip(&part.analysis.stopwords) {
+                    *count += part_count;
+                }
+            }
+            options.language = Language::from_stopword_counts(&stopwords);
+        }
+        let mut document = MarkdownToLatex::without_front_matter(String::new(), options.clone());
+        for part in &parts {
+            let analysis = &part.analysis;
+            document.theorems.extend(analysis.theorems.iter().cloned());
+            document.callouts.extend(analysis.callouts.iter().cloned());
+            document.highlighting |= analysis.highlighting;
+            document.force_enumitem |= analysis.list_markers;
+            document.list_nesting = ListNesting {
+                enumerate: document
+                    .list_nesting
+                    .enumerate
+                    .max(analysis.list_nesting.enumerate),
+                itemize: document
+                    .list_nesting
+                    .itemize
+                    .max(analysis.list_nesting.itemize),
+                total: document.list_nesting.total.max(analysis.list_nesting.total),
+            };
+        }
+        let enumitem = document.uses_enumitem();
+
+        let mut latex = Vec::new();
+        document.preamble().write_to(&mut latex).unwrap();
+        let mut writer = WhitespaceFormatter::new(latex);
+        writer.limit_newlines(2);
+        writer.add_newlines(2);
+
+        let mut converted_parts = 0;
+        for (part, converter) in parts.iter_mut().zip(converters) {
+            let newlines_before = writer.pending_newlines();
+            let up_to_date = part.conversion.as_ref().is_some_and(|conversion| {
+                conversion.newlines_before == newlines_before
+                    && conversion.language == options.language
+                    && conversion.enumitem == enumitem
+            });
+            if !up_to_date {
+                let mut converter = converter.unwrap_or_else(|| {
+                    let markdown = &self.body[part.range.clone()];
+                    part_converter(markdown, part.range.start == 0, &options, &metadata)
+                });
+                converter.options.language = options.language;
+                converter.force_enumitem = enumitem;
+                part.conversion = Some(Conversion::new(converter, newlines_before));
+                converted_parts += 1;
+            }
+
+            let conversion = part.conversion.as_ref().expect("converted above");
+            writer.limit_newlines(0);
+            writer
+                .get_mut()
+                .unwrap()
+                .write_all(conversion.latex.as_bytes())
+                .unwrap();
+            writer.set_pending_newlines(conversion.newlines_after);
+        }
+
+        writer.limit_newlines(3);
+        writer.add_newlines(3);
+        writer.write_all(b"\\end{document}\n").unwrap();
+        let latex = writer.into_inner().unwrap();
+        self.latex = String::from_utf8(latex).expect("LaTeX output is valid UTF-8");
+        self.parts = parts;
+        self.converted_parts = Some(converted_parts);
+    }
+
+    fn convert_whole_document(&mut self) {
+        self.latex = MarkdownToLatex::with_options(self.markdown.clone(), self.options.clone())
+            .into_string();
+        self.converted_parts = None;
+    }
+}
+
+impl Analysis {
+    fn of(markdown: &str, converter: &MarkdownToLatex, options: &ConversionOptions) -> Self {
+        let parser_options = options.extensions.parser_options();
+        let mut preprocessed = markdown.to_string();
+        preprocess(&mut preprocessed, options);
+        let mut hasher = DefaultHasher::new();
+        for (event, range) in Parser::new_ext(&preprocessed, parser_options).into_offset_iter() {
+            hash_event(&event, range, 0, &mut hasher);
+        }
+
+        let stopwords = if options.language.is_none() && options.detect_language {
+            count_stopwords(&converter.preprocessed, parser_options)
+        } else {
+            StopwordCounts::default()
+        };
+        let global = !converter.bibliography.is_empty()
+            || converter
+                .theorems
+                .iter()
+                .any(|(_, head)| head.label.is_some())
+            || (options.todo_summary && converter.task_items.iter().any(|item| !item.checked));
+
+        Self {
+            theorems: converter.theorems.clone(),
+            callouts: converter.callouts.clone(),
+            highlighting: converter.highlighting,
+            list_markers: !converter.list_markers.is_empty(),
+            list_nesting: converter.list_nesting,
+            stopwords,
+            structure: hasher.finish(),
+            global,
+        }
+    }
+}
+
+impl Conversion {
+    fn new(converter: MarkdownToLatex, newlines_before: PendingNewlines) -> Self {
+        let language = converter.options.language;
+        let enumitem = converter.force_enumitem;
+        let mut writer = WhitespaceFormatter::new(Vec::new());
+        writer.set_pending_newlines(newlines_before);
+        converter.write_body(&mut writer, &mut |_| None).unwrap();
+        let newlines_after = writer.pending_newlines();
+        writer.limit_newlines(0);
+        let latex = writer.into_inner().unwrap();
+
+        Self {
+            newlines_before,
+            language,
+            enumitem,
+            latex: String::from_utf8(latex).expect("LaTeX output is valid UTF-8"),
+            newlines_after,
+        }
+    }
+}
+
+/// Creates a converter for a part of a document whose front matter has already been
+/// extracted. Only the first part has a title.
+fn part_converter(
+    markdown: &str,
+    first: bool,
+    options: &ConversionOptions,
+    metadata: &DocumentMetadata,
+) -> MarkdownToLatex {
+    let options = ConversionOptions {
+        // The language is detected from the whole document.
+        detect_language: false,
+        title_from_first_heading: options.title_from_first_heading && first,
+        ..options.clone()
+    };
+    let mut converter = MarkdownToLatex::without_front_matter(markdown.to_string(), options);
+    if first {
+        converter.metadata = metadata.clone();
+    }
+    converter.resolve_citations(&mut NoMetadata);
+    converter
+}
+
+/// Applies the preprocessing steps of `MarkdownToLatex::without_front_matter` that
+/// affect how the markdown parser splits a document into blocks (except for
+/// definition lists, which change byte offsets).
+fn preprocess(markdown: &mut String, options: &ConversionOptions) {
+    if options.extensions.heading_attributes {
+        strip_heading_attributes(markdown);
+    }
+    if options.extensions.fancy_lists {
+        replace_list_markers(markdown, options.extensions.parser_options());
+    }
+    Replacer::replace(markdown, options.math_delimiters);
+}
+
+/// Adds an event of a part that starts at byte offset `offset` to the hash of the
+/// part's structure. The text of events isn't hashed because it is determined by
+/// the byte range.
+fn hash_event(event: &Event, range: Range<usize>, offset: usize, hasher: &mut impl Hasher) {
+    std::mem::discriminant(event).hash(hasher);
+    if let Event::Start(tag) | Event::End(tag) = event {
+        format!("{:?}", tag).hash(hasher);
+    }
+    (range.start - offset, range.end - offset).hash(hasher);
+}
+
+/// Splits a document (without front matter) into parts that can be converted
+/// independently of each other. Returns the byte ranges of the parts, which cover
+/// the whole document, together with the hashes of their structure (see
+/// `hash_event`), or `None` if the document can't be split.
+///
+/// A part ends after a top-level paragraph, heading, or list that is followed by a
+/// blank line and that doesn't end within an HTML comment or within what the
+/// preprocessing steps consider a fenced code block. The converter closes open HTML
+/// tags and quotation marks at the end of these blocks, and the blank line ensures
+/// that the next part is (usually) parsed and preprocessed the same way on its own.
+/// The document can't be split if it (possibly) contains link reference definitions,
+/// footnote definitions, definition lists, or special sections.
+fn split_into_parts(
+    markdown: &str,
+    options: &ConversionOptions,
+) -> Option<Vec<(Range<usize>, u64)>> {
+    if markdown.contains("]:") {
+        return None;
+    }
+
+    let mut preprocessed = markdown.to_string();
+    if options.extensions.definition_lists
+        && !replace_definition_lists(&mut preprocessed).is_empty()
+    {
+        return None;
+    }
+    preprocess(&mut preprocessed, options);
+
+    let fenced_code_blocks = fenced_code_blocks(preprocessed.as_bytes());
+    let mut fenced_code_blocks = fenced_code_blocks.iter().peekable();
+    let mut parts = Vec::new();
+    let mut start_of_part = 0;
+    let mut hasher = DefaultHasher::new();
+    let mut depth = 0;
+    let mut in_comment = false;
+    let mut splittable = false;
+    let parser_options = options.extensions.parser_options();
+    for (event, range) in Parser::new_ext(&preprocessed, parser_options).into_offset_iter() {
+        if depth == 0 && !matches!(event, Event::End(_)) && std::mem::take(&mut splittable) {
+            let start = preprocessed[..range.start]
+                .rfind('\n')
+                .map_or(0, |newline| newline + 1);
+            while fenced_code_blocks
+                .next_if(|block| block.end <= start)
+                .is_some()
+            {}
+            let in_fenced_code_block = fenced_code_blocks
+                .peek()
+                .is_some_and(|block| block.start < start);
+            if !in_comment && !in_fenced_code_block && follows_blank_line(&preprocessed, start) {
+                parts.push((start_of_part..start, hasher.finish()));
+                start_of_part = start;
+                hasher = DefaultHasher::new();
+            }
+        }
+
+        hash_event(&event, range.clone(), start_of_part, &mut hasher);
+
+        match event {
+            Event::Start(tag) => {
+                if let Tag::Heading(_) = tag {
+                    let text = heading_text(&preprocessed[range.clone()]);
+                    if options.section_names.classify(text).is_some() {
+                        return None;
+                    }
+                }
+                depth += 1;
+            }
+            Event::End(tag) => {
+                depth -= 1;
+                if depth == 0 {
+                    splittable = matches!(tag, Tag::Paragraph | Tag::Heading(_) | Tag::List(_));
+                }
+            }
+            Event::Html(html) => {
+                in_comment = html::in_comment_after(&html, in_comment);
+                if depth == 0 {
+                    splittable = false;
+                }
+            }
+            _ => {
+                if depth == 0 {
+                    splittable = false;
+                }
+            }
+        }
+    }
+
+    parts.push((start_of_part..markdown.len(), hasher.finish()));
+    Some(parts)
+}
+
+/// Checks if the line before the line that starts at `start` is blank. (The range of
+/// a list includes the blank lines after it.)
+fn follows_blank_line(markdown: &str, start: usize) -> bool {
+    let before = match markdown[..start].strip_suffix('\n') {
+        Some(before) => before,
+        None => return false,
+    };
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    before[line_start..]
+        .bytes()
+        .all(|b| b == b' ' || b == b'\t' || b == b'\r')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn converts_changed_parts() {
+        let markdown = "# Title\n\nFirst.\n\n- a\n- b\n\nLast.\n";
+        let mut converter = IncrementalConverter::new(markdown.to_string(), Default::default());
+        assert_eq!(converter.parts.len(), 4);
+        assert_eq!(converter.converted_parts, Some(4));
+
+        converter.edit(9..14, "Changed");
+        assert_eq!(converter.converted_parts, Some(1));
+
+        // Splitting a paragraph converts each of the new parts.
+        converter.edit(16..16, "\n\nNew.\n\n");
+        assert_eq!(
+            converter.markdown(),
+            "# Title\n\nChanged\n\nNew.\n\n.\n\n- a\n- b\n\nLast.\n"
+        );
+        assert_eq!(converter.parts.len(), 6);
+        assert_eq!(converter.converted_parts, Some(3));
+
+        converter.edit(0..0, "[x]: https://example.com\n\n");
+        assert_eq!(converter.converted_parts, None);
+    }
+}
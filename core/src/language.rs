@@ -0,0 +1,216 @@
+//! Document languages, their LaTeX packages, and detection from text statistics.
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::str::FromStr;
+
+/// A document language, used for hyphenation and quotation marks.
+///
+/// See [`ConversionOptions::language`].
+///
+/// [`ConversionOptions::language`]: ../struct.ConversionOptions.html#structfield.language
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Dutch,
+    Portuguese,
+}
+
+/// Frequent short words of each language, used by [`Language::detect`].
+///
+/// [`Language::detect`]: enum.Language.html#method.detect
+const STOPWORDS: [(Language, &[&str]); 7] = [
+    (
+        Language::English,
+        &[
+            "the", "and", "of", "to", "is", "in", "that", "it", "for", "with", "are", "this", "be",
+            "on", "not", "as", "we", "which", "by",
+        ],
+    ),
+    (
+        Language::German,
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "mit", "den", "dem", "von",
+            "zu", "sich", "auch", "auf", "für", "wir", "werden", "es",
+        ],
+    ),
+    (
+        Language::French,
+        &[
+            "le", "la", "les", "et", "est", "des", "une", "un", "du", "que", "qui", "pour", "dans",
+            "pas", "sur", "avec", "nous", "ce", "au",
+        ],
+    ),
+    (
+        Language::Spanish,
+        &[
+            "el", "la", "los", "las", "y", "es", "que", "de", "en", "un", "una", "por", "para",
+            "con", "no", "se", "del", "como",
+        ],
+    ),
+    (
+        Language::Italian,
+        &[
+            "il", "lo", "la", "gli", "le", "e", "è", "che", "di", "un", "una", "per", "con", "non",
+            "del", "della", "sono", "si",
+        ],
+    ),
+    (
+        Language::Dutch,
+        &[
+            "de", "het", "een", "en", "is", "van", "dat", "niet", "op", "met", "zijn", "voor",
+            "te", "er", "ook", "wij",
+        ],
+    ),
+    (
+        Language::Portuguese,
+        &[
+            "o", "os", "a", "as", "e", "é", "que", "de", "um", "uma", "para", "com", "não", "do",
+            "da", "em", "se",
+        ],
+    ),
+];
+
+/// Minimal number of stopwords in a document for [`Language::detect`] to be confident.
+///
+/// [`Language::detect`]: enum.Language.html#method.detect
+const MIN_STOPWORDS: usize = 5;
+
+/// The number of stopwords of each language in `STOPWORDS` that occur in a text.
+///
+/// Counts add up, so the counts of a document are the sums of the counts of its
+/// top-level blocks.
+pub(crate) type StopwordCounts = [usize; STOPWORDS.len()];
+
+/// Counts the stopwords in the text of `markdown`, ignoring code and math (if math
+/// has already been replaced by code spans).
+pub(crate) fn count_stopwords(markdown: &str, parser_options: Options) -> StopwordCounts {
+    let mut counts = [0usize; STOPWORDS.len()];
+    let mut in_code_block = false;
+    for event in Parser::new_ext(markdown, parser_options) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Text(text) if !in_code_block => {
+                for word in text.split(|c: char| !c.is_alphabetic()) {
+                    if word.is_empty() {
+                        continue;
+                    }
+                    let word = word.to_lowercase();
+                    for (count, (_, stopwords)) in counts.iter_mut().zip(STOPWORDS.iter()) {
+                        if stopwords.contains(&word.as_str()) {
+                            *count += 1;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    counts
+}
+
+impl Language {
+    /// Detects the language of the text in `markdown` from the frequencies of common
+    /// short words.
+    ///
+    /// Ignores code and math (if math has already been replaced by code spans).
+    /// Returns `None` if the text is too short or ambiguous.
+    pub fn detect(markdown: &str, parser_options: Options) -> Option<Self> {
+        Self::from_stopword_counts(&count_stopwords(markdown, parser_options))
+    }
+
+    /// Decides on a language given the stopword counts of a whole document (see
+    /// [`detect`](#method.detect)).
+    pub(crate) fn from_stopword_counts(counts: &StopwordCounts) -> Option<Self> {
+        let (best, &count) = counts.iter().enumerate().max_by_key(|&(_, count)| count)?;
+        let runner_up = counts
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| index != best)
+            .map(|(_, &count)| count)
+            .max()
+            .unwrap_or(0);
+        if count >= MIN_STOPWORDS && 4 * count > 5 * runner_up {
+            Some(STOPWORDS[best].0)
+        } else {
+            None
+        }
+    }
+
+    /// The option for the `babel` package (used with pdfLaTeX).
+    pub fn babel_name(self) -> &'static str {
+        match self {
+            Language::English => "english",
+            Language::German => "ngerman",
+            Language::French => "french",
+            Language::Spanish => "spanish",
+            Language::Italian => "italian",
+            Language::Dutch => "dutch",
+            Language::Portuguese => "portuguese",
+        }
+    }
+
+    /// The argument of `\setmainlanguage` from the `polyglossia` package (used with
+    /// XeLaTeX and LuaLaTeX).
+    pub fn polyglossia_name(self) -> &'static str {
+        match self {
+            Language::German => "german",
+            _ => self.babel_name(),
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    /// Parses a language name such as `"german"` or an ISO 639-1 code such as `"de"`
+    /// (case insensitive).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "english" | "en" => Ok(Language::English),
+            "german" | "ngerman" | "deutsch" | "de" => Ok(Language::German),
+            "french" | "français" | "fr" => Ok(Language::French),
+            "spanish" | "español" | "es" => Ok(Language::Spanish),
+            "italian" | "italiano" | "it" => Ok(Language::Italian),
+            "dutch" | "nederlands" | "nl" => Ok(Language::Dutch),
+            "portuguese" | "português" | "pt" => Ok(Language::Portuguese),
+            _ => Err(format!(
+                "Unknown language \"{}\". Expected one of \"english\", \"german\", \
+                 \"french\", \"spanish\", \"italian\", \"dutch\", or \"portuguese\".",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        let detect = |markdown| Language::detect(markdown, Options::empty());
+
+        assert_eq!(
+            detect(
+                "# Einleitung\n\nDas ist ein Text, der nicht auf Englisch ist. Wir \
+                    schreiben ihn mit `der` und `the` im Code:\n\n    the the the the the\n"
+            ),
+            Some(Language::German)
+        );
+        assert_eq!(
+            detect("This is a text that is written in English for the tests of the module."),
+            Some(Language::English)
+        );
+        assert_eq!(
+            detect("Este es un texto en español para las pruebas del módulo."),
+            Some(Language::Spanish)
+        );
+        assert_eq!(detect("Too short to tell."), None);
+        assert_eq!("DE".parse(), Ok(Language::German));
+    }
+}
@@ -1,14 +1,9 @@
 //! Utility for few-character level replacements in LaTeX output.
 
-use std::io::Write;
+use crate::UnicodeFallback;
 
-const UTF8_NB_SPACE_1: u8 = 0xC2;
-const UTF8_NB_SPACE_2: u8 = 0xA0;
-const UTF8_PUNCTUATION_1: u8 = 0xE2; // first byte of "en dash", "em dash", and "ellipsis"
-const UTF8_PUNCTUATION_2: u8 = 0x80; // second byte of "en dash", "em dash", and "ellipsis"
-const UTF8_EN_DASH_3: u8 = 0x93;
-const UTF8_EM_DASH_3: u8 = 0x94;
-const UTF8_ELLIPSIS_3: u8 = 0xA6;
+use log::warn;
+use std::io::Write;
 
 /// Writes a string of text so that it can be embedded in LaTeX code.
 ///
@@ -19,62 +14,585 @@ const UTF8_ELLIPSIS_3: u8 = 0xA6;
 ///   en-dash (`–`), em-dash (`—`), and ellipsis (`…` and `...`) with their
 ///   corresponding LaTeX commands.
 ///
-/// The replacement rules in neither group are exhaustive, so this method should not
-/// be used to escape untrusted text in a safety critical environment. Suggestions
-/// for additional replacement rules are welcome.
+/// Other non-ASCII characters such as "≤", "→", or "α" are written unchanged. To
+/// replace them with LaTeX commands (e.g., for pdfLaTeX), use
+/// [`escape_str_with`](fn.escape_str_with.html).
+///
+/// All characters with a special meaning in LaTeX are escaped, so the output can't
+/// execute any TeX code (see also
+/// [`ConversionOptions::safe_mode`](../struct.ConversionOptions.html#structfield.safe_mode)
+/// for converting untrusted documents). The rules in the second group are not
+/// exhaustive, and suggestions for additional replacement rules are welcome.
+///
+/// To escape text that arrives in several pieces, use a
+/// [`LatexEscaper`](struct.LatexEscaper.html) instead.
 pub fn escape_str(s: &str, writer: &mut impl Write) -> std::io::Result<()> {
-    unsafe {
+    escape_str_with(s, None, writer)
+}
+
+/// Like [`escape_str`](fn.escape_str.html) but also replaces non-ASCII characters
+/// for which there is a LaTeX replacement, keeping all others.
+pub(crate) fn escape_str_and_unicode(s: &str, writer: &mut impl Write) -> std::io::Result<()> {
+    escape_str_with(s, Some(&UnicodeFallback::Keep), writer)
+}
+
+/// Like [`escape_str`](fn.escape_str.html) but with control over non-ASCII characters.
+///
+/// If `unicode` is `Some(fallback)` then non-ASCII characters for which there is a
+/// LaTeX replacement (e.g., `\ensuremath{\leq}` for "≤") are replaced by it.
+/// Characters from the Latin-1 Supplement and Latin Extended-A blocks (e.g., "é")
+/// are written unchanged because `inputenc` supports them. All other characters are
+/// handled according to `fallback`. If `unicode` is `None` then only nonbreaking
+/// spaces, dashes, and ellipses are replaced (e.g., for XeLaTeX or LuaLaTeX, which
+/// support Unicode input natively).
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::latex_escape::escape_str_with;
+/// use bluepaper_core::UnicodeFallback;
+///
+/// let fallback = UnicodeFallback::Replace("?".to_string());
+/// let mut latex = Vec::new();
+/// escape_str_with("α ≤ β → café 🙂", Some(&fallback), &mut latex).unwrap();
+/// assert_eq!(
+///     String::from_utf8(latex).unwrap(),
+///     r"\ensuremath{\alpha} \ensuremath{\leq} \ensuremath{\beta} \ensuremath{\rightarrow} café ?"
+/// );
+/// ```
+pub fn escape_str_with(
+    s: &str,
+    unicode: Option<&UnicodeFallback>,
+    writer: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut escaper = LatexEscaper::with_unicode(writer, unicode.cloned());
+    escaper.write_all(s.as_bytes())?;
+    escaper.finish()
+}
+
+/// Writes a URL so that it can be used as the first argument of `\href`.
+///
+/// Escapes `#` and `%` so that the URL also works in the argument of another command
+/// (e.g., in a section title), and percent-encodes characters that are not allowed
+/// in URLs or that could be interpreted as TeX code (e.g., `\`, `{`, or `^`).
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::latex_escape::escape_url;
+///
+/// let mut latex = Vec::new();
+/// escape_url("https://example.com/a b?q=50%#{x}", &mut latex).unwrap();
+/// assert_eq!(latex, br"https://example.com/a\%20b?q=50\%\#\%7Bx\%7D");
+/// ```
+pub fn escape_url(url: &str, writer: &mut impl Write) -> std::io::Result<()> {
+    let bytes = url.as_bytes();
+    let mut unchanged = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        let safe = b.is_ascii_alphanumeric() || b"-._:/?[]@!$&'()*+,;=".contains(&b);
+        if !safe {
+            writer.write_all(&bytes[unchanged..i])?;
+            match b {
+                b'#' => writer.write_all(br"\#")?,
+                b'%' => writer.write_all(br"\%")?,
+                _ => write!(writer, r"\%{:02X}", b)?,
+            }
+            unchanged = i + 1;
+        }
+    }
+    writer.write_all(&bytes[unchanged..])
+}
+
+/// Checks if `key` can be used as a citation key or label without escaping.
+///
+/// Allows only ASCII letters, digits, and the characters `-._:/`, which can't be
+/// interpreted as TeX code.
+pub fn is_safe_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._:/".contains(&b))
+}
+
+/// Checks if `path` can be used as a file name (e.g., in `\includegraphics`) without
+/// escaping.
+///
+/// Allows only ASCII letters, digits, the characters `-._/`, and non-ASCII characters,
+/// none of which can be interpreted as TeX code (in contrast to, e.g., `{`, `^`, or a
+/// line break).
+pub fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && path
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._/".contains(&b) || !b.is_ascii())
+}
+
+/// A writer adapter that escapes all text that is written to it.
+///
+/// Performs the same replacements as [`escape_str`](fn.escape_str.html) (or
+/// [`escape_str_with`](fn.escape_str_with.html)), but keeps context across calls to
+/// `write`. Thus, an ellipsis `...` is recognized and runs of spaces are collapsed
+/// even if they are split across several writes, and so are UTF-8 encoded
+/// characters.
+///
+/// Call [`finish`](#method.finish) (or `flush`) at the end of a run of text, i.e.,
+/// before writing anything to the wrapped writer directly (e.g., via
+/// [`get_mut`](#method.get_mut)).
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::latex_escape::LatexEscaper;
+/// use std::io::Write;
+///
+/// let mut escaper = LatexEscaper::new(Vec::new());
+/// escaper.write_all(b"Wait.")?;
+/// escaper.write_all(b".. 50% ")?;
+/// escaper.write_all(b" off!")?;
+/// escaper.finish()?;
+/// escaper.get_mut().extend_from_slice(br"\\");
+/// escaper.write_all(b"Next line.")?;
+///
+/// assert_eq!(escaper.into_inner()?, br"Wait\ldots{} 50\% off!\\Next line.");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct LatexEscaper<W: Write> {
+    inner: W,
+    unicode: Option<UnicodeFallback>,
+
+    /// Number of dots at the end of the text that haven't been written yet.
+    dots: usize,
+
+    /// Whether the last written character was a space.
+    space: bool,
+
+    /// Incomplete UTF-8 encoded character at the end of the text.
+    partial: Vec<u8>,
+}
+
+impl<W: Write> LatexEscaper<W> {
+    /// Creates an escaper that performs the replacements of
+    /// [`escape_str`](fn.escape_str.html).
+    pub fn new(inner: W) -> Self {
+        Self::with_unicode(inner, None)
+    }
+
+    /// Creates an escaper that performs the replacements of
+    /// [`escape_str_with`](fn.escape_str_with.html).
+    pub fn with_unicode(inner: W, unicode: Option<UnicodeFallback>) -> Self {
+        Self {
+            inner,
+            unicode,
+            dots: 0,
+            space: false,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Ends the current run of text.
+    ///
+    /// Writes any pending output (e.g., the dots of "..", which might have turned out
+    /// to be the beginning of an ellipsis) and resets the context. Returns an error
+    /// if the text ends with an incomplete UTF-8 encoded character.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.space = false;
+        let dots = std::mem::take(&mut self.dots);
+        self.write_inner(&b".."[..dots])?;
+        if self.partial.is_empty() {
+            Ok(())
+        } else {
+            self.partial.clear();
+            Err(invalid_utf8())
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped writer.
+    ///
+    /// Call [`finish`](#method.finish) before writing to it.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Ends the current run of text and returns the wrapped writer.
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        self.finish()?;
+        Ok(self.inner)
+    }
+
+    /// Writes `bytes` to the wrapped writer unless they're empty (writing nothing
+    /// would still insert pending newlines of a `WhitespaceFormatter`).
+    fn write_inner(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if bytes.is_empty() {
+            Ok(())
+        } else {
+            self.inner.write_all(bytes)
+        }
+    }
+
+    fn escape(&mut self, s: &str) -> std::io::Result<()> {
         let bytes = s.as_bytes();
-        let mut i = 0usize;
+        // Start of the bytes that are copied unchanged.
+        let mut unchanged = 0;
+        let mut i = 0;
 
         while i != bytes.len() {
-            match *bytes.get_unchecked(i) {
-                b'&' => writer.write_all(br"\&")?,
-                b'%' => writer.write_all(br"\%")?,
-                b'$' => writer.write_all(br"\$")?,
-                b'#' => writer.write_all(br"\#")?,
-                b'_' => writer.write_all(br"\_")?,
-                b'{' => writer.write_all(br"\{")?,
-                b'}' => writer.write_all(br"\}")?,
-                b'~' => writer.write_all(br"\textasciitilde{}")?,
-                b'^' => writer.write_all(br"\textasciicircum{}")?,
-                b'\\' => writer.write_all(br"\textbackslash{}")?,
-                b'.' if i + 2 < bytes.len()
-                    && *bytes.get_unchecked(i + 1) == b'.'
-                    && *bytes.get_unchecked(i + 2) == b'.' =>
-                {
-                    writer.write_all(br"\ldots{}")?;
-                    i += 2;
-                }
-                b' ' if i + 1 < bytes.len() && *bytes.get_unchecked(i + 1) == b' ' => {
-                    // Ignore space if another space follows.
+            let b = bytes[i];
+            if self.dots != 0 && b != b'.' {
+                let dots = std::mem::take(&mut self.dots);
+                self.write_inner(&b".."[..dots])?;
+            }
+            if b == b' ' {
+                if self.space {
+                    // Collapse runs of spaces.
+                    self.write_inner(&bytes[unchanged..i])?;
+                    unchanged = i + 1;
                 }
-                UTF8_NB_SPACE_1 if *bytes.get_unchecked(i + 1) == UTF8_NB_SPACE_2 => {
-                    // Accessing the next byte is save because the input text is
-                    // guaranteed to be valid UTF-8.
-                    writer.write_all(br"~")?;
-                    i += 1;
+                self.space = true;
+                i += 1;
+                continue;
+            }
+            self.space = false;
+
+            let (len, replacement): (usize, &[u8]) = match b {
+                b'&' => (1, br"\&"),
+                b'%' => (1, br"\%"),
+                b'$' => (1, br"\$"),
+                b'#' => (1, br"\#"),
+                b'_' => (1, br"\_"),
+                b'{' => (1, br"\{"),
+                b'}' => (1, br"\}"),
+                b'~' => (1, br"\textasciitilde{}"),
+                b'^' => (1, br"\textasciicircum{}"),
+                b'\\' => (1, br"\textbackslash{}"),
+                b'.' => {
+                    self.dots += 1;
+                    if self.dots == 3 {
+                        self.dots = 0;
+                        (1, br"\ldots{}")
+                    } else {
+                        (1, b"")
+                    }
                 }
-                UTF8_PUNCTUATION_1 if *bytes.get_unchecked(i + 1) == UTF8_PUNCTUATION_2 => {
-                    // Accessing the next two bytes is save because the input text is
-                    // guaranteed to be valid UTF-8.
-                    match *bytes.get_unchecked(i + 2) {
-                        UTF8_EN_DASH_3 => writer.write_all(br"--")?,
-                        UTF8_EM_DASH_3 => writer.write_all(br"---")?,
-                        UTF8_ELLIPSIS_3 => writer.write_all(br"\ldots{}")?,
-                        _ => writer.write_all(bytes.get_unchecked(i..(i + 3)))?,
+                b if b >= 0x80 => {
+                    let c = s[i..].chars().next().expect("`i` is at a char boundary");
+                    match c {
+                        '\u{A0}' => (2, b"~"),
+                        '\u{2013}' => (3, b"--"),
+                        '\u{2014}' => (3, b"---"),
+                        '\u{2026}' => (3, br"\ldots{}"),
+                        _ if self.unicode.is_none() => {
+                            i += c.len_utf8();
+                            continue;
+                        }
+                        _ => {
+                            self.write_inner(&bytes[unchanged..i])?;
+                            write_char(c, self.unicode.as_ref(), &mut self.inner)?;
+                            i += c.len_utf8();
+                            unchanged = i;
+                            continue;
+                        }
                     }
-                    i += 2;
                 }
-                b => writer.write_all(&[b])?,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            self.write_inner(&bytes[unchanged..i])?;
+            self.write_inner(replacement)?;
+            i += len;
+            unchanged = i;
+        }
+
+        self.write_inner(&bytes[unchanged..])
+    }
+}
+
+impl<W: Write> Write for LatexEscaper<W> {
+    /// Escapes and writes all of `buf`, which has to be UTF-8 encoded text, except
+    /// that it may start or end in the middle of a character.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        if let Some(&first) = self.partial.first() {
+            // Complete the character that was split across writes.
+            let len = match first {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                _ => 4,
+            };
+            let missing = std::cmp::min(len - self.partial.len(), rest.len());
+            self.partial.extend_from_slice(&rest[..missing]);
+            rest = &rest[missing..];
+            if self.partial.len() < len {
+                return Ok(buf.len());
             }
-            i += 1;
+            let partial = std::mem::take(&mut self.partial);
+            self.escape(std::str::from_utf8(&partial).map_err(|_| invalid_utf8())?)?;
         }
 
-        Ok(())
+        let text = match std::str::from_utf8(rest) {
+            Ok(text) => text,
+            Err(err) if err.error_len().is_none() => {
+                let (text, partial) = rest.split_at(err.valid_up_to());
+                self.partial.extend_from_slice(partial);
+                std::str::from_utf8(text).expect("valid up to here")
+            }
+            Err(_) => return Err(invalid_utf8()),
+        };
+        self.escape(text)?;
+        Ok(buf.len())
+    }
+
+    /// Ends the current run of text (see [`finish`](#method.finish)) and flushes the
+    /// wrapped writer.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.finish()?;
+        self.inner.flush()
+    }
+}
+
+fn invalid_utf8() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "LaTeX escaper received invalid UTF-8",
+    )
+}
+
+fn write_char(
+    c: char,
+    unicode: Option<&UnicodeFallback>,
+    writer: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut buf = [0; 4];
+    let verbatim = c.encode_utf8(&mut buf).as_bytes();
+    let fallback = match unicode {
+        Some(fallback) => fallback,
+        None => return writer.write_all(verbatim),
+    };
+
+    if let Ok(index) = UNICODE_TO_LATEX.binary_search_by_key(&c, |&(c, _)| c) {
+        writer.write_all(UNICODE_TO_LATEX[index].1.as_bytes())
+    } else if (c as u32) < 0x180 {
+        // Latin-1 Supplement and Latin Extended-A.
+        writer.write_all(verbatim)
+    } else {
+        match fallback {
+            UnicodeFallback::Keep => writer.write_all(verbatim),
+            UnicodeFallback::Replace(replacement) => writer.write_all(replacement.as_bytes()),
+            UnicodeFallback::Warn => {
+                warn!(
+                    "No LaTeX replacement for character \"{}\" (U+{:04X}), pdfLaTeX may \
+                     not be able to typeset it.",
+                    c, c as u32
+                );
+                writer.write_all(verbatim)
+            }
+        }
     }
 }
 
+/// LaTeX replacements for non-ASCII characters, sorted by character.
+///
+/// Nonbreaking spaces, en and em dashes, and ellipses are handled separately because
+/// they are replaced even if Unicode escaping is turned off.
+const UNICODE_TO_LATEX: &[(char, &str)] = &[
+    ('\u{A7}', r"\S{}"),
+    ('\u{A9}', r"\copyright{}"),
+    ('\u{AC}', r"\ensuremath{\neg}"),
+    ('\u{AD}', r"\-"),
+    ('\u{AE}', r"\textregistered{}"),
+    ('\u{B1}', r"\ensuremath{\pm}"),
+    ('\u{B5}', r"\ensuremath{\mu}"),
+    ('\u{B6}', r"\P{}"),
+    ('\u{B7}', r"\textperiodcentered{}"),
+    ('\u{D7}', r"\ensuremath{\times}"),
+    ('\u{F7}', r"\ensuremath{\div}"),
+    // Greek
+    ('\u{391}', "A"),
+    ('\u{392}', "B"),
+    ('\u{393}', r"\ensuremath{\Gamma}"),
+    ('\u{394}', r"\ensuremath{\Delta}"),
+    ('\u{395}', "E"),
+    ('\u{396}', "Z"),
+    ('\u{397}', "H"),
+    ('\u{398}', r"\ensuremath{\Theta}"),
+    ('\u{399}', "I"),
+    ('\u{39A}', "K"),
+    ('\u{39B}', r"\ensuremath{\Lambda}"),
+    ('\u{39C}', "M"),
+    ('\u{39D}', "N"),
+    ('\u{39E}', r"\ensuremath{\Xi}"),
+    ('\u{39F}', "O"),
+    ('\u{3A0}', r"\ensuremath{\Pi}"),
+    ('\u{3A1}', "P"),
+    ('\u{3A3}', r"\ensuremath{\Sigma}"),
+    ('\u{3A4}', "T"),
+    ('\u{3A5}', r"\ensuremath{\Upsilon}"),
+    ('\u{3A6}', r"\ensuremath{\Phi}"),
+    ('\u{3A7}', "X"),
+    ('\u{3A8}', r"\ensuremath{\Psi}"),
+    ('\u{3A9}', r"\ensuremath{\Omega}"),
+    ('\u{3B1}', r"\ensuremath{\alpha}"),
+    ('\u{3B2}', r"\ensuremath{\beta}"),
+    ('\u{3B3}', r"\ensuremath{\gamma}"),
+    ('\u{3B4}', r"\ensuremath{\delta}"),
+    ('\u{3B5}', r"\ensuremath{\varepsilon}"),
+    ('\u{3B6}', r"\ensuremath{\zeta}"),
+    ('\u{3B7}', r"\ensuremath{\eta}"),
+    ('\u{3B8}', r"\ensuremath{\theta}"),
+    ('\u{3B9}', r"\ensuremath{\iota}"),
+    ('\u{3BA}', r"\ensuremath{\kappa}"),
+    ('\u{3BB}', r"\ensuremath{\lambda}"),
+    ('\u{3BC}', r"\ensuremath{\mu}"),
+    ('\u{3BD}', r"\ensuremath{\nu}"),
+    ('\u{3BE}', r"\ensuremath{\xi}"),
+    ('\u{3BF}', "o"),
+    ('\u{3C0}', r"\ensuremath{\pi}"),
+    ('\u{3C1}', r"\ensuremath{\rho}"),
+    ('\u{3C2}', r"\ensuremath{\varsigma}"),
+    ('\u{3C3}', r"\ensuremath{\sigma}"),
+    ('\u{3C4}', r"\ensuremath{\tau}"),
+    ('\u{3C5}', r"\ensuremath{\upsilon}"),
+    ('\u{3C6}', r"\ensuremath{\varphi}"),
+    ('\u{3C7}', r"\ensuremath{\chi}"),
+    ('\u{3C8}', r"\ensuremath{\psi}"),
+    ('\u{3C9}', r"\ensuremath{\omega}"),
+    ('\u{3D1}', r"\ensuremath{\vartheta}"),
+    ('\u{3D5}', r"\ensuremath{\phi}"),
+    ('\u{3D6}', r"\ensuremath{\varpi}"),
+    ('\u{3F1}', r"\ensuremath{\varrho}"),
+    ('\u{3F5}', r"\ensuremath{\epsilon}"),
+    // Spaces and punctuation
+    ('\u{2002}', r"\enspace{}"),
+    ('\u{2003}', r"\quad{}"),
+    ('\u{2009}', r"\,"),
+    ('\u{200B}', r"\hspace{0pt}"),
+    ('\u{2010}', "-"),
+    ('\u{2011}', r"\mbox{-}"),
+    ('\u{2012}', "--"),
+    ('\u{2015}', "---"),
+    ('\u{2016}', r"\ensuremath{\|}"),
+    ('\u{2018}', "`"),
+    ('\u{2019}', "'"),
+    ('\u{201A}', ","),
+    ('\u{201C}', "``"),
+    ('\u{201D}', "''"),
+    ('\u{201E}', ",,"),
+    ('\u{2020}', r"\dag{}"),
+    ('\u{2021}', r"\ddag{}"),
+    ('\u{2022}', r"\textbullet{}"),
+    ('\u{202F}', r"\,"),
+    ('\u{2032}', r"\ensuremath{\prime}"),
+    ('\u{2033}', r"\ensuremath{\prime\prime}"),
+    // Letterlike symbols
+    ('\u{2102}', r"\ensuremath{\mathbb{C}}"),
+    ('\u{210F}', r"\ensuremath{\hbar}"),
+    ('\u{2113}', r"\ensuremath{\ell}"),
+    ('\u{2115}', r"\ensuremath{\mathbb{N}}"),
+    ('\u{211A}', r"\ensuremath{\mathbb{Q}}"),
+    ('\u{211D}', r"\ensuremath{\mathbb{R}}"),
+    ('\u{2122}', r"\texttrademark{}"),
+    ('\u{2124}', r"\ensuremath{\mathbb{Z}}"),
+    ('\u{2135}', r"\ensuremath{\aleph}"),
+    // Arrows
+    ('\u{2190}', r"\ensuremath{\leftarrow}"),
+    ('\u{2191}', r"\ensuremath{\uparrow}"),
+    ('\u{2192}', r"\ensuremath{\rightarrow}"),
+    ('\u{2193}', r"\ensuremath{\downarrow}"),
+    ('\u{2194}', r"\ensuremath{\leftrightarrow}"),
+    ('\u{2195}', r"\ensuremath{\updownarrow}"),
+    ('\u{2196}', r"\ensuremath{\nwarrow}"),
+    ('\u{2197}', r"\ensuremath{\nearrow}"),
+    ('\u{2198}', r"\ensuremath{\searrow}"),
+    ('\u{2199}', r"\ensuremath{\swarrow}"),
+    ('\u{21A6}', r"\ensuremath{\mapsto}"),
+    ('\u{21A9}', r"\ensuremath{\hookleftarrow}"),
+    ('\u{21AA}', r"\ensuremath{\hookrightarrow}"),
+    ('\u{21D0}', r"\ensuremath{\Leftarrow}"),
+    ('\u{21D1}', r"\ensuremath{\Uparrow}"),
+    ('\u{21D2}', r"\ensuremath{\Rightarrow}"),
+    ('\u{21D3}', r"\ensuremath{\Downarrow}"),
+    ('\u{21D4}', r"\ensuremath{\Leftrightarrow}"),
+    // Mathematical operators
+    ('\u{2200}', r"\ensuremath{\forall}"),
+    ('\u{2202}', r"\ensuremath{\partial}"),
+    ('\u{2203}', r"\ensuremath{\exists}"),
+    ('\u{2204}', r"\ensuremath{\nexists}"),
+    ('\u{2205}', r"\ensuremath{\emptyset}"),
+    ('\u{2207}', r"\ensuremath{\nabla}"),
+    ('\u{2208}', r"\ensuremath{\in}"),
+    ('\u{2209}', r"\ensuremath{\notin}"),
+    ('\u{220B}', r"\ensuremath{\ni}"),
+    ('\u{220F}', r"\ensuremath{\prod}"),
+    ('\u{2211}', r"\ensuremath{\sum}"),
+    ('\u{2212}', r"\ensuremath{-}"),
+    ('\u{2213}', r"\ensuremath{\mp}"),
+    ('\u{2218}', r"\ensuremath{\circ}"),
+    ('\u{2219}', r"\ensuremath{\bullet}"),
+    ('\u{221A}', r"\ensuremath{\surd}"),
+    ('\u{221D}', r"\ensuremath{\propto}"),
+    ('\u{221E}', r"\ensuremath{\infty}"),
+    ('\u{2220}', r"\ensuremath{\angle}"),
+    ('\u{2223}', r"\ensuremath{\mid}"),
+    ('\u{2225}', r"\ensuremath{\parallel}"),
+    ('\u{2227}', r"\ensuremath{\wedge}"),
+    ('\u{2228}', r"\ensuremath{\vee}"),
+    ('\u{2229}', r"\ensuremath{\cap}"),
+    ('\u{222A}', r"\ensuremath{\cup}"),
+    ('\u{222B}', r"\ensuremath{\int}"),
+    ('\u{222E}', r"\ensuremath{\oint}"),
+    ('\u{2234}', r"\ensuremath{\therefore}"),
+    ('\u{2235}', r"\ensuremath{\because}"),
+    ('\u{223C}', r"\ensuremath{\sim}"),
+    ('\u{2243}', r"\ensuremath{\simeq}"),
+    ('\u{2245}', r"\ensuremath{\cong}"),
+    ('\u{2248}', r"\ensuremath{\approx}"),
+    ('\u{2260}', r"\ensuremath{\neq}"),
+    ('\u{2261}', r"\ensuremath{\equiv}"),
+    ('\u{2264}', r"\ensuremath{\leq}"),
+    ('\u{2265}', r"\ensuremath{\geq}"),
+    ('\u{226A}', r"\ensuremath{\ll}"),
+    ('\u{226B}', r"\ensuremath{\gg}"),
+    ('\u{2282}', r"\ensuremath{\subset}"),
+    ('\u{2283}', r"\ensuremath{\supset}"),
+    ('\u{2286}', r"\ensuremath{\subseteq}"),
+    ('\u{2287}', r"\ensuremath{\supseteq}"),
+    ('\u{2295}', r"\ensuremath{\oplus}"),
+    ('\u{2297}', r"\ensuremath{\otimes}"),
+    ('\u{22A2}', r"\ensuremath{\vdash}"),
+    ('\u{22A4}', r"\ensuremath{\top}"),
+    ('\u{22A5}', r"\ensuremath{\perp}"),
+    ('\u{22C5}', r"\ensuremath{\cdot}"),
+    ('\u{22EF}', r"\ensuremath{\cdots}"),
+    ('\u{2308}', r"\ensuremath{\lceil}"),
+    ('\u{2309}', r"\ensuremath{\rceil}"),
+    ('\u{230A}', r"\ensuremath{\lfloor}"),
+    ('\u{230B}', r"\ensuremath{\rfloor}"),
+    // Miscellaneous symbols and dingbats
+    ('\u{2605}', r"\ensuremath{\bigstar}"),
+    ('\u{2610}', r"\ensuremath{\square}"),
+    ('\u{2611}', r"\checkedbox{}"),
+    ('\u{2705}', r"\checkedbox{}"),
+    ('\u{2713}', r"\ensuremath{\checkmark}"),
+    ('\u{2714}', r"\ensuremath{\checkmark}"),
+    ('\u{2717}', r"\ensuremath{\times}"),
+    ('\u{2718}', r"\ensuremath{\times}"),
+    ('\u{27E8}', r"\ensuremath{\langle}"),
+    ('\u{27E9}', r"\ensuremath{\rangle}"),
+    ('\u{27F5}', r"\ensuremath{\longleftarrow}"),
+    ('\u{27F6}', r"\ensuremath{\longrightarrow}"),
+    ('\u{27F7}', r"\ensuremath{\longleftrightarrow}"),
+    ('\u{27F8}', r"\ensuremath{\Longleftarrow}"),
+    ('\u{27F9}', r"\ensuremath{\Longrightarrow}"),
+    ('\u{27FA}', r"\ensuremath{\Longleftrightarrow}"),
+];
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -94,6 +612,80 @@ mod test {
             "test \\ldots{} ellipsis \\ldots{}, 2 dots .., \
              hyphenation\u{2027}point, ---em dash and--en dash"
         );
+        assert_eq!(&escape_to_string("x ≤ α"), "x ≤ α");
+    }
+
+    #[test]
+    fn unicode_table() {
+        assert!(UNICODE_TO_LATEX.windows(2).all(|w| w[0].0 < w[1].0));
+
+        let mut result = Vec::new();
+        let src = "“x ≥ 0” ⇒ ∀ε ∃δ ✓ — naïve ‐ ☃";
+        escape_str_with(src, Some(&UnicodeFallback::Keep), &mut result).unwrap();
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            "``x \\ensuremath{\\geq} 0'' \\ensuremath{\\Rightarrow} \\ensuremath{\\forall}\\ensuremath{\\varepsilon} \
+             \\ensuremath{\\exists}\\ensuremath{\\delta} \\ensuremath{\\checkmark} --- naïve - ☃"
+        );
+
+        let mut result = Vec::new();
+        escape_str_with(src, None, &mut result).unwrap();
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            "“x ≥ 0” ⇒ ∀ε ∃δ ✓ --- naïve ‐ ☃"
+        );
+    }
+
+    #[test]
+    fn escaper() {
+        let mut escaper = LatexEscaper::new(Vec::new());
+        let en_dash = "\u{2013}".as_bytes();
+        let pieces: [&[u8]; 8] = [
+            b"a ",
+            b" b.",
+            b".",
+            b".",
+            b" c.",
+            b".",
+            &en_dash[..1],
+            &en_dash[1..],
+        ];
+        for piece in &pieces {
+            escaper.write_all(piece).unwrap();
+        }
+        escaper.finish().unwrap();
+        escaper.write_all(b" d \xE2").unwrap();
+        assert!(escaper.finish().is_err());
+        assert!(escaper.write_all(b"\xFF").is_err());
+        assert_eq!(
+            String::from_utf8(escaper.into_inner().unwrap()).unwrap(),
+            r"a b\ldots{} c..-- d "
+        );
+    }
+
+    #[test]
+    fn bulk_writes() {
+        /// Counts the calls of `write`.
+        struct CountingWriter(usize);
+
+        impl Write for CountingWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0 += 1;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut writer = CountingWriter(0);
+        escape_str("A run of text without special characters", &mut writer).unwrap();
+        assert_eq!(writer.0, 1);
+
+        let mut writer = CountingWriter(0);
+        escape_str("Two runs & one replacement", &mut writer).unwrap();
+        assert_eq!(writer.0, 3);
     }
 
     #[test]
@@ -7,19 +7,61 @@
 //!
 //! The main struct in this library is
 //! [`MarkdownToLatex`](struct.MarkdownToLatex.html).
+//!
+//! This crate contains no unsafe code. The vendored `pulldown-cmark` crate uses
+//! unsafe code only in its optional `simd` feature, which isn't enabled.
+
+#![forbid(unsafe_code)]
 
+pub mod bibliography;
+mod callout;
+mod citation;
+mod description;
+pub mod document;
 pub mod format;
+mod html;
+mod incremental;
+pub mod language;
 pub mod latex_escape;
+mod lists;
+pub mod math;
+pub mod metadata;
+mod options;
+mod preamble;
 mod preprocess;
+mod quotes;
+mod tasks;
+mod theorem;
+
+pub use incremental::IncrementalConverter;
+pub use language::Language;
+pub use options::{
+    CalloutStyle, ConversionOptions, Engine, InputDialect, MarkdownExtensions, MathDelimiters,
+    SectionNames, SoftBreak, UnicodeFallback,
+};
 
+use bibliography::{Bibliography, CitationId, MetadataSource, NoMetadata, BIBTEX_FILE_NAME};
+use callout::{find_callouts, Callout};
+use citation::{find_citations, write_citation, Citation};
+use description::{find_bold_terms, replace_definition_lists, DescriptionItem};
+use document::{Document, LinkKind, Node, NodeKind, Visit};
 use format::WhitespaceFormatter;
-use latex_escape::escape_str;
-use preprocess::Replacer;
+use html::HtmlTranslator;
+use latex_escape::{escape_url, is_safe_key, is_safe_path, LatexEscaper};
+use lists::{replace_list_markers, ListMarker, ListNesting};
+use metadata::{extract_front_matter, DocumentMetadata};
+use options::SectionKind;
+use preamble::Preamble;
+use preprocess::{strip_heading_attributes, text_runs, Replacer};
+use quotes::{find_quotes, Quote, QuoteWriter};
+use tasks::{find_task_items, TaskItem};
+use theorem::{find_references, find_theorems, TheoremHead, TheoremReference, THEOREM_KINDS};
 
-use log::{info, warn};
+use log::warn;
 use std::io::prelude::*;
+use std::ops::Range;
 
-use pulldown_cmark::{Event, LinkType, Options, Parser, Tag};
+use pulldown_cmark::{Event, Parser, Tag};
 
 const HEADINGS: [(&[u8], u32); 4] = [
     (br"\section{", 2),
@@ -70,9 +112,48 @@ const LOWER_ROMAN: [&str; MAX_ENUMERATE_NESTING as usize] = ["i", "ii", "iii", "
 ///     .is_some());
 /// assert_eq!(&latex[latex.len() - 15..], "\\end{document}\n");
 /// ```
+///
+/// # Memory Usage
+///
+/// The converter keeps one copy of the markdown in memory, which it preprocesses in
+/// place (only [definition lists](struct.MarkdownExtensions.html#structfield.definition_lists)
+/// temporarily need a second copy). In addition, it keeps some bookkeeping data
+/// whose size is proportional to the number of math spans, citations, headings,
+/// and similar elements. The LaTeX output is streamed to the writer passed to
+/// [`write_to`](#method.write_to) and never held in memory as a whole (unless you
+/// call [`into_string`](#method.into_string)).
+///
+/// The peak memory usage is dominated by the markdown parser. The converter makes
+/// several passes over the document, one after another, and each pass builds a
+/// tree of all blocks and inline elements of the whole document. The last pass
+/// turns this tree into a [`Document`](document/struct.Document.html) model, which
+/// owns a copy of all text, for the LaTeX writer. For a math-heavy 10 MB document,
+/// the peak heap usage is about 17 times the size of the input (measured by
+/// `cargo bench --bench memory`).
+///
+/// The markdown can't be processed in independent chunks because parts of a
+/// document affect the output of earlier parts: the preamble depends on the
+/// packages that the whole document uses, and link reference definitions,
+/// footnotes, theorems, and citations may appear after they are referenced.
 pub struct MarkdownToLatex {
     preprocessed: String,
     replacer: Replacer,
+    heading_labels: Vec<(usize, String)>,
+    annotations: Vec<(Range<usize>, Annotation)>,
+    theorems: Vec<(usize, TheoremHead)>,
+    callouts: Vec<(usize, Callout)>,
+    description_items: Vec<DescriptionItem>,
+    task_items: Vec<TaskItem>,
+    quotes: Vec<Quote>,
+    highlighting: bool,
+    list_markers: Vec<ListMarker>,
+    list_nesting: ListNesting,
+    /// Use `enumitem` for ordered lists even if this document doesn't need it (when
+    /// it is a part of a larger document that does).
+    force_enumitem: bool,
+    options: ConversionOptions,
+    metadata: DocumentMetadata,
+    bibliography: Bibliography,
 }
 
 impl MarkdownToLatex {
@@ -80,11 +161,328 @@ impl MarkdownToLatex {
     ///
     /// Takes ownership of `markdown` because it has to do some in-place
     /// preprocessing to handle LaTeX equations.
-    pub fn from_string(mut markdown: String) -> Self {
-        let replacer = Replacer::replace(&mut markdown);
+    pub fn from_string(markdown: String) -> Self {
+        Self::with_options(markdown, ConversionOptions::default())
+    }
+
+    /// Creates a new converter that reads markdown from `reader`.
+    ///
+    /// This is only a convenience: it reads the whole input into a `String` and then
+    /// calls [`with_options`](#method.with_options). The input isn't processed in
+    /// chunks, and the memory usage is the same as for
+    /// [`from_string`](#method.from_string) (see [Memory Usage](#memory-usage)).
+    /// Returns an error of kind `InvalidData` if the input is not valid UTF-8.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let file = "# Title\n\nSome *text*.".as_bytes(); // E.g., a `std::fs::File`.
+    /// let converter = bluepaper_core::MarkdownToLatex::from_reader(file).unwrap();
+    /// assert!(converter.into_string().contains("\\maketitle\n\nSome \\emph{text}."));
+    ///
+    /// let invalid = &[b'a', 0xff][..];
+    /// assert!(bluepaper_core::MarkdownToLatex::from_reader(invalid).is_err());
+    /// ```
+    pub fn from_reader(reader: impl Read) -> std::io::Result<Self> {
+        Self::from_reader_with_options(reader, ConversionOptions::default())
+    }
+
+    /// Creates a new converter that reads markdown from `reader` with custom options.
+    ///
+    /// See [`from_reader`](#method.from_reader) and
+    /// [`with_options`](#method.with_options).
+    pub fn from_reader_with_options(
+        mut reader: impl Read,
+        options: ConversionOptions,
+    ) -> std::io::Result<Self> {
+        let mut markdown = String::new();
+        reader.read_to_string(&mut markdown)?;
+        Ok(Self::with_options(markdown, options))
+    }
+
+    /// Creates a new converter from a `String` of markdown with custom options.
+    ///
+    /// If the markdown starts with a front matter block (see module
+    /// [`metadata`](metadata/index.html)) then any option overrides in the front
+    /// matter take precedence over `options`.
+    ///
+    /// See [`ConversionOptions`](struct.ConversionOptions.html) for an example.
+    pub fn with_options(mut markdown: String, mut options: ConversionOptions) -> Self {
+        let metadata = extract_front_matter(&mut markdown, &mut options);
+        let mut converter = Self::without_front_matter(markdown, options);
+        converter.metadata = metadata;
+        converter.resolve_citations(&mut NoMetadata);
+        converter
+    }
+
+    fn without_front_matter(mut markdown: String, mut options: ConversionOptions) -> Self {
+        // Has to come first because it changes byte offsets.
+        let mut description_items = if options.extensions.definition_lists {
+            replace_definition_lists(&mut markdown)
+        } else {
+            Vec::new()
+        };
+        let mut heading_labels = if options.extensions.heading_attributes {
+            strip_heading_attributes(&mut markdown)
+        } else {
+            Vec::new()
+        };
+        if options.safe_mode {
+            heading_labels.retain(|(_, label)| {
+                let safe = is_safe_key(label);
+                if !safe {
+                    warn!("Ignoring heading label \"{}\" in safe mode.", label);
+                }
+                safe
+            });
+        }
+        let parser_options = options.extensions.parser_options();
+        let list_markers = if options.extensions.fancy_lists {
+            replace_list_markers(&mut markdown, parser_options)
+        } else {
+            Vec::new()
+        };
+        let task_items = if options.extensions.tasklists {
+            find_task_items(&markdown, parser_options)
+        } else {
+            Vec::new()
+        };
+        let replacer = Replacer::replace(&mut markdown, options.math_delimiters);
+        if options.description_lists {
+            description_items.extend(find_bold_terms(&mut markdown, parser_options));
+            description_items.sort_by_key(|item| item.pos);
+        }
+        if options.language.is_none() && options.detect_language {
+            options.language = Language::detect(&markdown, parser_options);
+        }
+        let quotes = if options.smart_quotes {
+            find_quotes(&markdown, parser_options)
+        } else {
+            Vec::new()
+        };
+        let highlighting = html::uses_highlighting(&markdown, parser_options);
+        let list_nesting = ListNesting::of(&markdown, parser_options);
+        let theorems = if options.theorem_environments {
+            find_theorems(&markdown, parser_options)
+        } else {
+            Vec::new()
+        };
+        // Only needed for citations and references to labeled theorems.
+        let runs =
+            if options.citation_syntax || theorems.iter().any(|(_, head)| head.label.is_some()) {
+                text_runs(&markdown, parser_options)
+            } else {
+                Vec::new()
+            };
+        let mut annotations = Vec::new();
+        if options.citation_syntax {
+            annotations.extend(
+                find_citations(&markdown, &runs)
+                    .into_iter()
+                    .filter(|(_, citation)| {
+                        let unsafe_key = citation.items.iter().find(|item| !is_safe_key(&item.key));
+                        if let (true, Some(item)) = (options.safe_mode, unsafe_key) {
+                            warn!("Ignoring citation with key \"{}\" in safe mode.", item.key);
+                            false
+                        } else {
+                            true
+                        }
+                    })
+                    .map(|(range, citation)| (range, Annotation::Citation(citation))),
+            );
+        }
+        annotations.extend(
+            find_references(&markdown, &runs, &theorems)
+                .into_iter()
+                .map(|(range, reference)| (range, Annotation::TheoremReference(reference))),
+        );
+        annotations.sort_by_key(|(range, _)| range.start);
+        let callouts = find_callouts(&markdown, parser_options, &options.callouts);
+
         Self {
             replacer,
             preprocessed: markdown,
+            heading_labels,
+            annotations,
+            theorems,
+            callouts,
+            description_items,
+            task_items,
+            quotes,
+            highlighting,
+            list_markers,
+            list_nesting,
+            force_enumitem: false,
+            options,
+            metadata: DocumentMetadata::default(),
+            bibliography: Bibliography::new(),
+        }
+    }
+
+    /// Returns the metadata from the front matter of the document.
+    ///
+    /// Returns empty metadata if the document has no front matter.
+    pub fn metadata(&self) -> &DocumentMetadata {
+        &self.metadata
+    }
+
+    /// Returns the structure of the document as the LaTeX writer sees it, e.g., to
+    /// inspect its headings, math, images, or links (see module
+    /// [`document`](document/index.html)).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bluepaper_core::document::NodeKind;
+    ///
+    /// let markdown = "Text with $$m_a^{th}$$ and ![an image](image.png).".to_string();
+    /// let converter = bluepaper_core::MarkdownToLatex::from_string(markdown);
+    /// let document = converter.document();
+    ///
+    /// let paragraph = &document.blocks()[0];
+    /// assert_eq!(paragraph.kind, NodeKind::Paragraph);
+    /// match &paragraph.children[1].kind {
+    ///     NodeKind::Math(math) => assert_eq!(math.content, "m_a^{th}"),
+    ///     kind => panic!("unexpected node {:?}", kind),
+    /// }
+    /// assert!(document
+    ///     .nodes()
+    ///     .any(|node| matches!(&node.kind, NodeKind::Image { url, .. } if url == "image.png")));
+    /// ```
+    pub fn document(&self) -> Document {
+        let parser_options = self.options.extensions.parser_options();
+        Document::parse(&self.preprocessed, self.replacer.clone(), parser_options)
+    }
+
+    /// Looks up bibliographic data for all works that are cited in the document.
+    ///
+    /// Has no effect unless
+    /// [`ConversionOptions::cite_links`](struct.ConversionOptions.html#structfield.cite_links)
+    /// or
+    /// [`ConversionOptions::citation_syntax`](struct.ConversionOptions.html#structfield.citation_syntax)
+    /// is set. The constructors already resolve citations with a
+    /// [`NoMetadata`](bibliography/struct.NoMetadata.html) source, which generates
+    /// BibTeX entries that contain only the identifiers of cited works. Call this
+    /// method to replace them with more complete entries from a different source.
+    ///
+    /// Returns the keys of pandoc-style citations (`[@key]`) that were not found in
+    /// `source`, in the order in which they first appear in the document.
+    ///
+    /// # Examples
+    ///
+    /// Citing links:
+    ///
+    /// ```
+    /// use bluepaper_core::bibliography::{BibEntry, CitationId};
+    /// use bluepaper_core::{ConversionOptions, MarkdownToLatex};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut metadata_source = HashMap::new();
+    /// metadata_source.insert(
+    ///     CitationId::Arxiv("2001.01234".to_string()),
+    ///     BibEntry {
+    ///         entry_type: "article".to_string(),
+    ///         key: String::new(), // Generate a key.
+    ///         fields: vec![
+    ///             ("author".to_string(), "Smith, Jane".to_string()),
+    ///             ("title".to_string(), "Studying Things".to_string()),
+    ///             ("year".to_string(), "2020".to_string()),
+    ///         ],
+    ///     },
+    /// );
+    ///
+    /// let markdown = "As shown [recently](https://arxiv.org/abs/2001.01234v2).".to_string();
+    /// let options = ConversionOptions {
+    ///     cite_links: true,
+    ///     ..Default::default()
+    /// };
+    /// let mut converter = MarkdownToLatex::with_options(markdown, options);
+    /// converter.resolve_citations(&mut metadata_source);
+    ///
+    /// assert_eq!(
+    ///     converter.bibtex().unwrap(),
+    ///     "@article{smith2020studying,\n  author = {Smith, Jane},\n  \
+    ///      title = {Studying Things},\n  year = {2020},\n}\n"
+    /// );
+    ///
+    /// let latex = converter.into_string();
+    /// assert!(latex.find("As shown \\cite{smith2020studying}.").is_some());
+    /// assert!(latex
+    ///     .find("\\bibliographystyle{plainnat}\n\\bibliography{references}\n")
+    ///     .is_some());
+    /// ```
+    ///
+    /// Pandoc-style citations with keys from an existing BibTeX file:
+    ///
+    /// ```
+    /// use bluepaper_core::bibliography::BibFile;
+    /// use bluepaper_core::{ConversionOptions, MarkdownToLatex};
+    ///
+    /// let mut bib_file = BibFile::parse("@book{roe2019, title = {A Book}}");
+    ///
+    /// let markdown = "See [@roe2019, p. 3; @doe2018] and @roe2019.".to_string();
+    /// let options = ConversionOptions {
+    ///     citation_syntax: true,
+    ///     ..Default::default()
+    /// };
+    /// let mut converter = MarkdownToLatex::with_options(markdown, options);
+    /// let unresolved = converter.resolve_citations(&mut bib_file);
+    ///
+    /// assert_eq!(unresolved, ["doe2018"]);
+    /// assert_eq!(converter.bibtex().unwrap(), "@book{roe2019,\n  title = {A Book},\n}\n");
+    ///
+    /// let latex = converter.into_string();
+    /// assert!(latex.find("\\usepackage[numbers]{natbib}").is_some());
+    /// assert!(latex
+    ///     .find("See \\citetext{\\citealp[p.~3]{roe2019}; \\citealp{doe2018}} and \\citet{roe2019}.")
+    ///     .is_some());
+    /// ```
+    pub fn resolve_citations(&mut self, source: &mut dyn MetadataSource) -> Vec<String> {
+        let mut bibliography = Bibliography::new();
+
+        // Resolve fixed citation keys first so that generated keys can't clash with them.
+        for (_, annotation) in &self.annotations {
+            if let Annotation::Citation(citation) = annotation {
+                for item in &citation.items {
+                    bibliography.cite_key(&item.key, source);
+                }
+            }
+        }
+
+        if self.options.cite_links {
+            let parser_options = self.options.extensions.parser_options();
+            for event in Parser::new_ext(&self.preprocessed, parser_options) {
+                if let Event::Start(Tag::Link(_, url, _)) = event {
+                    if let Some(id) = CitationId::from_url(&url) {
+                        bibliography.cite(id, source);
+                    }
+                }
+            }
+        }
+        let unresolved = bibliography.unresolved_keys().to_vec();
+        self.bibliography = bibliography;
+        unresolved
+    }
+
+    /// Returns the contents of the BibTeX file with all cited works.
+    ///
+    /// Returns `None` if the document doesn't cite any works. Otherwise, the
+    /// returned string has to be saved in a file named
+    /// [`BIBTEX_FILE_NAME`](bibliography/constant.BIBTEX_FILE_NAME.html) next to the
+    /// LaTeX file.
+    pub fn bibtex(&self) -> Option<String> {
+        if self.bibliography.entries().next().is_none() {
+            None
+        } else {
+            Some(self.bibliography.to_bibtex())
+        }
+    }
+
+    fn citation_key(&self, url: &str) -> Option<&str> {
+        if self.options.cite_links {
+            self.bibliography.key(&CitationId::from_url(url)?)
+        } else {
+            None
         }
     }
 
@@ -103,16 +501,15 @@ impl MarkdownToLatex {
     /// URL of the image and `f` must return the path to an image file and a bool. If
     /// the bool returned by `f` is `true` then an uncommented `\includegraphics` will
     /// be generated. If it is `false`, then the `\includegraphics` will be generated
-    /// but commented out.
+    /// but commented out. Paths that could be interpreted as TeX code (see
+    /// [`is_safe_path`](latex_escape/fn.is_safe_path.html)) are commented out, too.
     pub fn into_string_with_image_callback(
         self,
         f: &mut dyn FnMut(&str) -> Option<String>,
     ) -> String {
-        unsafe {
-            let mut latex = Vec::new();
-            self.write_to_with_image_callback(&mut latex, f).unwrap();
-            String::from_utf8_unchecked(latex)
-        }
+        let mut latex = Vec::new();
+        self.write_to_with_image_callback(&mut latex, f).unwrap();
+        String::from_utf8(latex).expect("LaTeX output is valid UTF-8")
     }
 
     /// Consumes the converter and writes LaTeX code without images to `writer`.
@@ -134,95 +531,418 @@ impl MarkdownToLatex {
     /// path to an image file and a bool. If the bool returned by `image_callback` is
     /// `true` then an uncommented `\includegraphics` will be generated. If it is
     /// `false`, then the `\includegraphics` will be generated but commented out.
+    /// Paths that could be interpreted as TeX code (see
+    /// [`is_safe_path`](latex_escape/fn.is_safe_path.html)) are commented out, too.
     ///
     /// Hands back ownership of the writer when it's done. The written output is
     /// guaranteed to be valid UTF-8.
     pub fn write_to_with_image_callback<W: Write>(
-        mut self,
+        self,
         writer: W,
         image_callback: &mut dyn FnMut(&str) -> Option<String>,
     ) -> std::io::Result<W> {
-        let mut options = Options::empty();
-        options.insert(Options::ENABLE_STRIKETHROUGH);
-        options.insert(Options::ENABLE_TASKLISTS);
-        let mut parser = Parser::new_ext(&self.preprocessed, options).into_offset_iter();
-
-        let mut writer = WhitespaceFormatter::new_latex_formatter(writer)?;
+        let mut writer = writer;
+        self.preamble().write_to(&mut writer)?;
+        let mut writer = WhitespaceFormatter::new(writer);
         writer.limit_newlines(2);
         writer.add_newlines(2);
 
+        self.write_body(&mut writer, image_callback)?;
+
+        writer.limit_newlines(3);
+        writer.add_newlines(3);
+        writer.write_all(b"\\end{document}\n")?;
+
+        writer.into_inner()
+    }
+
+    /// Returns the preamble with all packages that the document needs.
+    fn preamble(&self) -> Preamble {
+        let mut preamble = Preamble::new(self.options.engine, self.options.language);
+        if !self.bibliography.is_empty() {
+            preamble.use_package("natbib", "numbers");
+        }
+        if !self.theorems.is_empty() {
+            preamble.use_package("amsthm", "");
+            for kind in THEOREM_KINDS.iter() {
+                if let Some(declaration) = kind.declaration() {
+                    if self.theorems.iter().any(|(_, head)| head.kind == *kind) {
+                        preamble.add_line(declaration);
+                    }
+                }
+            }
+        }
+        if self.highlighting {
+            if self.options.engine == Engine::LuaLatex {
+                // `soul` doesn't work with LuaLaTeX.
+                preamble.use_package("luacolor", "");
+                preamble.use_package("lua-ul", "");
+                preamble.add_line(r"\newcommand{\hl}[1]{\highLight{#1}}".to_string());
+            } else {
+                preamble.use_package("xcolor", "");
+                preamble.use_package("soul", "");
+            }
+        }
+        if self.uses_enumitem() {
+            self.list_nesting.declare(&mut preamble);
+        }
+        if !self.callouts.is_empty() {
+            preamble.use_package("tcolorbox", "");
+            for (_, callout) in &self.callouts {
+                preamble.add_line(callout::declaration(&self.options.callouts[callout.style]));
+            }
+        }
+        preamble
+    }
+
+    fn uses_enumitem(&self) -> bool {
+        self.force_enumitem || !self.list_markers.is_empty() || self.list_nesting.exceeds_latex()
+    }
+
+    fn write_body<W: Write>(
+        mut self,
+        writer: &mut WhitespaceFormatter<W>,
+        image_callback: &mut dyn FnMut(&str) -> Option<String>,
+    ) -> std::io::Result<()> {
+        let document = self.document();
+        let mut walk = document.walk();
+        let mut heading_labels = std::mem::take(&mut self.heading_labels)
+            .into_iter()
+            .peekable();
+        let mut annotations = std::mem::take(&mut self.annotations).into_iter().peekable();
+        let mut theorems = std::mem::take(&mut self.theorems).into_iter().peekable();
+        let mut consumed_until = 0;
+        let mut callouts = std::mem::take(&mut self.callouts).into_iter().peekable();
+        let enumitem = self.uses_enumitem();
+        let mut list_markers = std::mem::take(&mut self.list_markers)
+            .into_iter()
+            .peekable();
+        let mut description_items = std::mem::take(&mut self.description_items)
+            .into_iter()
+            .peekable();
+        let mut description_term: Option<DescriptionItem> = None;
+        let task_items = std::mem::take(&mut self.task_items);
+        let mut task_items_iter = task_items.iter().peekable();
+        let mut open_tasks = Vec::new();
+        let mut html = HtmlTranslator::new();
+        let mut quotes = QuoteWriter::new(
+            std::mem::take(&mut self.quotes),
+            self.options.language.is_some(),
+        );
+        let mut quote_environments = Vec::new();
+        let mut itemize_environments = Vec::new();
+        let mut lead_in_end = 0;
+        let mut theorem_environment = None;
+        let metadata = std::mem::take(&mut self.metadata);
+
+        let mut title_from_heading = metadata.title.is_none()
+            && self.options.title_from_first_heading
+            && matches!(
+                document.blocks().first(),
+                Some(Node {
+                    kind: NodeKind::Heading { level: 1 },
+                    ..
+                })
+            );
+        if !title_from_heading {
+            write_title_block(&metadata, &self.options, writer, image_callback)?;
+        }
+
         let mut enumerate_nesting = 0;
-        let mut first_event = true;
+        let mut list_depth = 0;
         let mut in_title = false;
+        let mut heading_label = None;
+        let mut seen_section = false;
+        let mut in_appendix = false;
+        let mut special_section = None;
+        let mut num_bibitems = 0;
+        let mut skipping_section = false;
+        let mut bibliography_written = false;
+
+        // Escapes consecutive text events as a single run of text (e.g., so that an
+        // ellipsis split across events is recognized).
+        let mut escaper =
+            LatexEscaper::with_unicode(&mut *writer, self.options.unicode_escaping().cloned());
+
+        while let Some((visit, node)) = walk.next() {
+            let range = node.range.clone();
+            if skipping_section {
+                // Skips whole blocks, so headings within quotes or lists don't end
+                // the section.
+                if let (Visit::Enter, NodeKind::Heading { .. }) = (visit, &node.kind) {
+                    skipping_section = false;
+                } else {
+                    if visit == Visit::Enter {
+                        walk.skip_subtree();
+                    }
+                    continue;
+                }
+            }
+
+            // Skip the markup of a theorem head or callout marker (its text is skipped
+            // via `consumed_until`).
+            if range.end <= lead_in_end
+                && matches!(node.kind, NodeKind::Strong | NodeKind::SoftBreak)
+            {
+                continue;
+            }
+
+            if !matches!(node.kind, NodeKind::Text(_))
+                || description_term.is_some_and(|item| range.start >= item.term_end)
+            {
+                escaper.finish()?;
+            }
+            let writer = &mut **escaper.get_mut();
+
+            // Close the term of a description item when its description starts.
+            if let Some(item) = description_term.filter(|item| range.start >= item.term_end) {
+                description_term = None;
+                consumed_until = std::cmp::max(consumed_until, item.end);
+                writer.limit_newlines(0);
+                match (visit, &node.kind) {
+                    (_, NodeKind::SoftBreak) | (_, NodeKind::HardBreak) => {
+                        writer.write_all(b"}]")?;
+                        writer.add_newlines(1);
+                        continue;
+                    }
+                    (_, NodeKind::Text(_)) if range.end <= item.end => writer.write_all(b"}]")?,
+                    (_, NodeKind::Text(_))
+                    | (_, NodeKind::Code(_))
+                    | (_, NodeKind::Math(_))
+                    | (_, NodeKind::Html(_))
+                    | (Visit::Enter, NodeKind::Emphasis)
+                    | (Visit::Enter, NodeKind::Strong)
+                    | (Visit::Enter, NodeKind::Strikethrough)
+                    | (Visit::Enter, NodeKind::Link { .. }) => writer.write_all(b"}] ")?,
+                    _ => writer.write_all(b"}]")?,
+                }
+            }
 
-        while let Some((event, range)) = parser.next() {
-            match event {
-                Event::Start(Tag::Heading(level)) => {
-                    if first_event && level == 1 {
+            match (visit, &node.kind) {
+                (Visit::Enter, &NodeKind::Heading { level }) => {
+                    // Headings within lists or quotes don't start or end sections.
+                    let top_level = list_depth == 0 && quote_environments.is_empty();
+                    if top_level {
+                        if let Some(section) = special_section.take() {
+                            close_special_section(section, writer)?;
+                        }
+                    }
+
+                    while heading_labels
+                        .peek()
+                        .is_some_and(|(pos, _)| *pos < range.start)
+                    {
+                        heading_labels.next();
+                    }
+                    heading_label = heading_labels
+                        .next_if(|(pos, _)| *pos < range.end)
+                        .map(|(_, label)| label);
+
+                    if title_from_heading {
+                        title_from_heading = false;
                         in_title = true;
                         writer.write_all(b"\n\\title{")?;
                     } else {
-                        let h = HEADINGS[std::cmp::min(level as usize, HEADINGS.len()) - 1];
-                        writer.add_newlines(h.1 + 1);
-                        writer.write_all(h.0)?;
+                        let text = heading_text(&self.preprocessed[range.clone()]);
+                        let kind = Some(text)
+                            .filter(|_| top_level)
+                            .and_then(|text| self.options.section_names.classify(text));
+                        match kind {
+                            Some(SectionKind::Abstract)
+                                if !seen_section && metadata.abstract_text.is_none() =>
+                            {
+                                walk.skip_subtree();
+                                writer.add_newlines(2);
+                                writer.write_on_single_line(r"\begin{abstract}")?;
+                                writer.limit_newlines(1);
+                                special_section = Some(SectionKind::Abstract);
+                            }
+                            Some(SectionKind::Bibliography) if !self.bibliography.is_empty() => {
+                                warn!(
+                                    "Replacing section \"{}\" with the generated bibliography.",
+                                    text
+                                );
+                                walk.skip_subtree();
+                                write_bibliography_commands(writer)?;
+                                bibliography_written = true;
+                                skipping_section = true;
+                            }
+                            Some(SectionKind::Bibliography) => {
+                                walk.skip_subtree();
+                                writer.add_newlines(3);
+                                writer.write_on_single_line(r"\begin{thebibliography}{99}")?;
+                                writer.increase_indent();
+                                special_section = Some(SectionKind::Bibliography);
+                            }
+                            kind => {
+                                if kind == Some(SectionKind::Appendix) && !in_appendix {
+                                    in_appendix = true;
+                                    writer.add_newlines(3);
+                                    writer.write_all(br"\appendix")?;
+                                    writer.add_newlines(1);
+                                    writer.limit_newlines(1);
+                                }
+                                seen_section = true;
+                                let h = HEADINGS[std::cmp::min(level as usize, HEADINGS.len()) - 1];
+                                writer.add_newlines(h.1 + 1);
+                                writer.write_all(h.0)?;
+                            }
+                        }
                     }
                 }
-                Event::End(Tag::Heading(level)) => {
+                (Visit::Leave, &NodeKind::Heading { level }) => {
+                    html.close_all(writer)?;
+                    quotes.close_all(writer)?;
                     if in_title {
                         in_title = false;
-                        writer.write_all(b"}\n\\maketitle")?;
-                        writer.add_newlines(2);
+                        writer.write_all(b"}")?;
+                        write_title_details(&metadata, &self.options, writer, image_callback)?;
                     } else {
                         writer.write_all(b"}")?;
+                        if let Some(label) = heading_label.take() {
+                            write!(writer, r"\label{{{}}}", label)?;
+                        }
                         let h = HEADINGS[std::cmp::min(level as usize, HEADINGS.len()) - 1];
                         writer.add_newlines(h.1);
                     }
                     writer.limit_newlines(2); // Handles case of multiple consecutive headers
                 }
 
-                Event::Start(Tag::Paragraph) => {
+                (Visit::Enter, NodeKind::Paragraph)
+                    if special_section == Some(SectionKind::Bibliography) && list_depth == 0 =>
+                {
+                    num_bibitems += 1;
+                    write_bibitem(num_bibitems, writer)?;
+                }
+                (Visit::Enter, NodeKind::Paragraph) => {
                     writer.add_newlines(2);
+                    while theorems.next_if(|(pos, _)| *pos < range.start).is_some() {}
+                    if let Some((_, head)) = theorems
+                        .next_if(|(pos, _)| *pos == range.start)
+                        .filter(|(_, head)| head.end > consumed_until)
+                    {
+                        theorem::write_begin(&head, writer.get_mut()?)?;
+                        writer.add_newlines(1);
+                        writer.limit_newlines(1);
+                        lead_in_end = head.end;
+                        consumed_until = std::cmp::max(consumed_until, head.end);
+                        theorem_environment = Some(head.kind.environment());
+                    }
                 }
-                Event::End(Tag::Paragraph) => {
+                (Visit::Leave, NodeKind::Paragraph) => {
+                    html.close_all(writer)?;
+                    quotes.close_all(writer)?;
+                    if let Some(environment) = theorem_environment.take() {
+                        writer.limit_newlines(1);
+                        writer.add_newlines(1);
+                        write!(writer, r"\end{{{}}}", environment)?;
+                    }
                     writer.add_newlines(2);
                 }
 
-                Event::Start(Tag::BlockQuote) => {
+                (Visit::Enter, NodeKind::BlockQuote) => {
                     writer.add_newlines(2);
-                    writer.write_on_single_line(r"\begin{quote}")?;
+                    while callouts.next_if(|(pos, _)| *pos < range.start).is_some() {}
+                    let environment = if let Some((_, callout)) =
+                        callouts.next_if(|(pos, _)| *pos == range.start)
+                    {
+                        lead_in_end = callout.end;
+                        consumed_until = std::cmp::max(consumed_until, callout.end);
+                        callout::environment(&self.options.callouts[callout.style])
+                    } else {
+                        "quote".to_string()
+                    };
+                    writer.write_on_single_line(&format!(r"\begin{{{}}}", environment))?;
+                    quote_environments.push(environment);
                 }
-                Event::End(Tag::BlockQuote) => {
-                    writer.write_on_single_line(r"\end{quote}")?;
+                (Visit::Leave, NodeKind::BlockQuote) => {
+                    let environment = quote_environments.pop().expect("balanced blockquotes");
+                    writer.write_on_single_line(&format!(r"\end{{{}}}", environment))?;
                     writer.add_newlines(2);
                 }
 
-                Event::Start(Tag::CodeBlock(_language)) => {
-                    // TODO: Use the verbatim package to define a new verbatim environment if the
-                    //       text of the code block contains the string "\end{verbatim}".
-                    writer.write_on_single_line(r"\begin{verbatim}")?;
-                    writer.limit_newlines(1);
-                    // TODO: Check if there's always exactly one text block inside a code block.
-                    //       If that's the case, process it here and properly escape it.
+                (_, NodeKind::CodeBlock { code, .. }) => {
+                    write_code_block(code, writer)?;
                 }
-                Event::End(Tag::CodeBlock(_language)) => {
-                    // No newline before `\end{verbatim}` because the contents of the code block
-                    // already ended with one.
-                    writer.write_all(br"\end{verbatim}")?;
-                    writer.add_newlines(1);
+
+                (Visit::Enter, NodeKind::List { .. })
+                    if special_section == Some(SectionKind::Bibliography) && list_depth == 0 =>
+                {
+                    list_depth += 1;
+                }
+                (Visit::Leave, NodeKind::List { .. })
+                    if special_section == Some(SectionKind::Bibliography) && list_depth == 1 =>
+                {
+                    list_depth -= 1;
                 }
 
-                Event::Start(Tag::List(None)) => {
-                    writer.write_on_single_line(r"\begin{itemize}")?;
+                (Visit::Enter, NodeKind::List { start: None }) => {
+                    list_depth += 1;
+                    while description_items
+                        .next_if(|item| item.pos < range.start)
+                        .is_some()
+                    {}
+                    let environment = if description_items
+                        .peek()
+                        .is_some_and(|item| item.pos == range.start)
+                    {
+                        "description"
+                    } else {
+                        "itemize"
+                    };
+                    writer.write_on_single_line(&format!(r"\begin{{{}}}", environment))?;
+                    itemize_environments.push(environment);
                     writer.increase_indent();
                     writer.increase_indent();
                 }
-                Event::End(Tag::List(None)) => {
+                (Visit::Leave, NodeKind::List { start: None }) => {
                     writer.decrease_indent();
                     writer.decrease_indent();
-                    writer.write_on_single_line(r"\end{itemize}")?;
+                    let environment = itemize_environments.pop().expect("balanced lists");
+                    writer.write_on_single_line(&format!(r"\end{{{}}}", environment))?;
+                    list_depth -= 1;
                 }
 
-                Event::Start(Tag::List(Some(first_number))) => {
+                (
+                    Visit::Enter,
+                    &NodeKind::List {
+                        start: Some(first_number),
+                    },
+                ) if enumitem => {
+                    list_depth += 1;
+                    while list_markers
+                        .next_if(|marker| marker.pos < range.start)
+                        .is_some()
+                    {}
+                    let marker = list_markers.next_if(|marker| marker.pos == range.start);
+
+                    let mut list_options = Vec::new();
+                    if let Some(marker) = &marker {
+                        list_options.push(format!("label={}", marker.label()));
+                    }
+                    let start = marker.map_or(first_number, |marker| u64::from(marker.number));
+                    if start != 1 {
+                        list_options.push(format!("start={}", start));
+                    }
+                    if list_options.is_empty() {
+                        writer.write_on_single_line(r"\begin{enumerate}")?;
+                    } else {
+                        writer.write_on_single_line(&format!(
+                            r"\begin{{enumerate}}[{}]",
+                            list_options.join(",")
+                        ))?;
+                    }
+                    writer.increase_indent();
+                    writer.increase_indent();
+                    enumerate_nesting += 1
+                }
+                (
+                    Visit::Enter,
+                    &NodeKind::List {
+                        start: Some(first_number),
+                    },
+                ) => {
+                    list_depth += 1;
                     writer.write_on_single_line(r"\begin{enumerate}")?;
                     writer.increase_indent();
 
@@ -236,175 +956,674 @@ impl MarkdownToLatex {
                     writer.increase_indent();
                     enumerate_nesting += 1
                 }
-                Event::End(Tag::List(Some(_first_number))) => {
+                (Visit::Leave, NodeKind::List { start: Some(_) }) => {
                     writer.decrease_indent();
                     writer.decrease_indent();
                     writer.write_on_single_line(r"\end{enumerate}")?;
-                    enumerate_nesting -= 1
+                    enumerate_nesting -= 1;
+                    list_depth -= 1;
                 }
 
-                Event::Start(Tag::Item) => {
+                (Visit::Enter, NodeKind::Item)
+                    if special_section == Some(SectionKind::Bibliography) && list_depth == 1 =>
+                {
+                    num_bibitems += 1;
+                    write_bibitem(num_bibitems, writer)?;
+                }
+                (Visit::Enter, NodeKind::Item) => {
                     writer.add_newlines(1);
                     writer.decrease_indent();
-                    writer.write_all(br"\item ")?;
+                    while task_items_iter
+                        .next_if(|item| item.pos < range.start)
+                        .is_some()
+                    {}
+                    if let Some(item) = task_items_iter.next_if(|item| item.pos == range.start) {
+                        // The checkbox replaces the bullet.
+                        if item.checked {
+                            writer.write_all(br"\item[\checkedbox] ")?;
+                        } else if self.options.todo_summary {
+                            write!(
+                                writer,
+                                r"\item[\uncheckedbox]\phantomsection\label{{{}}} ",
+                                tasks::label(open_tasks.len())
+                            )?;
+                            open_tasks.push(item);
+                        } else {
+                            writer.write_all(br"\item[\uncheckedbox] ")?;
+                        }
+                    } else if let Some(item) =
+                        description_items.next_if(|item| item.pos == range.start)
+                    {
+                        // Markup of the term (e.g., the "**" of a bold term) is skipped
+                        // because LaTeX already typesets the term in bold. The braces
+                        // allow brackets in the term.
+                        writer.write_all(br"\item[{")?;
+                        lead_in_end = item.term_end;
+                        description_term = Some(item);
+                    } else {
+                        writer.write_all(br"\item ")?;
+                    }
                     writer.increase_indent();
                     writer.limit_newlines(0);
                 }
-                Event::End(Tag::Item) => {}
+                (Visit::Leave, NodeKind::Item) => {
+                    html.close_all(writer)?;
+                    quotes.close_all(writer)?;
+                    if description_term.take().is_some() {
+                        writer.write_all(b"}]")?;
+                    }
+                }
 
-                Event::TaskListMarker(checked) => {
-                    let checkbox = if checked {
-                        &br"[\checkedbox] "[..]
-                    } else {
-                        &br"[\uncheckedbox] "[..]
-                    };
-                    writer.write_all(checkbox)?;
-                    writer.limit_newlines(0);
+                (_, NodeKind::TaskListMarker { .. }) => {
+                    // Already handled at the start of the list item.
                 }
 
-                Event::Start(Tag::FootnoteDefinition(_)) => {
+                (Visit::Enter, NodeKind::FootnoteDefinition { .. }) => {
                     warn!("Ignoring footnote definition (not yet implemented).")
                     // TODO
                 }
-                Event::End(Tag::FootnoteDefinition(_)) => {}
+                (Visit::Leave, NodeKind::FootnoteDefinition { .. }) => {}
 
-                Event::FootnoteReference(_) => {
+                (_, NodeKind::FootnoteReference { .. }) => {
                     warn!("Ignoring footnote reference (not yet implemented).") // TODO
                 }
 
-                Event::Start(Tag::Table(..)) => {
+                (Visit::Enter, NodeKind::Table { .. }) => {
                     warn!("Ignoring table (not yet implemented)."); // TODO
                 }
-                Event::End(Tag::Table(..)) => {}
+                (Visit::Leave, NodeKind::Table { .. }) => {}
 
-                Event::Start(Tag::TableHead) => {}
-                Event::End(Tag::TableHead) => {}
+                (Visit::Enter, NodeKind::TableHead) => {}
+                (Visit::Leave, NodeKind::TableHead) => {}
 
-                Event::Start(Tag::TableRow) => {}
-                Event::End(Tag::TableRow) => {}
+                (Visit::Enter, NodeKind::TableRow) => {}
+                (Visit::Leave, NodeKind::TableRow) => {}
 
-                Event::Start(Tag::TableCell) => {}
-                Event::End(Tag::TableCell) => {}
+                (Visit::Enter, NodeKind::TableCell) => {}
+                (Visit::Leave, NodeKind::TableCell) => {}
 
-                Event::Start(Tag::Emphasis) => {
+                (Visit::Enter, NodeKind::Emphasis) => {
                     writer.write_all(br"\emph{")?;
                 }
-                Event::End(Tag::Emphasis) => {
+                (Visit::Leave, NodeKind::Emphasis) => {
                     writer.write_all(br"}")?;
                 }
 
-                Event::Start(Tag::Strong) => {
+                (Visit::Enter, NodeKind::Strong) => {
                     writer.write_all(br"\textbf{")?;
                 }
-                Event::End(Tag::Strong) => {
+                (Visit::Leave, NodeKind::Strong) => {
                     writer.write_all(br"}")?;
                 }
 
-                Event::Start(Tag::Strikethrough) => {
+                (Visit::Enter, NodeKind::Strikethrough) => {
                     writer.write_all(br"\sout{")?;
                 }
-                Event::End(Tag::Strikethrough) => {
+                (Visit::Leave, NodeKind::Strikethrough) => {
                     writer.write_all(br"}")?;
                 }
 
-                Event::Start(Tag::Link(LinkType::Inline, url, _title)) => {
-                    writer.write_all(br"\href{")?;
-                    // TODO: escape url (Note: unfortunately, we cannot un_replace here).
-                    writer.write_all(url.as_bytes())?;
-                    writer.write_all(br"}{")?;
+                (Visit::Enter, NodeKind::Link { kind, url, .. }) => {
+                    if let Some(key) = self.citation_key(url) {
+                        write!(writer, r"\cite{{{}}}", key)?;
+                        walk.skip_subtree();
+                    } else if *kind == LinkKind::Inline {
+                        writer.write_all(br"\href{")?;
+                        // TODO: un_replace (Note: unfortunately, we cannot un_replace here).
+                        escape_url(url, writer.get_mut()?)?;
+                        writer.write_all(br"}{")?;
+                    } else {
+                        warn!("Ignoring non-inline link (not yet implemented).")
+                    }
                 }
-                Event::End(Tag::Link(LinkType::Inline, _url, _title)) => {
+                (
+                    Visit::Leave,
+                    NodeKind::Link {
+                        kind: LinkKind::Inline,
+                        ..
+                    },
+                ) => {
                     writer.write_all(br"}")?;
                 }
-                Event::Start(Tag::Link(_, _, _)) => {
-                    warn!("Ignoring non-inline link (not yet implemented).")
-                }
-                Event::End(Tag::Link(_, _, _)) => panic!(),
+                (Visit::Leave, NodeKind::Link { .. }) => {} // Non-inline link (see above).
 
-                Event::Start(Tag::Image(LinkType::Inline, url, _title)) => {
+                (
+                    Visit::Enter,
+                    NodeKind::Image {
+                        kind: LinkKind::Inline,
+                        url,
+                        ..
+                    },
+                ) => {
                     writer.add_newlines(1);
-                    let filename = image_callback(url.as_ref());
-                    let filename = if let Some(ref filename) = filename {
-                        filename.as_str()
-                    } else {
-                        writer.write_all(b"%")?;
-                        url.as_ref()
-                    };
-                    writer.write_all(br"\includegraphics[width=\textwidth]{")?;
-                    // TODO: escape url  (Note: unfortunately, we cannot un_replace here).
-                    writer.write_all(filename.as_bytes())?;
+                    match image_callback(url) {
+                        Some(filename) if is_safe_path(&filename) => {
+                            writer.write_all(br"\includegraphics[width=\textwidth]{")?;
+                            writer.write_all(filename.as_bytes())?;
+                        }
+                        filename => {
+                            // Comment the image out, but keep the (escaped) reference.
+                            let filename = if let Some(ref filename) = filename {
+                                warn!("Ignoring image with unsafe file name {:?}.", filename);
+                                filename.as_str()
+                            } else {
+                                url
+                            };
+                            writer.write_all(br"%\includegraphics[width=\textwidth]{")?;
+                            escape_url(filename, writer)?;
+                        }
+                    }
                     writer.write_all(b"}")?;
                     writer.add_newlines(1);
-                    if let Some((Event::End(Tag::Image(LinkType::Inline, ..)), _)) = parser.next() {
-                        // OK.
-                    } else {
-                        panic!("Unclosed inline link.")
-                    }
+                    // Skip the alt text.
+                    walk.skip_subtree();
                 }
-                Event::End(Tag::Image(_, _, _)) => panic!(),
-                Event::Start(Tag::Image(_, _, _)) => {
+                (Visit::Leave, NodeKind::Image { .. }) => {} // Non-inline image (see below).
+                (Visit::Enter, NodeKind::Image { .. }) => {
                     warn!("Ignoring non-inline image (not yet implemented).")
                 }
 
-                Event::Text(text) => {
-                    let text = self.replacer.un_replace(text, range.start..range.end);
-                    let inner_writer = writer.get_mut()?;
-                    escape_str(&text, inner_writer)?;
+                (_, NodeKind::Text(text)) => {
+                    if text.len() != range.len() {
+                        // Not a verbatim copy of the source (e.g., an entity), so we can't
+                        // split it up. Annotations never start within such text.
+                        if range.start >= consumed_until {
+                            escaper.write_all(text.as_bytes())?;
+                        }
+                    } else {
+                        // An annotation may span several text events (e.g., "[", "@a", "]").
+                        let mut pos =
+                            std::cmp::min(std::cmp::max(range.start, consumed_until), range.end);
+                        while let Some((annotation_range, annotation)) = annotations
+                            .next_if(|(annotation_range, _)| annotation_range.start < range.end)
+                        {
+                            if annotation_range.start >= pos {
+                                quotes.write_text(
+                                    &text[pos - range.start..annotation_range.start - range.start],
+                                    pos,
+                                    &mut escaper,
+                                )?;
+                                escaper.finish()?;
+                                annotation.write_to(escaper.get_mut().get_mut()?)?;
+                                consumed_until = annotation_range.end;
+                                pos = std::cmp::min(consumed_until, range.end);
+                            }
+                        }
+                        quotes.write_text(&text[pos - range.start..], pos, &mut escaper)?;
+                    }
                 }
 
-                Event::Code(text) => {
-                    if self.replacer.check_if_replacement_point(range.start)
-                        && range.end > 2
-                        && self.replacer.check_if_replacement_point(range.end - 2)
-                    {
-                        let math = self
-                            .replacer
-                            .un_replace(text, range.start + 2..range.end - 2);
+                (_, NodeKind::Math(span)) => {
+                    let math = span.content.as_str();
+                    let check = if self.options.safe_mode {
+                        math::check_safe_math(math)
+                    } else {
+                        math::check_math(math)
+                    };
+                    let broken = if let Err(err) = check {
+                        let content_start = range.start + span.delimiters.0.len();
+                        warn!(
+                            "Invalid math \"{}{}{}\" on line {}: {}.",
+                            span.delimiters.0,
+                            math,
+                            span.delimiters.1,
+                            line_number(&self.preprocessed, content_start + err.pos()),
+                            err
+                        );
+                        self.options.broken_math_as_code || self.options.safe_mode
+                    } else {
+                        false
+                    };
+
+                    if broken {
+                        let mut escaper = LatexEscaper::with_unicode(
+                            writer.get_mut()?,
+                            self.options.unicode_escaping().cloned(),
+                        );
+                        escaper.get_mut().write_all(br"\texttt{")?;
+                        escaper.write_all(span.delimiters.0.as_bytes())?;
+                        escaper.write_all(math.as_bytes())?;
+                        escaper.write_all(span.delimiters.1.as_bytes())?;
+                        escaper.into_inner()?.write_all(br"}")?;
+                    } else if span.display {
+                        let math = math.trim();
+                        writer.add_newlines(1);
+                        writer.write_all(br"\[")?;
+                        writer.write_all(math.as_bytes())?;
+                        if math::ends_with_comment(math) {
+                            writer.write_all(b"\n")?;
+                        }
+                        writer.write_all(br"\]")?;
+                        writer.add_newlines(1);
+                    } else {
                         writer.write_all(br"$")?;
                         writer.write_all(math.as_bytes())?;
+                        if math::ends_with_comment(math) {
+                            writer.write_all(b"\n")?;
+                        }
                         writer.write_all(br"$")?;
-                    } else {
-                        let inner_writer = writer.get_mut()?;
-                        inner_writer.write_all(br"\texttt{")?;
-                        // TODO: un_replace
-                        escape_str(&text, inner_writer)?;
-                        inner_writer.write_all(br"}")?;
                     }
                 }
 
-                Event::Html(html) => {
-                    info!("Found an HTML tag. Including it verbatim in LaTeX writer.");
-                    let html = self.replacer.un_replace(html, range.start..range.end);
-                    let inner_writer = writer.get_mut()?;
-                    inner_writer.write_all(br"\texttt{")?;
-                    escape_str(&html, inner_writer)?;
-                    inner_writer.write_all(br"}")?;
+                (_, NodeKind::Code(text)) => {
+                    let mut escaper = LatexEscaper::with_unicode(
+                        writer.get_mut()?,
+                        self.options.unicode_escaping().cloned(),
+                    );
+                    escaper.get_mut().write_all(br"\texttt{")?;
+                    // TODO: un_replace
+                    escaper.write_all(text.as_bytes())?;
+                    escaper.into_inner()?.write_all(br"}")?;
                 }
 
-                Event::SoftBreak => {
-                    // We should not have to print " \\" here but Dropbox seems to abuse
-                    // soft line breaks for hard line breaks.
-                    writer.write_all(br" \\")?;
-                    writer.add_newlines(1);
+                (_, NodeKind::Html(text)) => {
+                    let line_start = self.preprocessed[..range.start]
+                        .rfind('\n')
+                        .map_or(0, |newline| newline + 1);
+                    let block = self.preprocessed[line_start..range.start]
+                        .bytes()
+                        .all(|b| b == b' ' || b == b'\t' || b == b'>');
+                    let preprocessed = &self.preprocessed;
+                    html.write(
+                        text,
+                        block,
+                        || line_number(preprocessed, range.start),
+                        writer,
+                    )?;
                 }
 
-                Event::HardBreak => {
+                (_, NodeKind::SoftBreak) => match self.options.soft_break {
+                    SoftBreak::LineBreak => {
+                        writer.write_all(br" \\")?;
+                        writer.add_newlines(1);
+                    }
+                    SoftBreak::Space => writer.add_newlines(1),
+                },
+
+                (_, NodeKind::HardBreak) => {
                     writer.write_all(br" \\")?;
                     writer.add_newlines(1);
                 }
 
-                Event::Rule => {
+                (_, NodeKind::Rule) => {
                     writer.write_all(br"\par\noindent\hrulefill\par")?;
                 }
+
+                (Visit::Leaf, _) => unreachable!("only leaf nodes are visited as leaves"),
             }
+        }
 
-            first_event = false;
+        escaper.finish()?;
+        html.close_all(writer)?;
+        quotes.close_all(writer)?;
+        if let Some(section) = special_section {
+            close_special_section(section, writer)?;
+        }
+        if !self.bibliography.is_empty() && !bibliography_written {
+            write_bibliography_commands(writer)?;
+        }
+        if !open_tasks.is_empty() {
+            tasks::write_summary(&open_tasks, &self.options, writer, image_callback)?;
         }
 
-        writer.limit_newlines(3);
-        writer.add_newlines(3);
-        writer.write_all(b"\\end{document}\n")?;
+        Ok(())
+    }
+}
 
-        writer.into_inner()
+/// A span of text that is typeset in a special way.
+enum Annotation {
+    Citation(Citation),
+    TheoremReference(TheoremReference),
+}
+
+impl Annotation {
+    fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            Annotation::Citation(citation) => write_citation(citation, writer),
+            Annotation::TheoremReference(reference) => theorem::write_reference(reference, writer),
+        }
+    }
+}
+
+/// Returns the text of a heading, given its markdown source.
+fn heading_text(source: &str) -> &str {
+    let source = source.trim();
+    if source.starts_with('#') {
+        // ATX heading, possibly with a closing sequence of `#` characters.
+        let text = source.trim_start_matches('#');
+        let without_closing = text.trim_end_matches('#');
+        if without_closing.is_empty() || without_closing.ends_with(char::is_whitespace) {
+            without_closing.trim()
+        } else {
+            text.trim()
+        }
+    } else {
+        // Setext heading: strip the underline.
+        source
+            .rfind('\n')
+            .map_or(source, |line_end| source[..line_end].trim())
+    }
+}
+
+fn write_bibliography_commands<W: Write>(
+    writer: &mut WhitespaceFormatter<W>,
+) -> std::io::Result<()> {
+    let file_stem = BIBTEX_FILE_NAME.trim_end_matches(".bib");
+    writer.add_newlines(3);
+    writer.write_on_single_line(r"\bibliographystyle{plainnat}")?;
+    writer.write_on_single_line(&format!(r"\bibliography{{{}}}", file_stem))?;
+    writer.add_newlines(2);
+    Ok(())
+}
+
+fn write_bibitem<W: Write>(
+    index: usize,
+    writer: &mut WhitespaceFormatter<W>,
+) -> std::io::Result<()> {
+    writer.add_newlines(1);
+    write!(writer, r"\bibitem{{ref{}}} ", index)?;
+    writer.limit_newlines(0);
+    Ok(())
+}
+
+fn close_special_section<W: Write>(
+    section: SectionKind,
+    writer: &mut WhitespaceFormatter<W>,
+) -> std::io::Result<()> {
+    match section {
+        SectionKind::Abstract => {
+            writer.limit_newlines(1);
+            writer.write_on_single_line(r"\end{abstract}")?;
+            writer.add_newlines(2);
+        }
+        SectionKind::Bibliography => {
+            writer.decrease_indent();
+            writer.limit_newlines(1);
+            writer.write_on_single_line(r"\end{thebibliography}")?;
+            writer.add_newlines(2);
+        }
+        SectionKind::Appendix => {}
+    }
+    Ok(())
+}
+
+/// Writes the title, authors, date, and abstract from the front matter.
+///
+/// Does nothing if the front matter has neither a title nor an abstract.
+fn write_title_block<W: Write>(
+    metadata: &DocumentMetadata,
+    options: &ConversionOptions,
+    writer: &mut WhitespaceFormatter<W>,
+    image_callback: &mut dyn FnMut(&str) -> Option<String>,
+) -> std::io::Result<()> {
+    if let Some(title) = &metadata.title {
+        writer.write_all(b"\n\\title{")?;
+        write_inline_markdown(title, options, writer, image_callback)?;
+        writer.write_all(b"}")?;
+        write_title_details(metadata, options, writer, image_callback)
+    } else {
+        if !metadata.authors.is_empty() || metadata.date.is_some() {
+            warn!("Ignoring authors and date in front matter because the document has no title.");
+        }
+        write_abstract(metadata, options, writer, image_callback)
+    }
+}
+
+/// Writes the authors, date, and abstract that follow the `\title`.
+fn write_title_details<W: Write>(
+    metadata: &DocumentMetadata,
+    options: &ConversionOptions,
+    writer: &mut WhitespaceFormatter<W>,
+    image_callback: &mut dyn FnMut(&str) -> Option<String>,
+) -> std::io::Result<()> {
+    let mut escaper =
+        LatexEscaper::with_unicode(writer.get_mut()?, options.unicode_escaping().cloned());
+
+    if !metadata.authors.is_empty() {
+        escaper.get_mut().write_all(b"\n\\author{")?;
+        for (i, author) in metadata.authors.iter().enumerate() {
+            if i != 0 {
+                escaper.get_mut().write_all(br" \and ")?;
+            }
+            escaper.write_all(author.name.as_bytes())?;
+            escaper.finish()?;
+            for affiliation in &author.affiliations {
+                escaper.get_mut().write_all(br"\\")?;
+                escaper.write_all(affiliation.as_bytes())?;
+                escaper.finish()?;
+            }
+            if let Some(email) = &author.email {
+                escaper.get_mut().write_all(br"\\\texttt{")?;
+                escaper.write_all(email.as_bytes())?;
+                escaper.finish()?;
+                escaper.get_mut().write_all(b"}")?;
+            }
+        }
+        escaper.get_mut().write_all(b"}")?;
+    }
+
+    if let Some(date) = &metadata.date {
+        escaper.get_mut().write_all(b"\n\\date{")?;
+        escaper.write_all(date.as_bytes())?;
+        escaper.finish()?;
+        escaper.get_mut().write_all(b"}")?;
+    }
+
+    writer.write_all(b"\n\\maketitle")?;
+    writer.add_newlines(2);
+    write_abstract(metadata, options, writer, image_callback)
+}
+
+fn write_abstract<W: Write>(
+    metadata: &DocumentMetadata,
+    options: &ConversionOptions,
+    writer: &mut WhitespaceFormatter<W>,
+    image_callback: &mut dyn FnMut(&str) -> Option<String>,
+) -> std::io::Result<()> {
+    if let Some(abstract_text) = &metadata.abstract_text {
+        writer.write_on_single_line(r"\begin{abstract}")?;
+        writer.limit_newlines(1);
+        let options = ConversionOptions {
+            title_from_first_heading: false,
+            ..options.clone()
+        };
+        MarkdownToLatex::without_front_matter(abstract_text.clone(), options)
+            .write_body(writer, image_callback)?;
+        writer.limit_newlines(1);
+        writer.write_on_single_line(r"\end{abstract}")?;
+        writer.add_newlines(2);
+    }
+    Ok(())
+}
+
+/// Writes the contents of a code block as a `verbatim` environment.
+///
+/// If the code contains `\end{verbatim}`, which would end the environment early,
+/// falls back to one `\verb` command per line instead.
+fn write_code_block<W: Write>(
+    code: &str,
+    writer: &mut WhitespaceFormatter<W>,
+) -> std::io::Result<()> {
+    if !code.contains(r"\end{verbatim}") {
+        writer.write_on_single_line(r"\begin{verbatim}")?;
+        writer.limit_newlines(1);
+        writer.write_all(code.as_bytes())?;
+        if !code.is_empty() && !code.ends_with('\n') {
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(br"\end{verbatim}")?;
+        writer.add_newlines(1);
+        return Ok(());
+    }
+
+    writer.write_on_single_line(r"\begin{flushleft}")?;
+    writer.add_newlines(1);
+    let lines: Vec<_> = code.lines().collect();
+    for (index, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            writer.write_all(br"\mbox{}")?;
+        } else if let Some(delimiter) = "|!+=@/~^".chars().find(|&c| !line.contains(c)) {
+            write!(writer, r"\verb{}{}{}", delimiter, line, delimiter)?;
+        } else {
+            writer.write_all(br"\texttt{")?;
+            latex_escape::escape_str_and_unicode(line, &mut *writer)?;
+            writer.write_all(b"}")?;
+        }
+        if index + 1 != lines.len() {
+            writer.write_all(br" \\")?;
+        }
+        writer.add_newlines(1);
+    }
+    writer.write_on_single_line(r"\end{flushleft}")?;
+    writer.add_newlines(1);
+    Ok(())
+}
+
+/// Writes a short snippet of markdown (e.g., a title) without surrounding newlines.
+fn write_inline_markdown<W: Write>(
+    markdown: &str,
+    options: &ConversionOptions,
+    writer: &mut WhitespaceFormatter<W>,
+    image_callback: &mut dyn FnMut(&str) -> Option<String>,
+) -> std::io::Result<()> {
+    let options = ConversionOptions {
+        title_from_first_heading: false,
+        // The language has to match the one of the preamble.
+        detect_language: false,
+        ..options.clone()
+    };
+    writer.limit_newlines(0);
+    MarkdownToLatex::without_front_matter(markdown.to_string(), options)
+        .write_body(writer, image_callback)?;
+    writer.limit_newlines(0);
+    Ok(())
+}
+
+/// Returns the (1-based) line number of byte offset `pos` in `text`.
+fn line_number(text: &str, pos: usize) -> usize {
+    text.as_bytes()[..pos]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+        + 1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn convert(markdown: &str, options: ConversionOptions) -> String {
+        MarkdownToLatex::with_options(markdown.to_string(), options).into_string()
+    }
+
+    /// Returns the part of the LaTeX output between `\begin{document}` and
+    /// `\end{document}`.
+    fn body(latex: &str) -> &str {
+        let start = latex.find("\\begin{document}\n").expect("begin") + 17;
+        let end = latex.rfind("\\end{document}").expect("end");
+        &latex[start..end]
+    }
+
+    #[test]
+    fn image_file_names() {
+        let markdown = "![x](a&#10;\\input{/etc/passwd})\n\n![y](figure.png)\n";
+        let latex = MarkdownToLatex::from_string(markdown.to_string())
+            .into_string_with_image_callback(&mut |url| Some(url.to_string()));
+        assert!(body(&latex).contains(
+            "%\\includegraphics[width=\\textwidth]{a\\%0A\\%5Cinput\\%7B/etc/passwd\\%7D}\n"
+        ));
+        assert!(body(&latex).contains("\n\\includegraphics[width=\\textwidth]{figure.png}\n"));
+
+        let latex = convert("![x](a&#13;b)", ConversionOptions::default());
+        assert!(body(&latex).contains("%\\includegraphics[width=\\textwidth]{a\\%0Db}\n"));
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let options = ConversionOptions {
+            extensions: MarkdownExtensions {
+                heading_attributes: true,
+                ..ConversionOptions::default().extensions
+            },
+            ..ConversionOptions::default()
+        };
+        let latex = convert(
+            "# Title\n\n## Abstract\n\nWe study *things*.\n\n## Intro {#sec:intro}\n\n\
+             Text with *em*, **strong**, `code`, and a [link](https://example.com).\n\n\
+             > A quote\n> - with a list\n\n1. one\n2. two\n   - nested\n\n---\n",
+            options,
+        );
+
+        assert_eq!(
+            body(&latex),
+            "\n\\title{Title}\n\\maketitle\n\n\
+             \\begin{abstract}\nWe study \\emph{things}.\n\\end{abstract}\n\n\n\
+             \\subsection{Intro}\\label{sec:intro}\n\n\
+             Text with \\emph{em}, \\textbf{strong}, \\texttt{code}, and a \
+             \\href{https://example.com}{link}.\n\n\
+             \\begin{quote}\n\nA quote\n\n\
+             \\begin{itemize}\n  \\item with a list\n\\end{itemize}\n\\end{quote}\n\n\
+             \\begin{enumerate}\n  \\item one\n  \\item two\n\
+             \x20   \\begin{itemize}\n      \\item nested\n    \\end{itemize}\n\
+             \\end{enumerate}\n\\par\\noindent\\hrulefill\\par\n\n\n"
+        );
+    }
+
+    #[test]
+    fn no_fancy_lists_by_default() {
+        let latex = convert("e. coli is a bacterium.", ConversionOptions::default());
+        assert!(latex.contains("\ne. coli is a bacterium.\n"));
+        assert!(!latex.contains("enumerate"));
+    }
+
+    #[test]
+    fn heading_in_list_of_special_section() {
+        let latex = convert(
+            "# Title\n\n# References\n\nA reference.\n\n- # Appendix\n",
+            ConversionOptions::default(),
+        );
+
+        assert!(latex.contains("\\begin{thebibliography}{99}"));
+        assert!(!latex.contains("\\appendix"));
+        assert!(latex.find("\\section{Appendix}") < latex.find("\\end{thebibliography}"));
+    }
+
+    #[test]
+    fn description_items() {
+        let options = ConversionOptions {
+            description_lists: true,
+            extensions: MarkdownExtensions {
+                definition_lists: true,
+                ..ConversionOptions::default().extensions
+            },
+            ..ConversionOptions::default()
+        };
+        let latex = convert(
+            "- **See [1]:** first\n- **Plain**: second\n\nTerm [a]\n: Definition\n",
+            options,
+        );
+
+        assert_eq!(
+            body(&latex).trim(),
+            "\\begin{description}\n  \\item[{See [1]}] first\n  \\item[{Plain}] second\n\
+             \\end{description}\n\\begin{description}\n  \\item[{Term [a]}]\n    Definition\n\
+             \\end{description}"
+        );
+    }
+
+    #[test]
+    fn heading_in_quote_of_replaced_bibliography() {
+        let options = ConversionOptions {
+            citation_syntax: true,
+            ..ConversionOptions::default()
+        };
+        let latex = convert(
+            "# Title\n\nSee @key.\n\n# References\n\n> # Appendix\n\n- # Notes\n\n# Next\n",
+            options,
+        );
+
+        assert!(latex.contains("\\bibliography{references}"));
+        assert!(!latex.contains("Appendix") && !latex.contains("Notes"));
+        assert!(latex.contains("\\section{Next}"));
     }
 }
@@ -0,0 +1,572 @@
+//! Support for ordered lists with alphabetic or Roman numbering and for lists that
+//! are nested deeper than standard LaTeX allows.
+
+use crate::preamble::Preamble;
+use crate::preprocess::{
+    code_fence, find_line_end, is_blank_line, skip_fenced_code_block, strip_line_prefix,
+};
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// Standard LaTeX supports at most four levels of `enumerate` and of `itemize`, and
+/// at most six levels of nested list environments (including `quote`) in total.
+const MAX_LATEX_NESTING: usize = 4;
+const MAX_LATEX_TOTAL_NESTING: usize = 6;
+
+/// Default labels for levels beyond the fourth, repeating the standard labels.
+const ENUMERATE_LABELS: [&str; 4] = [r"\arabic*.", r"(\alph*)", r"\roman*.", r"\Alph*."];
+const ITEMIZE_LABELS: [&str; 4] = [
+    r"\textbullet",
+    r"\textendash",
+    r"\textasteriskcentered",
+    r"\textperiodcentered",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberingStyle {
+    LowerAlpha,
+    UpperAlpha,
+    LowerRoman,
+    UpperRoman,
+}
+
+impl NumberingStyle {
+    fn is_roman(self) -> bool {
+        matches!(
+            self,
+            NumberingStyle::LowerRoman | NumberingStyle::UpperRoman
+        )
+    }
+
+    fn is_uppercase(self) -> bool {
+        matches!(
+            self,
+            NumberingStyle::UpperAlpha | NumberingStyle::UpperRoman
+        )
+    }
+}
+
+/// A list item marker such as "b." or "iv)" that was replaced by digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListMarker {
+    /// Byte offset of the marker in the markdown source.
+    pub pos: usize,
+    pub style: NumberingStyle,
+    /// Either `'.'` or `')'`.
+    pub delimiter: char,
+    pub number: u32,
+}
+
+impl ListMarker {
+    /// Returns the `enumitem` label, e.g., `\alph*)`.
+    pub fn label(&self) -> String {
+        let counter = match self.style {
+            NumberingStyle::LowerAlpha => r"\alph*",
+            NumberingStyle::UpperAlpha => r"\Alph*",
+            NumberingStyle::LowerRoman => r"\roman*",
+            NumberingStyle::UpperRoman => r"\Roman*",
+        };
+        format!("{}{}", counter, self.delimiter)
+    }
+}
+
+/// A possible list item marker, before deciding between letters and Roman numerals.
+struct Candidate {
+    pos: usize,
+    /// Length of the marker without delimiter.
+    len: usize,
+    uppercase: bool,
+    delimiter: char,
+    /// The number if the marker is a letter.
+    alpha: Option<u32>,
+    /// The number if the marker is a Roman numeral.
+    roman: Option<u32>,
+    /// Whether the marker is in the same list as the previous candidate.
+    continues: bool,
+}
+
+/// Replaces alphabetic and Roman list item markers (e.g., "a.", "B)", or "iv.") by
+/// decimal markers of the same length so that pulldown-cmark recognizes them as
+/// ordered list items. Byte offsets don't change.
+///
+/// The replaced markers use the other delimiter (e.g., "a." becomes "1)") so that
+/// pulldown-cmark starts a new list where the numbering style changes (e.g., from
+/// "b." to "1." or from "c." to "vi."). The original delimiter is kept in the
+/// returned `ListMarker`s.
+///
+/// Only lines that start a new block (after a blank line, a heading, or at the start
+/// of the document) or that follow a list item are considered, so that a line like
+/// "i. e., ..." within a paragraph is left alone. As in pandoc, an uppercase letter
+/// followed by a period must be followed by at least two spaces (to avoid
+/// misinterpreting initials such as "A. Smith"). A single letter that is also a
+/// Roman numeral (e.g., "v" or "c") is read as a Roman numeral if it succeeds the
+/// previous Roman numeral in the list or if the next item succeeds it as a Roman
+/// numeral (e.g., "v." followed by "vi."), and as a letter if the same holds for
+/// letters; only neighboring items of the same case are considered. Otherwise, only
+/// "i" and "I" are Roman numerals. Fenced code blocks are
+/// skipped.
+///
+/// Markers that pulldown-cmark (with `parser_options`) doesn't parse as list items
+/// are restored. Returns the replaced markers sorted by position.
+pub fn replace_list_markers(input: &mut String, parser_options: Options) -> Vec<ListMarker> {
+    let candidates = find_candidates(input.as_bytes());
+    let mut markers = Vec::<ListMarker>::with_capacity(candidates.len());
+    let mut replacements = Vec::with_capacity(candidates.len());
+
+    for (i, candidate) in candidates.iter().enumerate() {
+        let previous = markers.last().filter(|_| candidate.continues);
+        // Only neighbors of the same case decide between letters and Roman numerals
+        // so that they end up in the same list.
+        let same_case_previous =
+            previous.filter(|previous| previous.style.is_uppercase() == candidate.uppercase);
+        let same_case_next = candidates
+            .get(i + 1)
+            .filter(|next| next.continues && next.uppercase == candidate.uppercase);
+        let (is_roman, number) = match (candidate.alpha, candidate.roman) {
+            (Some(alpha), Some(roman)) => {
+                let successor = same_case_previous.map(|p| (p.style.is_roman(), p.number + 1));
+                let is_roman = match successor {
+                    Some((true, successor)) if successor == roman => true,
+                    Some((false, successor)) if successor == alpha => false,
+                    _ => match same_case_next {
+                        Some(next) if next.roman == Some(roman + 1) => true,
+                        Some(next) if next.alpha == Some(alpha + 1) => false,
+                        _ => roman == 1,
+                    },
+                };
+                (is_roman, if is_roman { roman } else { alpha })
+            }
+            (Some(alpha), None) => (false, alpha),
+            (None, Some(roman)) => (true, roman),
+            (None, None) => unreachable!("candidates are letters or Roman numerals"),
+        };
+        let style = match (is_roman, candidate.uppercase) {
+            (false, false) => NumberingStyle::LowerAlpha,
+            (false, true) => NumberingStyle::UpperAlpha,
+            (true, false) => NumberingStyle::LowerRoman,
+            (true, true) => NumberingStyle::UpperRoman,
+        };
+
+        let opposite = if candidate.delimiter == '.' {
+            b')'
+        } else {
+            b'.'
+        };
+        let replacement_delimiter = match (previous, replacements.last()) {
+            (Some(previous), Some(&(_, _, _, previous_delimiter))) => {
+                if previous.style == style {
+                    previous_delimiter
+                } else if previous_delimiter == opposite {
+                    candidate.delimiter as u8
+                } else {
+                    opposite
+                }
+            }
+            _ => opposite,
+        };
+
+        markers.push(ListMarker {
+            pos: candidate.pos,
+            style,
+            delimiter: candidate.delimiter,
+            number,
+        });
+        replacements.push((candidate.pos, candidate.len, number, replacement_delimiter));
+    }
+
+    let mut originals = Vec::with_capacity(replacements.len());
+    for (pos, len, number, delimiter) in replacements {
+        // Any decimal number of the right length will do for items after the first
+        // one because pulldown-cmark only uses the number of the first item.
+        let digits = format!("{:0width$}", number, width = len);
+        let digits = if digits.len() == len {
+            digits
+        } else {
+            "1".repeat(len)
+        };
+        originals.push(input[pos..pos + len + 1].to_string());
+        input.replace_range(pos..pos + len, &digits);
+        input.replace_range(pos + len..pos + len + 1, &(delimiter as char).to_string());
+    }
+
+    // Restore markers that didn't become list items after all (e.g., because they
+    // are part of a paragraph outside of the list), so that their digits don't show
+    // up in the output.
+    if !markers.is_empty() {
+        let items = Parser::new_ext(input, parser_options)
+            .into_offset_iter()
+            .filter(|(event, _)| matches!(event, Event::Start(Tag::Item)))
+            .map(|(_, range)| range.start)
+            .collect::<Vec<_>>();
+        let mut items = items.into_iter().peekable();
+        let mut originals = originals.into_iter();
+        markers.retain(|marker| {
+            let original = originals.next().expect("one original per marker");
+            while items.next_if(|&start| start < marker.pos).is_some() {}
+            if items.peek() == Some(&marker.pos) {
+                true
+            } else {
+                input.replace_range(marker.pos..marker.pos + original.len(), &original);
+                false
+            }
+        });
+    }
+
+    markers
+}
+
+/// Finds all lines that may start with an alphabetic or Roman list item marker, see
+/// `replace_list_markers`.
+fn find_candidates(bytes: &[u8]) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    let mut i = 0;
+    let mut block_start = true;
+    let mut in_list = false;
+    let mut previous_indent = None;
+
+    while i < bytes.len() {
+        let line_end = find_line_end(bytes, i);
+        let line = &bytes[i..line_end];
+        if let Some(fence) = code_fence(line) {
+            i = skip_fenced_code_block(bytes, line_end, fence);
+            block_start = true;
+            continue;
+        }
+
+        if is_blank_line(line) {
+            block_start = true;
+        } else {
+            let content = strip_line_prefix(line);
+            let indent = line.len() - content.len();
+            let candidate = if block_start || in_list {
+                parse_marker(content)
+            } else {
+                None
+            };
+
+            if let Some(candidate) = candidate {
+                candidates.push(Candidate {
+                    pos: i + indent,
+                    continues: in_list && previous_indent == Some(indent),
+                    ..candidate
+                });
+                in_list = true;
+                previous_indent = Some(indent);
+            } else if is_list_item(content) {
+                in_list = true;
+            } else if block_start && indent == 0 {
+                in_list = false;
+                previous_indent = None;
+            }
+            block_start = content.starts_with(b"#");
+        }
+
+        i = line_end + 1;
+    }
+
+    candidates
+}
+
+/// Parses an alphabetic or Roman list item marker at the start of `content`. The
+/// fields `pos` and `continues` of the result are left for the caller.
+fn parse_marker(content: &[u8]) -> Option<Candidate> {
+    let len = content
+        .iter()
+        .take_while(|b| b.is_ascii_alphabetic())
+        .count();
+    if len == 0 || len > 4 {
+        return None;
+    }
+    let delimiter = match content.get(len) {
+        Some(b'.') => '.',
+        Some(b')') => ')',
+        _ => return None,
+    };
+    let rest = &content[len + 1..];
+    let uppercase = content[0].is_ascii_uppercase();
+    let min_spaces = if uppercase && delimiter == '.' { 2 } else { 1 };
+    if rest.iter().take_while(|&&b| b == b' ').count() < min_spaces
+        && !(rest.is_empty() && min_spaces == 1)
+    {
+        return None;
+    }
+
+    let marker = std::str::from_utf8(&content[..len]).expect("ASCII");
+    let lowercase = marker.to_ascii_lowercase();
+    if lowercase != marker && marker.to_ascii_uppercase() != marker {
+        return None; // Mixed case.
+    }
+
+    let alpha = Some(u32::from(lowercase.as_bytes()[0] - b'a') + 1).filter(|_| len == 1);
+    let roman = parse_roman(&lowercase);
+    if alpha.is_none() && roman.is_none() {
+        return None;
+    }
+    Some(Candidate {
+        pos: 0,
+        len,
+        uppercase,
+        delimiter,
+        alpha,
+        roman,
+        continues: false,
+    })
+}
+
+/// Parses a lowercase Roman numeral in canonical form.
+fn parse_roman(s: &str) -> Option<u32> {
+    let value = |c| match c {
+        'i' => Some(1),
+        'v' => Some(5),
+        'x' => Some(10),
+        'l' => Some(50),
+        'c' => Some(100),
+        'd' => Some(500),
+        'm' => Some(1000),
+        _ => None,
+    };
+    let values = s.chars().map(value).collect::<Option<Vec<u32>>>()?;
+    let mut number = 0i64;
+    for (i, &v) in values.iter().enumerate() {
+        if values.get(i + 1).is_some_and(|&next| next > v) {
+            number -= i64::from(v);
+        } else {
+            number += i64::from(v);
+        }
+    }
+
+    if number <= 0 || number > 4000 {
+        return None;
+    }
+    let number = number as u32;
+    if to_roman(number) == s {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+fn to_roman(mut number: u32) -> String {
+    const NUMERALS: [(u32, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut roman = String::new();
+    for &(value, numeral) in NUMERALS.iter() {
+        while number >= value {
+            roman.push_str(numeral);
+            number -= value;
+        }
+    }
+    roman
+}
+
+/// Checks if a line (without indentation) starts with a bullet or decimal list marker.
+pub fn is_list_item(content: &[u8]) -> bool {
+    let digits = content.iter().take_while(|b| b.is_ascii_digit()).count();
+    let marker_len = match content.first() {
+        Some(b'-') | Some(b'*') | Some(b'+') => 1,
+        _ if (1..10).contains(&digits)
+            && matches!(content.get(digits), Some(b'.') | Some(b')')) =>
+        {
+            digits + 1
+        }
+        _ => return false,
+    };
+    content
+        .get(marker_len)
+        .is_none_or(|&b| b == b' ' || b == b'\t')
+}
+
+/// The maximal nesting depths of lists in a document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListNesting {
+    pub enumerate: usize,
+    pub itemize: usize,
+    /// Nesting of all list-like environments, including `quote`.
+    pub total: usize,
+}
+
+impl ListNesting {
+    pub fn of(markdown: &str, parser_options: Options) -> Self {
+        let mut max = Self::default();
+        let mut current = Self::default();
+        for event in Parser::new_ext(markdown, parser_options) {
+            match event {
+                Event::Start(Tag::List(first_number)) => {
+                    if first_number.is_some() {
+                        current.enumerate += 1;
+                    } else {
+                        current.itemize += 1;
+                    }
+                    current.total += 1;
+                }
+                Event::End(Tag::List(first_number)) => {
+                    if first_number.is_some() {
+                        current.enumerate -= 1;
+                    } else {
+                        current.itemize -= 1;
+                    }
+                    current.total -= 1;
+                }
+                Event::Start(Tag::BlockQuote) => current.total += 1,
+                Event::End(Tag::BlockQuote) => current.total -= 1,
+                _ => continue,
+            }
+            max.enumerate = std::cmp::max(max.enumerate, current.enumerate);
+            max.itemize = std::cmp::max(max.itemize, current.itemize);
+            max.total = std::cmp::max(max.total, current.total);
+        }
+        max
+    }
+
+    /// Returns `true` if standard LaTeX can't typeset lists this deeply nested.
+    pub fn exceeds_latex(&self) -> bool {
+        self.enumerate > MAX_LATEX_NESTING
+            || self.itemize > MAX_LATEX_NESTING
+            || self.total > MAX_LATEX_TOTAL_NESTING
+    }
+
+    /// Adds `enumitem` declarations that allow for the nesting depths.
+    pub fn declare(&self, preamble: &mut Preamble) {
+        preamble.use_package("enumitem", "");
+        if self.total > MAX_LATEX_TOTAL_NESTING {
+            preamble.add_line(format!(r"\setlistdepth{{{}}}", self.total));
+        }
+        for &(list, depth, labels) in [
+            ("enumerate", self.enumerate, ENUMERATE_LABELS),
+            ("itemize", self.itemize, ITEMIZE_LABELS),
+        ]
+        .iter()
+        {
+            if depth > MAX_LATEX_NESTING {
+                preamble.add_line(format!(r"\renewlist{{{0}}}{{{0}}}{{{1}}}", list, depth));
+                for level in MAX_LATEX_NESTING + 1..=depth {
+                    preamble.add_line(format!(
+                        r"\setlist[{},{}]{{label={}}}",
+                        list,
+                        level,
+                        labels[(level - 1) % labels.len()]
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn replace(markdown: &str) -> (String, Vec<(usize, String, u32)>) {
+        let mut markdown = markdown.to_string();
+        let markers = replace_list_markers(&mut markdown, Options::empty())
+            .into_iter()
+            .map(|marker| (marker.pos, marker.label(), marker.number))
+            .collect();
+        (markdown, markers)
+    }
+
+    #[test]
+    fn list_markers() {
+        let l = |s: &str| s.to_string();
+
+        assert_eq!(
+            replace("a. one\nb. two\n   i) sub\n   ii) sub\n"),
+            (
+                l("1) one\n2) two\n   1. sub\n   02. sub\n"),
+                vec![
+                    (0, l(r"\alph*."), 1),
+                    (7, l(r"\alph*."), 2),
+                    (17, l(r"\roman*)"), 1),
+                    (27, l(r"\roman*)"), 2),
+                ]
+            )
+        );
+        assert_eq!(
+            replace("Text\n\nC.  third\nIV.  fourth\n"),
+            (
+                l("Text\n\n3)  third\n04.  fourth\n"),
+                vec![(6, l(r"\Alph*."), 3), (16, l(r"\Roman*."), 4)]
+            )
+        );
+        assert_eq!(
+            replace("h. eight\ni. nine"),
+            (
+                l("8) eight\n9) nine"),
+                vec![(0, l(r"\alph*."), 8), (9, l(r"\alph*."), 9)]
+            )
+        );
+        assert_eq!(
+            replace("x. ten\n\nvi. six"),
+            (
+                l("1) ten\n\n06. six"),
+                vec![(0, l(r"\alph*."), 24), (8, l(r"\roman*."), 6)]
+            )
+        );
+
+        assert_eq!(
+            replace("v. five\nvi. six"),
+            (
+                l("5) five\n06) six"),
+                vec![(0, l(r"\roman*."), 5), (8, l(r"\roman*."), 6)]
+            )
+        );
+        assert_eq!(
+            replace("vi. six\n\nc. x\nd. y"),
+            (
+                l("06) six\n\n3. x\n4. y"),
+                vec![
+                    (0, l(r"\roman*."), 6),
+                    (9, l(r"\alph*."), 3),
+                    (14, l(r"\alph*."), 4),
+                ]
+            )
+        );
+        assert_eq!(
+            replace("i. nine\nj. ten"),
+            (
+                l("9) nine\n1) ten"),
+                vec![(0, l(r"\alph*."), 9), (8, l(r"\alph*."), 10)]
+            )
+        );
+
+        // Not list items.
+        for markdown in &[
+            "Text\ni. e., not a list",
+            "A. Smith wrote this.",
+            "iiii. not roman",
+            "```\na. code\n```",
+            "Ab. mixed case",
+            "- a\n\n b\nvi. continues the paragraph",
+        ] {
+            assert_eq!(replace(markdown), (l(markdown), vec![]));
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        let markdown = "- 1\n  1. 2\n     - 3\n       > - 4\n       >   - 5\n       >     - 6";
+        let nesting = ListNesting::of(markdown, Options::empty());
+        assert_eq!(
+            nesting,
+            ListNesting {
+                enumerate: 1,
+                itemize: 5,
+                total: 7
+            }
+        );
+        assert!(nesting.exceeds_latex());
+    }
+}
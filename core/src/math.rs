@@ -0,0 +1,951 @@
+//! Lightweight tokenizer and validator for LaTeX math spans.
+//!
+//! Math spans are copied verbatim into the generated LaTeX code. A single unbalanced
+//! brace or a stray `\end{...}` in a math span therefore breaks compilation of the
+//! entire document, and pdflatex usually reports the error far away from its cause.
+//! This module catches the most common mistakes early so that they can be reported
+//! with a position in the markdown source.
+//!
+//! The tokenizer is not a full TeX parser. It only understands enough of TeX's
+//! lexical structure to find groups (`{...}`), delimiter pairs (`\left...\right`),
+//! and environments (`\begin{...}...\end{...}`).
+//!
+//! For untrusted input, [`check_safe_math`](fn.check_safe_math.html) additionally
+//! restricts math spans to a whitelist of harmless commands and environments.
+
+use std::fmt;
+
+/// A lexical token of a LaTeX math span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// A control word such as `\alpha`, without the leading backslash.
+    ControlWord(&'a str),
+
+    /// A control symbol such as `\{` or `\\`, without the leading backslash.
+    ControlSymbol(char),
+
+    /// An unescaped `{`.
+    BeginGroup,
+
+    /// An unescaped `}`.
+    EndGroup,
+
+    /// A comment starting with `%`, up to but not including the end of the line.
+    Comment(&'a str),
+
+    /// Any other character.
+    Other(char),
+}
+
+/// An iterator over the [`Token`](enum.Token.html)s of a LaTeX math span.
+///
+/// Yields tuples `(pos, token)` where `pos` is the byte offset of the token in the
+/// input string. Whitespace is skipped.
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::math::{Token, Tokenizer};
+///
+/// let tokens = Tokenizer::new(r"\frac{a}{\{b\}}").map(|(_, t)| t).collect::<Vec<_>>();
+/// assert_eq!(
+///     tokens,
+///     [
+///         Token::ControlWord("frac"),
+///         Token::BeginGroup,
+///         Token::Other('a'),
+///         Token::EndGroup,
+///         Token::BeginGroup,
+///         Token::ControlSymbol('{'),
+///         Token::Other('b'),
+///         Token::ControlSymbol('}'),
+///         Token::EndGroup,
+///     ]
+/// );
+/// ```
+pub struct Tokenizer<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Creates a tokenizer over the math span `src` (without the math delimiters).
+    pub fn new(src: &'a str) -> Self {
+        Self { src, pos: 0 }
+    }
+
+    /// Returns the part of the input that has not yet been tokenized.
+    pub fn remainder(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    /// Reads an argument in curly braces such as the environment name in
+    /// `\begin{name}`, skipping any whitespace before the opening brace.
+    ///
+    /// Returns `None` and does not advance the tokenizer if the remaining input
+    /// does not start with such an argument.
+    fn read_braced_argument(&mut self) -> Option<&'a str> {
+        let remainder = self.remainder();
+        let trimmed = remainder.trim_start();
+        if !trimmed.starts_with('{') {
+            return None;
+        }
+        let len = trimmed.find('}')?;
+        let argument = &trimmed[1..len];
+        self.pos += remainder.len() - trimmed.len() + len + 1;
+        Some(argument.trim())
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = (usize, Token<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remainder = self.remainder();
+        let trimmed = remainder.trim_start();
+        self.pos += remainder.len() - trimmed.len();
+        let start = self.pos;
+
+        let mut chars = trimmed.chars();
+        let token = match chars.next()? {
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let len = trimmed[1..]
+                        .find(|c: char| !c.is_ascii_alphabetic())
+                        .unwrap_or(trimmed.len() - 1);
+                    self.pos += len + 1;
+                    return Some((start, Token::ControlWord(&trimmed[1..len + 1])));
+                }
+                Some(c) => {
+                    self.pos += 1 + c.len_utf8();
+                    return Some((start, Token::ControlSymbol(c)));
+                }
+                None => Token::Other('\\'),
+            },
+            '%' => {
+                let len = trimmed.find('\n').unwrap_or(trimmed.len());
+                self.pos += len;
+                return Some((start, Token::Comment(&trimmed[1..len])));
+            }
+            '{' => Token::BeginGroup,
+            '}' => Token::EndGroup,
+            c => Token::Other(c),
+        };
+
+        self.pos += trimmed.len() - chars.as_str().len();
+        Some((start, token))
+    }
+}
+
+/// An error found by [`check_math`](fn.check_math.html).
+///
+/// All positions are byte offsets into the math span that was checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MathError {
+    /// A `}` without a matching `{`.
+    UnmatchedEndGroup { pos: usize },
+
+    /// A `{` that is never closed.
+    UnclosedGroup { pos: usize },
+
+    /// A `\right` without a matching `\left`.
+    UnmatchedRight { pos: usize },
+
+    /// A `\left` without a matching `\right`.
+    UnclosedLeft { pos: usize },
+
+    /// A `\begin` or `\end` that is not followed by an environment name in braces.
+    MissingEnvironmentName { pos: usize },
+
+    /// An `\end{name}` without any open environment.
+    UnmatchedEnd { pos: usize, name: String },
+
+    /// An `\end{found}` where `\end{expected}` was expected.
+    MismatchedEnd {
+        pos: usize,
+        expected: String,
+        found: String,
+    },
+
+    /// A `\begin{name}` that is never closed.
+    UnclosedBegin { pos: usize, name: String },
+
+    /// A command, environment, or character sequence that is not allowed in safe
+    /// mode (see [`check_safe_math`](fn.check_safe_math.html)), e.g., `"\\input"`.
+    NotAllowed { pos: usize, token: String },
+}
+
+impl MathError {
+    /// Returns the byte offset of the error within the checked math span.
+    pub fn pos(&self) -> usize {
+        match *self {
+            MathError::UnmatchedEndGroup { pos }
+            | MathError::UnclosedGroup { pos }
+            | MathError::UnmatchedRight { pos }
+            | MathError::UnclosedLeft { pos }
+            | MathError::MissingEnvironmentName { pos }
+            | MathError::UnmatchedEnd { pos, .. }
+            | MathError::MismatchedEnd { pos, .. }
+            | MathError::UnclosedBegin { pos, .. }
+            | MathError::NotAllowed { pos, .. } => pos,
+        }
+    }
+}
+
+impl fmt::Display for MathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MathError::UnmatchedEndGroup { .. } => write!(f, "unmatched \"}}\""),
+            MathError::UnclosedGroup { .. } => write!(f, "unclosed \"{{\""),
+            MathError::UnmatchedRight { .. } => write!(f, "\"\\right\" without \"\\left\""),
+            MathError::UnclosedLeft { .. } => write!(f, "\"\\left\" without \"\\right\""),
+            MathError::MissingEnvironmentName { .. } => {
+                write!(f, "missing environment name after \"\\begin\" or \"\\end\"")
+            }
+            MathError::UnmatchedEnd { name, .. } => {
+                write!(f, "\"\\end{{{}}}\" without \"\\begin{{{}}}\"", name, name)
+            }
+            MathError::MismatchedEnd {
+                expected, found, ..
+            } => write!(
+                f,
+                "found \"\\end{{{}}}\" but expected \"\\end{{{}}}\"",
+                found, expected
+            ),
+            MathError::UnclosedBegin { name, .. } => {
+                write!(f, "\"\\begin{{{}}}\" without \"\\end{{{}}}\"", name, name)
+            }
+            MathError::NotAllowed { token, .. } => {
+                write!(f, "\"{}\" is not allowed in safe mode", token)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MathError {}
+
+enum Opener<'a> {
+    Group,
+    Left,
+    Begin(&'a str),
+}
+
+/// Checks a math span for balanced braces, `\left`/`\right` pairs, and matching
+/// `\begin`/`\end` environments.
+///
+/// The argument `math` is the content of the math span without the surrounding
+/// math delimiters. Returns the first error found, if any. Groups, delimiter pairs
+/// and environments have to nest properly, i.e., `{\left( }\right)` is an error
+/// just like it is in TeX.
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::math::{check_math, MathError};
+///
+/// assert_eq!(check_math(r"\left(\frac{a}{b}\right)^{2}"), Ok(()));
+/// assert_eq!(check_math(r"\frac{a}{b"), Err(MathError::UnclosedGroup { pos: 8 }));
+/// ```
+pub fn check_math(math: &str) -> Result<(), MathError> {
+    let mut stack = Vec::new();
+    let mut tokenizer = Tokenizer::new(math);
+
+    while let Some((pos, token)) = tokenizer.next() {
+        match token {
+            Token::BeginGroup => stack.push((pos, Opener::Group)),
+            Token::EndGroup => match stack.pop() {
+                Some((_, Opener::Group)) => {}
+                Some((pos, Opener::Left)) => return Err(MathError::UnclosedLeft { pos }),
+                Some((pos, Opener::Begin(name))) => {
+                    return Err(MathError::UnclosedBegin {
+                        pos,
+                        name: name.to_string(),
+                    })
+                }
+                None => return Err(MathError::UnmatchedEndGroup { pos }),
+            },
+            Token::ControlWord("left") => stack.push((pos, Opener::Left)),
+            Token::ControlWord("right") => match stack.pop() {
+                Some((_, Opener::Left)) => {}
+                Some((pos, Opener::Group)) => return Err(MathError::UnclosedGroup { pos }),
+                Some((pos, Opener::Begin(name))) => {
+                    return Err(MathError::UnclosedBegin {
+                        pos,
+                        name: name.to_string(),
+                    })
+                }
+                None => return Err(MathError::UnmatchedRight { pos }),
+            },
+            Token::ControlWord("begin") => {
+                let name = tokenizer
+                    .read_braced_argument()
+                    .ok_or(MathError::MissingEnvironmentName { pos })?;
+                stack.push((pos, Opener::Begin(name)));
+            }
+            Token::ControlWord("end") => {
+                let name = tokenizer
+                    .read_braced_argument()
+                    .ok_or(MathError::MissingEnvironmentName { pos })?;
+                match stack.pop() {
+                    Some((_, Opener::Begin(expected))) if expected == name => {}
+                    Some((_, Opener::Begin(expected))) => {
+                        return Err(MathError::MismatchedEnd {
+                            pos,
+                            expected: expected.to_string(),
+                            found: name.to_string(),
+                        })
+                    }
+                    Some((pos, Opener::Group)) => return Err(MathError::UnclosedGroup { pos }),
+                    Some((pos, Opener::Left)) => return Err(MathError::UnclosedLeft { pos }),
+                    None => {
+                        return Err(MathError::UnmatchedEnd {
+                            pos,
+                            name: name.to_string(),
+                        })
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match stack.pop() {
+        None => Ok(()),
+        Some((pos, Opener::Group)) => Err(MathError::UnclosedGroup { pos }),
+        Some((pos, Opener::Left)) => Err(MathError::UnclosedLeft { pos }),
+        Some((pos, Opener::Begin(name))) => Err(MathError::UnclosedBegin {
+            pos,
+            name: name.to_string(),
+        }),
+    }
+}
+
+/// Checks if a math span ends in a comment, which would swallow the closing math
+/// delimiter unless a newline is inserted before it.
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::math::ends_with_comment;
+///
+/// assert!(ends_with_comment("x^2 % squared"));
+/// assert!(!ends_with_comment("x^2 % squared\n"));
+/// assert!(!ends_with_comment(r"50\%"));
+/// ```
+pub fn ends_with_comment(math: &str) -> bool {
+    Tokenizer::new(math)
+        .last()
+        .is_some_and(|(pos, token)| match token {
+            Token::Comment(comment) => pos + 1 + comment.len() == math.len(),
+            _ => false,
+        })
+}
+
+/// Control words that are allowed in math spans in safe mode, sorted.
+///
+/// None of these commands reads or writes files, changes category codes, or defines
+/// new macros.
+const SAFE_CONTROL_WORDS: &[&str] = &[
+    "Big",
+    "Bigg",
+    "Biggl",
+    "Biggr",
+    "Bigl",
+    "Bigr",
+    "Delta",
+    "Downarrow",
+    "Gamma",
+    "Im",
+    "Lambda",
+    "Leftarrow",
+    "Leftrightarrow",
+    "Longleftarrow",
+    "Longleftrightarrow",
+    "Longrightarrow",
+    "Omega",
+    "Phi",
+    "Pi",
+    "Pr",
+    "Psi",
+    "Re",
+    "Rightarrow",
+    "Sigma",
+    "Theta",
+    "Uparrow",
+    "Updownarrow",
+    "Upsilon",
+    "Vert",
+    "Xi",
+    "acute",
+    "aleph",
+    "alpha",
+    "amalg",
+    "angle",
+    "approx",
+    "approxeq",
+    "arccos",
+    "arcsin",
+    "arctan",
+    "arg",
+    "ast",
+    "asymp",
+    "backslash",
+    "bar",
+    "because",
+    "beta",
+    "big",
+    "bigcap",
+    "bigcirc",
+    "bigcup",
+    "bigg",
+    "biggl",
+    "biggr",
+    "bigl",
+    "bigodot",
+    "bigoplus",
+    "bigotimes",
+    "bigr",
+    "bigsqcup",
+    "bigtriangledown",
+    "bigtriangleup",
+    "biguplus",
+    "bigvee",
+    "bigwedge",
+    "binom",
+    "blacksquare",
+    "bmod",
+    "boldsymbol",
+    "bot",
+    "bowtie",
+    "boxed",
+    "breve",
+    "bullet",
+    "cap",
+    "cdot",
+    "cdots",
+    "cfrac",
+    "check",
+    "checkmark",
+    "chi",
+    "circ",
+    "clubsuit",
+    "colon",
+    "cong",
+    "coprod",
+    "cos",
+    "cosh",
+    "cot",
+    "coth",
+    "csc",
+    "cup",
+    "dagger",
+    "dashv",
+    "dbinom",
+    "ddagger",
+    "ddot",
+    "ddots",
+    "deg",
+    "delta",
+    "det",
+    "dfrac",
+    "diamond",
+    "diamondsuit",
+    "digamma",
+    "dim",
+    "displaystyle",
+    "div",
+    "dot",
+    "doteq",
+    "dots",
+    "dotsb",
+    "dotsc",
+    "dotsi",
+    "dotsm",
+    "downarrow",
+    "ell",
+    "emptyset",
+    "epsilon",
+    "equiv",
+    "eta",
+    "exists",
+    "exp",
+    "flat",
+    "forall",
+    "frac",
+    "frown",
+    "gamma",
+    "gcd",
+    "ge",
+    "geq",
+    "geqslant",
+    "gets",
+    "gg",
+    "ggg",
+    "grave",
+    "gtrsim",
+    "hat",
+    "hbar",
+    "heartsuit",
+    "hom",
+    "hookleftarrow",
+    "hookrightarrow",
+    "hphantom",
+    "iff",
+    "iiint",
+    "iint",
+    "imath",
+    "impliedby",
+    "implies",
+    "in",
+    "inf",
+    "infty",
+    "int",
+    "iota",
+    "jmath",
+    "kappa",
+    "ker",
+    "lVert",
+    "lambda",
+    "land",
+    "langle",
+    "lbrace",
+    "lbrack",
+    "lceil",
+    "ldots",
+    "le",
+    "leadsto",
+    "left",
+    "leftarrow",
+    "leftrightarrow",
+    "leq",
+    "leqslant",
+    "lesssim",
+    "lfloor",
+    "lg",
+    "lhd",
+    "lim",
+    "liminf",
+    "limits",
+    "limsup",
+    "ll",
+    "lll",
+    "ln",
+    "lnot",
+    "log",
+    "longleftarrow",
+    "longleftrightarrow",
+    "longmapsto",
+    "longrightarrow",
+    "lor",
+    "lvert",
+    "mapsto",
+    "mathbb",
+    "mathbf",
+    "mathcal",
+    "mathfrak",
+    "mathit",
+    "mathnormal",
+    "mathring",
+    "mathrm",
+    "mathsf",
+    "mathtt",
+    "max",
+    "mbox",
+    "mid",
+    "middle",
+    "min",
+    "mod",
+    "models",
+    "mp",
+    "mu",
+    "nabla",
+    "natural",
+    "ne",
+    "nearrow",
+    "neg",
+    "neq",
+    "nexists",
+    "ni",
+    "nmid",
+    "nolimits",
+    "nonumber",
+    "not",
+    "notag",
+    "notin",
+    "nu",
+    "nwarrow",
+    "odot",
+    "oint",
+    "omega",
+    "ominus",
+    "operatorname",
+    "oplus",
+    "oslash",
+    "otimes",
+    "overbrace",
+    "overleftarrow",
+    "overline",
+    "overrightarrow",
+    "overset",
+    "owns",
+    "parallel",
+    "partial",
+    "perp",
+    "phantom",
+    "phi",
+    "pi",
+    "pm",
+    "pmod",
+    "pod",
+    "prec",
+    "preceq",
+    "prime",
+    "prod",
+    "propto",
+    "psi",
+    "qquad",
+    "quad",
+    "rVert",
+    "rangle",
+    "rbrace",
+    "rbrack",
+    "rceil",
+    "rfloor",
+    "rhd",
+    "rho",
+    "right",
+    "rightarrow",
+    "rightharpoonup",
+    "rightleftharpoons",
+    "rvert",
+    "scriptscriptstyle",
+    "scriptstyle",
+    "searrow",
+    "sec",
+    "setminus",
+    "sharp",
+    "sigma",
+    "sim",
+    "simeq",
+    "sin",
+    "sinh",
+    "smash",
+    "smile",
+    "spadesuit",
+    "sqcap",
+    "sqcup",
+    "sqrt",
+    "sqsubset",
+    "sqsubseteq",
+    "sqsupset",
+    "sqsupseteq",
+    "square",
+    "stackrel",
+    "star",
+    "subset",
+    "subseteq",
+    "subsetneq",
+    "substack",
+    "succ",
+    "succeq",
+    "sum",
+    "sup",
+    "supset",
+    "supseteq",
+    "supsetneq",
+    "surd",
+    "swarrow",
+    "tag",
+    "tan",
+    "tanh",
+    "tau",
+    "text",
+    "textbf",
+    "textit",
+    "textnormal",
+    "textrm",
+    "textsf",
+    "textstyle",
+    "texttt",
+    "tfrac",
+    "therefore",
+    "theta",
+    "tilde",
+    "times",
+    "to",
+    "top",
+    "triangle",
+    "triangleleft",
+    "triangleq",
+    "triangleright",
+    "underbrace",
+    "underline",
+    "underset",
+    "unlhd",
+    "unrhd",
+    "uparrow",
+    "updownarrow",
+    "uplus",
+    "upsilon",
+    "varepsilon",
+    "varkappa",
+    "varnothing",
+    "varphi",
+    "varpi",
+    "varrho",
+    "varsigma",
+    "vartheta",
+    "vdash",
+    "vdots",
+    "vec",
+    "vee",
+    "vert",
+    "vphantom",
+    "wedge",
+    "widehat",
+    "widetilde",
+    "wp",
+    "wr",
+    "xi",
+    "xleftarrow",
+    "xrightarrow",
+    "zeta",
+];
+
+/// Control symbols (e.g., `\,` or `\{`) that are allowed in math spans in safe mode.
+const SAFE_CONTROL_SYMBOLS: &str = "\\,;:!> \n\t{}|%#&$_";
+
+/// Environments that are allowed in math spans in safe mode, sorted.
+const SAFE_ENVIRONMENTS: &[&str] = &[
+    "Bmatrix",
+    "Vmatrix",
+    "aligned",
+    "alignedat",
+    "array",
+    "bmatrix",
+    "cases",
+    "gathered",
+    "matrix",
+    "pmatrix",
+    "smallmatrix",
+    "split",
+    "subarray",
+    "vmatrix",
+];
+
+/// Checks a math span like [`check_math`](fn.check_math.html) and additionally
+/// ensures that it can't execute arbitrary TeX code.
+///
+/// Allows only a whitelist of common math commands (e.g., `\frac` or `\alpha`) and
+/// math environments (e.g., `pmatrix`). In particular, commands that read or write
+/// files (e.g., `\input` or `\write18`), change category codes (`\catcode`), or
+/// define macros (`\def`) are rejected. Comments, unescaped dollar signs, and TeX's
+/// `^^` notation for characters (e.g., `^^5c` for a backslash) are rejected too.
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::math::{check_safe_math, MathError};
+///
+/// assert_eq!(check_safe_math(r"\sum_{i=1}^n \frac{\alpha_i}{2}"), Ok(()));
+/// assert_eq!(
+///     check_safe_math(r"x \input{/etc/passwd}"),
+///     Err(MathError::NotAllowed { pos: 2, token: r"\input".to_string() })
+/// );
+/// ```
+pub fn check_safe_math(math: &str) -> Result<(), MathError> {
+    check_math(math)?;
+
+    if let Some(pos) = math.find("^^") {
+        return Err(MathError::NotAllowed {
+            pos,
+            token: "^^".to_string(),
+        });
+    }
+
+    let mut tokenizer = Tokenizer::new(math);
+    while let Some((pos, token)) = tokenizer.next() {
+        let allowed = match token {
+            Token::ControlWord(name @ "begin") | Token::ControlWord(name @ "end") => {
+                // Can't fail because `check_math` succeeded.
+                let environment = tokenizer.read_braced_argument().unwrap_or("");
+                if SAFE_ENVIRONMENTS.binary_search(&environment).is_err() {
+                    return Err(MathError::NotAllowed {
+                        pos,
+                        token: format!("\\{}{{{}}}", name, environment),
+                    });
+                }
+                true
+            }
+            Token::ControlWord(name) => SAFE_CONTROL_WORDS.binary_search(&name).is_ok(),
+            Token::ControlSymbol(c) => SAFE_CONTROL_SYMBOLS.contains(c),
+            Token::Comment(_) | Token::Other('$') | Token::Other('\\') => false,
+            Token::BeginGroup | Token::EndGroup | Token::Other(_) => true,
+        };
+        if !allowed {
+            let end = math.len() - tokenizer.remainder().len();
+            return Err(MathError::NotAllowed {
+                pos,
+                token: math[pos..end].to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tokenizer() {
+        let tokens = Tokenizer::new("\\alpha_1 \\\\ x% comment\n\\,\\").collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                (0, Token::ControlWord("alpha")),
+                (6, Token::Other('_')),
+                (7, Token::Other('1')),
+                (9, Token::ControlSymbol('\\')),
+                (12, Token::Other('x')),
+                (13, Token::Comment(" comment")),
+                (23, Token::ControlSymbol(',')),
+                (25, Token::Other('\\')),
+            ]
+        );
+    }
+
+    #[test]
+    fn valid_math() {
+        for math in &[
+            "",
+            "m_a^{th}",
+            r"\frac{\{a\}}{b}",
+            r"\left( \frac{a}{b} \right)",
+            r"\left. \frac{a}{b} \right|_{x=0}",
+            r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}",
+            r"\begin {aligned} a &= \left[ b \right] \end{ aligned }",
+            "a % comment with unbalanced { brace\n + b",
+        ] {
+            assert_eq!(check_math(math), Ok(()), "{}", math);
+        }
+    }
+
+    #[test]
+    fn invalid_math() {
+        assert_eq!(
+            check_math("a}"),
+            Err(MathError::UnmatchedEndGroup { pos: 1 })
+        );
+        assert_eq!(check_math("{a"), Err(MathError::UnclosedGroup { pos: 0 }));
+        assert_eq!(
+            check_math(r"a \right)"),
+            Err(MathError::UnmatchedRight { pos: 2 })
+        );
+        assert_eq!(
+            check_math(r"\left( a"),
+            Err(MathError::UnclosedLeft { pos: 0 })
+        );
+        assert_eq!(
+            check_math(r"{\left( a } \right)"),
+            Err(MathError::UnclosedLeft { pos: 1 })
+        );
+        assert_eq!(
+            check_math(r"a \end{matrix}"),
+            Err(MathError::UnmatchedEnd {
+                pos: 2,
+                name: "matrix".to_string()
+            })
+        );
+        assert_eq!(
+            check_math(r"\begin{matrix} a \end{pmatrix}"),
+            Err(MathError::MismatchedEnd {
+                pos: 17,
+                expected: "matrix".to_string(),
+                found: "pmatrix".to_string()
+            })
+        );
+        assert_eq!(
+            check_math(r"\begin{cases} a"),
+            Err(MathError::UnclosedBegin {
+                pos: 0,
+                name: "cases".to_string()
+            })
+        );
+        assert_eq!(
+            check_math(r"\begin x"),
+            Err(MathError::MissingEnvironmentName { pos: 0 })
+        );
+    }
+
+    #[test]
+    fn safe_math() {
+        assert!(SAFE_CONTROL_WORDS.windows(2).all(|w| w[0] < w[1]));
+        assert!(SAFE_ENVIRONMENTS.windows(2).all(|w| w[0] < w[1]));
+
+        for math in &[
+            r"\left( \frac{a}{b} \right)^{2} \,\quad \{x\}",
+            r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}",
+            r"\mathbb{R} \to \mathbb{R}, \text{for all } x \in \mathcal{X}",
+            r"50\% \text{ of } \$5",
+        ] {
+            assert_eq!(check_safe_math(math), Ok(()), "{}", math);
+        }
+
+        let not_allowed = |math| match check_safe_math(math) {
+            Err(MathError::NotAllowed { pos, token }) => Some((pos, token)),
+            _ => None,
+        };
+        let some = |pos, token: &str| Some((pos, token.to_string()));
+        assert_eq!(not_allowed(r"a \input{x}"), some(2, r"\input"));
+        assert_eq!(
+            not_allowed(r"\immediate\write18{rm -rf}"),
+            some(0, r"\immediate")
+        );
+        assert_eq!(not_allowed(r"x\write18{ls}"), some(1, r"\write"));
+        assert_eq!(not_allowed(r"\openout1=x"), some(0, r"\openout"));
+        assert_eq!(not_allowed(r"\catcode`\@=11"), some(0, r"\catcode"));
+        assert_eq!(not_allowed(r"\def\x{y}"), some(0, r"\def"));
+        assert_eq!(
+            not_allowed(r"\begin{verbatim}\end{verbatim}"),
+            some(0, r"\begin{verbatim}")
+        );
+        assert_eq!(not_allowed(r"x^^5cinput"), some(1, "^^"));
+        assert_eq!(not_allowed("x % \\input\n"), some(2, "% \\input"));
+        assert_eq!(not_allowed(r"x $ \alpha"), some(2, "$"));
+        assert_eq!(not_allowed(r"x \@ y"), some(2, r"\@"));
+        assert_eq!(not_allowed(r"\alpha\"), some(6, r"\"));
+
+        // Errors of `check_math` take precedence.
+        assert_eq!(
+            check_safe_math(r"\input{"),
+            Err(MathError::UnclosedGroup { pos: 6 })
+        );
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn safe_math_has_only_whitelisted_commands(
+            math in r"(\\[a-z]{1,9}|\\[^a-z]|[{}$%^ a-z0-9]|\\(begin|end)\{[a-z]{1,7}\})*"
+        ) {
+            if check_safe_math(&math).is_ok() {
+                proptest::prop_assert!(!math.contains("^^"));
+                for (_, token) in Tokenizer::new(&math) {
+                    match token {
+                        Token::ControlWord(name) => proptest::prop_assert!(
+                            SAFE_CONTROL_WORDS.contains(&name)
+                                || name == "begin"
+                                || name == "end"
+                        ),
+                        Token::ControlSymbol(c) => {
+                            proptest::prop_assert!(SAFE_CONTROL_SYMBOLS.contains(c))
+                        }
+                        Token::Comment(_) | Token::Other('$') => proptest::prop_assert!(false),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
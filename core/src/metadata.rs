@@ -0,0 +1,502 @@
+//! Document metadata from a YAML or TOML front matter block.
+//!
+//! A markdown document may start with a block of metadata, delimited by lines
+//! consisting of `---` (YAML) or `+++` (TOML):
+//!
+//! ```text
+//! ---
+//! title: A Study of Things
+//! author:
+//!   - name: Jane Doe
+//!     affiliation: University of Somewhere
+//!   - John Roe
+//! date: May 2020
+//! abstract: |
+//!   We study *things*, in particular $$x^2$$.
+//! bluepaper:
+//!   dialect: gfm
+//!   broken-math-as-code: true
+//! ---
+//! ```
+//!
+//! The recognized keys are `title`, `author` (or `authors`), `date`, and `abstract`,
+//! plus a table `bluepaper` that overrides [`ConversionOptions`] for this document.
+//! All other keys are ignored. An author can either be a plain name or a table with
+//! the keys `name`, `affiliation` (or `affiliations`), and `email`. The title and
+//! the abstract may contain markdown, the remaining fields are typeset as plain
+//! text.
+//!
+//! The following keys are recognized in the `bluepaper` table:
+//! - `dialect`: any [`InputDialect`] name (e.g., `"gfm"`); resets the math
+//!   delimiters, soft breaks, markdown extensions, and `title-from-first-heading`
+//!   to the defaults for this dialect before the remaining keys are applied;
+//! - `broken-math-as-code`: a boolean;
+//! - `soft-break`: either `"line-break"` or `"space"`;
+//! - `title-from-first-heading`: a boolean;
+//! - `cite-links`: a boolean;
+//! - `citation-syntax`: a boolean;
+//! - `theorem-environments`: a boolean;
+//! - `description-lists`: a boolean;
+//! - `todo-summary`: a boolean;
+//! - `smart-quotes`: a boolean; and
+//! - `language`: any [`Language`] name (e.g., `"german"` or `"de"`), or `"auto"` to
+//!   detect the language from the text.
+//!
+//! [`ConversionOptions`]: ../struct.ConversionOptions.html
+//! [`InputDialect`]: ../enum.InputDialect.html
+//! [`Language`]: ../language/enum.Language.html
+
+use crate::{ConversionOptions, InputDialect, Language, SoftBreak};
+
+use log::warn;
+use serde::Deserialize;
+
+/// Metadata of a document, as specified in its front matter.
+///
+/// See [module level documentation](index.html) for the syntax of front matter.
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::MarkdownToLatex;
+///
+/// let markdown = "---\ntitle: Title\nauthor: [Alice, Bob]\n---\n\nText.".to_string();
+/// let converter = MarkdownToLatex::from_string(markdown);
+///
+/// let metadata = converter.metadata();
+/// assert_eq!(metadata.title.as_deref(), Some("Title"));
+/// assert_eq!(metadata.authors.len(), 2);
+/// assert_eq!(metadata.authors[1].name, "Bob");
+///
+/// let latex = converter.into_string();
+/// assert!(latex
+///     .find("\\title{Title}\n\\author{Alice \\and Bob}\n\\maketitle\n\nText.")
+///     .is_some());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DocumentMetadata {
+    /// The title of the document (markdown).
+    pub title: Option<String>,
+
+    /// The authors of the document, in order.
+    pub authors: Vec<Author>,
+
+    /// The date of the document (plain text).
+    ///
+    /// If `None`, then LaTeX typesets the date on which the document is compiled.
+    pub date: Option<String>,
+
+    /// The abstract of the document (markdown).
+    pub abstract_text: Option<String>,
+}
+
+/// An author of a document, see [`DocumentMetadata`](struct.DocumentMetadata.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Author {
+    /// Name of the author (plain text).
+    pub name: String,
+
+    /// Affiliations of the author (plain text), each typeset on its own line.
+    pub affiliations: Vec<String>,
+
+    /// Email address of the author.
+    pub email: Option<String>,
+}
+
+impl DocumentMetadata {
+    /// Returns `true` if no metadata is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Removes a front matter block from the beginning of `markdown`, if there is one.
+///
+/// Returns the parsed metadata and applies any option overrides to `options`. The
+/// front matter block is replaced by the same number of newlines so that line
+/// numbers in diagnostics still refer to the original input. If the front matter
+/// cannot be parsed then a warning is logged and `markdown` is left unchanged.
+pub(crate) fn extract_front_matter(
+    markdown: &mut String,
+    options: &mut ConversionOptions,
+) -> DocumentMetadata {
+    let (end, value) = match find_front_matter(markdown) {
+        Some((content, end, is_toml)) => {
+            let value = if is_toml {
+                toml::from_str::<toml::Value>(content)
+                    .map(toml_to_yaml)
+                    .map_err(|e| e.to_string())
+            } else {
+                serde_yaml::from_str::<serde_yaml::Value>(content).map_err(|e| e.to_string())
+            };
+
+            match value {
+                Ok(value @ serde_yaml::Value::Mapping(_)) => (end, value),
+                Ok(_) => {
+                    // Not actually front matter but, e.g., a thematic break followed by
+                    // a setext heading. Leave it to the markdown parser.
+                    return DocumentMetadata::default();
+                }
+                Err(err) => {
+                    warn!("Ignoring front matter that cannot be parsed: {}", err);
+                    return DocumentMetadata::default();
+                }
+            }
+        }
+        None => return DocumentMetadata::default(),
+    };
+
+    let newlines = markdown[..end].bytes().filter(|&b| b == b'\n').count();
+    markdown.replace_range(..end, &"\n".repeat(newlines));
+
+    let front_matter = match serde_yaml::from_value::<FrontMatter>(value) {
+        Ok(front_matter) => front_matter,
+        Err(err) => {
+            warn!("Ignoring invalid front matter: {}", err);
+            return DocumentMetadata::default();
+        }
+    };
+
+    if let Some(overrides) = front_matter.bluepaper {
+        overrides.apply(options);
+    }
+
+    DocumentMetadata {
+        title: front_matter.title.map(Text::into_string),
+        authors: front_matter
+            .author
+            .into_vec()
+            .into_iter()
+            .map(AuthorEntry::into_author)
+            .collect(),
+        date: front_matter.date.map(Text::into_string),
+        abstract_text: front_matter.r#abstract.map(Text::into_string),
+    }
+}
+
+/// Returns the contents of the front matter block, the end of the block, and
+/// whether the block is TOML (rather than YAML).
+fn find_front_matter(markdown: &str) -> Option<(&str, usize, bool)> {
+    let first_line_end = markdown.find('\n')?;
+    let (is_toml, closing): (_, &[&str]) = match markdown[..first_line_end].trim_end() {
+        "---" => (false, &["---", "..."]),
+        "+++" => (true, &["+++"]),
+        _ => return None,
+    };
+
+    let content_start = first_line_end + 1;
+    let mut line_start = content_start;
+    while line_start < markdown.len() {
+        let line_end = markdown[line_start..]
+            .find('\n')
+            .map_or(markdown.len(), |i| line_start + i + 1);
+        if closing.contains(&markdown[line_start..line_end].trim_end()) {
+            return Some((&markdown[content_start..line_start], line_end, is_toml));
+        }
+        line_start = line_end;
+    }
+
+    None
+}
+
+/// Converts a TOML value to a YAML value so that both front matter formats can be
+/// deserialized in the same way. Dates and times are converted to strings.
+fn toml_to_yaml(value: toml::Value) -> serde_yaml::Value {
+    match value {
+        toml::Value::String(s) => serde_yaml::Value::String(s),
+        toml::Value::Integer(i) => serde_yaml::Value::Number(i.into()),
+        toml::Value::Float(f) => serde_yaml::Value::Number(f.into()),
+        toml::Value::Boolean(b) => serde_yaml::Value::Bool(b),
+        toml::Value::Datetime(d) => serde_yaml::Value::String(d.to_string()),
+        toml::Value::Array(a) => a.into_iter().map(toml_to_yaml).collect(),
+        toml::Value::Table(t) => serde_yaml::Value::Mapping(
+            t.into_iter()
+                .map(|(k, v)| (serde_yaml::Value::String(k), toml_to_yaml(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct FrontMatter {
+    title: Option<Text>,
+    #[serde(default, alias = "authors")]
+    author: OneOrMany<AuthorEntry>,
+    date: Option<Text>,
+    r#abstract: Option<Text>,
+    bluepaper: Option<OptionOverrides>,
+}
+
+/// A scalar that is interpreted as text (e.g., `date: 2020` is the text "2020").
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+    String(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl Text {
+    fn into_string(self) -> String {
+        match self {
+            Text::String(s) => s,
+            Text::Integer(i) => i.to_string(),
+            Text::Float(f) => f.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> Default for OneOrMany<T> {
+    fn default() -> Self {
+        OneOrMany::Many(Vec::new())
+    }
+}
+
+impl<T> OneOrMany<T> {
+    fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(item) => vec![item],
+            OneOrMany::Many(items) => items,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AuthorEntry {
+    Name(String),
+    Details {
+        name: String,
+        #[serde(default, alias = "affiliations")]
+        affiliation: OneOrMany<String>,
+        email: Option<String>,
+    },
+}
+
+impl AuthorEntry {
+    fn into_author(self) -> Author {
+        match self {
+            AuthorEntry::Name(name) => Author {
+                name,
+                ..Default::default()
+            },
+            AuthorEntry::Details {
+                name,
+                affiliation,
+                email,
+            } => Author {
+                name,
+                affiliations: affiliation.into_vec(),
+                email,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct OptionOverrides {
+    dialect: Option<String>,
+    broken_math_as_code: Option<bool>,
+    soft_break: Option<String>,
+    title_from_first_heading: Option<bool>,
+    cite_links: Option<bool>,
+    citation_syntax: Option<bool>,
+    theorem_environments: Option<bool>,
+    description_lists: Option<bool>,
+    todo_summary: Option<bool>,
+    smart_quotes: Option<bool>,
+    language: Option<String>,
+}
+
+impl OptionOverrides {
+    fn apply(self, options: &mut ConversionOptions) {
+        if let Some(dialect) = self.dialect {
+            match dialect.parse::<InputDialect>() {
+                Ok(dialect) => {
+                    // Only reset the options that depend on the dialect; the others
+                    // (e.g., the engine or safe mode) are chosen by the caller.
+                    let defaults = ConversionOptions::for_dialect(dialect);
+                    options.math_delimiters = defaults.math_delimiters;
+                    options.soft_break = defaults.soft_break;
+                    options.extensions = defaults.extensions;
+                    options.title_from_first_heading = defaults.title_from_first_heading;
+                }
+                Err(err) => warn!("Ignoring option in front matter: {}", err),
+            }
+        }
+        if let Some(broken_math_as_code) = self.broken_math_as_code {
+            options.broken_math_as_code = broken_math_as_code;
+        }
+        if let Some(soft_break) = self.soft_break {
+            match soft_break.as_str() {
+                "line-break" => options.soft_break = SoftBreak::LineBreak,
+                "space" => options.soft_break = SoftBreak::Space,
+                _ => warn!(
+                    "Ignoring option in front matter: unknown soft-break \"{}\" (expected \
+                     \"line-break\" or \"space\").",
+                    soft_break
+                ),
+            }
+        }
+        if let Some(title_from_first_heading) = self.title_from_first_heading {
+            options.title_from_first_heading = title_from_first_heading;
+        }
+        if let Some(cite_links) = self.cite_links {
+            options.cite_links = cite_links;
+        }
+        if let Some(citation_syntax) = self.citation_syntax {
+            options.citation_syntax = citation_syntax;
+        }
+        if let Some(theorem_environments) = self.theorem_environments {
+            options.theorem_environments = theorem_environments;
+        }
+        if let Some(description_lists) = self.description_lists {
+            options.description_lists = description_lists;
+        }
+        if let Some(todo_summary) = self.todo_summary {
+            options.todo_summary = todo_summary;
+        }
+        if let Some(smart_quotes) = self.smart_quotes {
+            options.smart_quotes = smart_quotes;
+        }
+        if let Some(language) = self.language {
+            if language == "auto" {
+                options.language = None;
+                options.detect_language = true;
+            } else {
+                match language.parse::<Language>() {
+                    Ok(language) => options.language = Some(language),
+                    Err(err) => warn!("Ignoring option in front matter: {}", err),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Engine;
+
+    fn extract(markdown: &str) -> (String, DocumentMetadata, ConversionOptions) {
+        let mut markdown = markdown.to_string();
+        let mut options = ConversionOptions::default();
+        let metadata = extract_front_matter(&mut markdown, &mut options);
+        (markdown, metadata, options)
+    }
+
+    #[test]
+    fn yaml() {
+        let (markdown, metadata, options) = extract(
+            "---\n\
+             title: A *Study*\n\
+             authors:\n  \
+               - name: Jane Doe\n    \
+                 affiliation: Univ. A\n    \
+                 email: jane@example.com\n  \
+               - name: John Roe\n    \
+                 affiliations: [Univ. A, Univ. B]\n  \
+               - Max\n\
+             date: 2020-05-01\n\
+             abstract: We study things.\n\
+             tags: [ignored]\n\
+             bluepaper:\n  \
+               soft-break: space\n  \
+               broken-math-as-code: true\n  \
+               language: de\n\
+             ...\n\
+             # Heading\n",
+        );
+
+        assert_eq!(markdown, format!("{}# Heading\n", "\n".repeat(17)));
+        assert_eq!(metadata.title.as_deref(), Some("A *Study*"));
+        assert_eq!(
+            metadata.authors,
+            vec![
+                Author {
+                    name: "Jane Doe".to_string(),
+                    affiliations: vec!["Univ. A".to_string()],
+                    email: Some("jane@example.com".to_string()),
+                },
+                Author {
+                    name: "John Roe".to_string(),
+                    affiliations: vec!["Univ. A".to_string(), "Univ. B".to_string()],
+                    email: None,
+                },
+                Author {
+                    name: "Max".to_string(),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(metadata.date.as_deref(), Some("2020-05-01"));
+        assert_eq!(metadata.abstract_text.as_deref(), Some("We study things."));
+        assert_eq!(options.soft_break, SoftBreak::Space);
+        assert!(options.broken_math_as_code);
+        assert_eq!(options.language, Some(Language::German));
+        assert!(options.title_from_first_heading);
+    }
+
+    #[test]
+    fn toml() {
+        let (markdown, metadata, options) = extract(
+            "+++\n\
+             title = \"Title\"\n\
+             author = \"Jane Doe\"\n\
+             date = 2020-05-01\n\
+             [bluepaper]\n\
+             dialect = \"obsidian\"\n\
+             +++\n\
+             Text",
+        );
+
+        assert_eq!(markdown, "\n\n\n\n\n\n\nText");
+        assert_eq!(metadata.title.as_deref(), Some("Title"));
+        assert_eq!(metadata.authors.len(), 1);
+        assert_eq!(metadata.authors[0].name, "Jane Doe");
+        assert_eq!(metadata.date.as_deref(), Some("2020-05-01"));
+        assert_eq!(options.soft_break, SoftBreak::Space);
+        assert!(!options.title_from_first_heading);
+    }
+
+    #[test]
+    fn dialect_keeps_caller_options() {
+        let mut markdown = "---\nbluepaper:\n  dialect: commonmark\n---\nText".to_string();
+        let mut options = ConversionOptions {
+            engine: Engine::XeLatex,
+            cite_links: true,
+            language: Some(Language::German),
+            safe_mode: true,
+            ..ConversionOptions::default()
+        };
+        extract_front_matter(&mut markdown, &mut options);
+
+        assert_eq!(options.soft_break, SoftBreak::Space);
+        assert_eq!(options.engine, Engine::XeLatex);
+        assert!(options.cite_links);
+        assert_eq!(options.language, Some(Language::German));
+        assert!(options.safe_mode);
+    }
+
+    #[test]
+    fn not_front_matter() {
+        for &markdown in &[
+            "Text\n---\ntitle: Title\n---\n",
+            "---\nUnclosed: front matter\n",
+            "---\nA setext heading\n---\n",
+            "---\n- a list\n---\n",
+        ] {
+            let (result, metadata, _) = extract(markdown);
+            assert_eq!(result, markdown);
+            assert!(metadata.is_empty());
+        }
+    }
+}
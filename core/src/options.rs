@@ -0,0 +1,830 @@
+//! Configuration of the conversion from markdown to LaTeX.
+
+use crate::Language;
+
+use std::str::FromStr;
+
+/// Options that control how [`MarkdownToLatex`] converts markdown to LaTeX.
+///
+/// The `Default` implementation reproduces the behavior of
+/// [`MarkdownToLatex::from_string`], which is tailored to markdown exported from
+/// Dropbox Paper. Use [`for_dialect`](#method.for_dialect) to get options that are
+/// tailored to a different [`InputDialect`](enum.InputDialect.html). To change only
+/// some of the options, use struct update syntax:
+///
+/// ```
+/// use bluepaper_core::{ConversionOptions, MarkdownToLatex};
+///
+/// let options = ConversionOptions {
+///     broken_math_as_code: true,
+///     ..Default::default()
+/// };
+/// let markdown = "Broken math: $$\\frac{a}{b$$.".to_string();
+/// let latex = MarkdownToLatex::with_options(markdown, options).into_string();
+///
+/// assert!(latex.find("Broken math: \\texttt{\\$\\$\\textbackslash{}frac").is_some());
+/// ```
+///
+/// [`MarkdownToLatex`]: struct.MarkdownToLatex.html
+/// [`MarkdownToLatex::from_string`]: struct.MarkdownToLatex.html#method.from_string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionOptions {
+    /// Render math spans that fail validation as code instead of math.
+    ///
+    /// Every math span is checked with [`check_math`](math/fn.check_math.html)
+    /// and a warning is logged for each broken span. If this option is `true` then
+    /// broken spans are additionally rendered in `\texttt{...}` (including their
+    /// math delimiters) so that the generated document still compiles. If it is
+    /// `false` then broken spans are copied verbatim like all other math spans.
+    pub broken_math_as_code: bool,
+
+    /// Delimiters that mark math spans in the markdown source.
+    ///
+    /// Defaults to [`MathDelimiters::DROPBOX_PAPER`], i.e., only `$$...$$` is
+    /// recognized, and it is typeset as inline math.
+    ///
+    /// [`MathDelimiters::DROPBOX_PAPER`]: struct.MathDelimiters.html#associatedconstant.DROPBOX_PAPER
+    pub math_delimiters: MathDelimiters,
+
+    /// How soft line breaks (i.e., single newlines within a paragraph) are typeset.
+    pub soft_break: SoftBreak,
+
+    /// Markdown syntax extensions that are recognized in addition to CommonMark.
+    pub extensions: MarkdownExtensions,
+
+    /// Typeset a level 1 heading at the very beginning of the document as the title.
+    ///
+    /// If `false`, all headings are typeset as section headings.
+    pub title_from_first_heading: bool,
+
+    /// Names of sections that are typeset in a special way (e.g., "Abstract").
+    pub section_names: SectionNames,
+
+    /// Typeset links to DOIs, arXiv preprints, and ISBNs as citations.
+    ///
+    /// If `true`, such links are replaced by `\cite{key}` and the document ends with
+    /// a bibliography (or the bibliography replaces a section named "References",
+    /// see [`section_names`](#structfield.section_names)). The bibliography has to be
+    /// saved as a separate BibTeX file, see module
+    /// [`bibliography`](bibliography/index.html).
+    pub cite_links: bool,
+
+    /// Recognize pandoc-style citations, e.g., `[see @smith2020, p. 3; @doe2019]`.
+    ///
+    /// If `true`, bracketed citations are typeset with `\citep` (or `\citetext` if
+    /// several of the cited works have their own prefix or locator) and citations in
+    /// running text (`@smith2020` or `@smith2020 [p. 3]`) are typeset with `\citet`.
+    /// A `-` before the `@` suppresses the author (`\citeyearpar`). The citation keys
+    /// are looked up in the [`MetadataSource`] that is passed to
+    /// [`MarkdownToLatex::resolve_citations`], usually a [`BibFile`].
+    ///
+    /// Disabled by default because Dropbox Paper uses `@name` for mentions.
+    ///
+    /// [`MetadataSource`]: bibliography/trait.MetadataSource.html
+    /// [`MarkdownToLatex::resolve_citations`]: struct.MarkdownToLatex.html#method.resolve_citations
+    /// [`BibFile`]: bibliography/struct.BibFile.html
+    pub citation_syntax: bool,
+
+    /// Typeset paragraphs that start with a bold label such as "**Theorem 1.**",
+    /// "**Lemma 2 (Name).**", or "**Proof.**" as `amsthm` environments.
+    ///
+    /// The recognized labels are Theorem, Lemma, Proposition, Corollary, Definition,
+    /// Remark, Example, and Proof. The environment ends with the paragraph. A manual
+    /// number becomes a `\label` (e.g., `lem:2`), and references such as "Lemma 2"
+    /// elsewhere in the text become `Lemma~\ref{lem:2}`. LaTeX numbers each kind of
+    /// environment separately, so the manual numbers should do the same.
+    pub theorem_environments: bool,
+
+    /// Typeset bullet lists whose items all start with a bold term followed by a colon
+    /// (e.g., `- **Term:** description` or `- **Term**: description`) as
+    /// `description` environments with `\item[{Term}] description`.
+    ///
+    /// See also
+    /// [`MarkdownExtensions::definition_lists`](struct.MarkdownExtensions.html#structfield.definition_lists)
+    /// for pandoc-style definition lists.
+    pub description_lists: bool,
+
+    /// Append a section "Open TODOs" that lists all unchecked task list items
+    /// (`- [ ] ...`) with references to the pages on which they appear.
+    ///
+    /// Has no effect unless
+    /// [`MarkdownExtensions::tasklists`](struct.MarkdownExtensions.html#structfield.tasklists)
+    /// is set.
+    pub todo_summary: bool,
+
+    /// The LaTeX engine that the generated code will be compiled with.
+    ///
+    /// Determines the packages for font and input encoding in the preamble, and
+    /// whether non-ASCII characters are escaped (see
+    /// [`escape_unicode`](#structfield.escape_unicode)). Defaults to
+    /// [`Engine::PdfLatex`](enum.Engine.html#variant.PdfLatex).
+    pub engine: Engine,
+
+    /// The language of the document.
+    ///
+    /// If set, the preamble loads `babel` (pdfLaTeX) or `polyglossia` (XeLaTeX and
+    /// LuaLaTeX) for correct hyphenation, and `csquotes`, and
+    /// [smart quotes](#structfield.smart_quotes) are typeset as `\enquote{...}`, which
+    /// produces the quotation marks of the language. Defaults to `None`, i.e., no
+    /// language specific packages (unless
+    /// [`detect_language`](#structfield.detect_language) is set).
+    ///
+    /// ```
+    /// use bluepaper_core::{ConversionOptions, Language, MarkdownToLatex};
+    ///
+    /// let options = ConversionOptions {
+    ///     language: Some(Language::German),
+    ///     ..Default::default()
+    /// };
+    /// let markdown = "Er sagte \"Hallo\".".to_string();
+    /// let latex = MarkdownToLatex::with_options(markdown, options).into_string();
+    ///
+    /// assert!(latex.find("\\usepackage[shorthands=off,ngerman]{babel}").is_some());
+    /// assert!(latex.find("Er sagte \\enquote{Hallo}.").is_some());
+    /// ```
+    pub language: Option<Language>,
+
+    /// Detect the [`language`](#structfield.language) from the text if it is `None`.
+    ///
+    /// See [`Language::detect`](language/enum.Language.html#method.detect).
+    pub detect_language: bool,
+
+    /// Replace straight quotes (`"..."` and `'...'`) in text with typographic quotes
+    /// (``` ``...'' ``` and `` `...' ``, or `\enquote{...}` if a
+    /// [`language`](#structfield.language) is set).
+    ///
+    /// Apostrophes (e.g., in "don't") are recognized from the context and kept.
+    pub smart_quotes: bool,
+
+    /// Replace non-ASCII characters such as "≤", "→", "α", or "“" with LaTeX commands
+    /// (e.g., `\ensuremath{\leq}`).
+    ///
+    /// pdfLaTeX can't typeset most non-ASCII characters directly. Has no effect unless
+    /// [`engine`](#structfield.engine) is pdfLaTeX because XeLaTeX and LuaLaTeX
+    /// support Unicode input natively. See
+    /// [`latex_escape::escape_str_with`](latex_escape/fn.escape_str_with.html) for
+    /// details.
+    pub escape_unicode: bool,
+
+    /// What to do with non-ASCII characters that have no LaTeX replacement if
+    /// [`escape_unicode`](#structfield.escape_unicode) is set. Defaults to
+    /// [`UnicodeFallback::Warn`](enum.UnicodeFallback.html#variant.Warn).
+    pub unicode_fallback: UnicodeFallback,
+
+    /// Guarantee that untrusted markdown can't execute arbitrary TeX code.
+    ///
+    /// Text, HTML, and URLs are always escaped. In safe mode, math spans are
+    /// additionally checked with [`check_safe_math`](math/fn.check_safe_math.html),
+    /// which allows only a whitelist of math commands and rejects, e.g., `\input`,
+    /// `\write18`, or `\def`. Math spans that fail the check are rendered as code
+    /// regardless of [`broken_math_as_code`](#structfield.broken_math_as_code).
+    /// Citations and heading labels whose keys contain characters other than ASCII
+    /// letters, digits, and `-._:/` are ignored. Front matter can't turn off safe
+    /// mode.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bluepaper_core::{ConversionOptions, MarkdownToLatex};
+    ///
+    /// let options = ConversionOptions {
+    ///     safe_mode: true,
+    ///     ..Default::default()
+    /// };
+    /// let markdown = "Math: $$x \\input{/etc/passwd}$$.".to_string();
+    /// let latex = MarkdownToLatex::with_options(markdown, options).into_string();
+    ///
+    /// assert!(latex
+    ///     .find("Math: \\texttt{\\$\\$x \\textbackslash{}input\\{/etc/passwd\\}\\$\\$}.")
+    ///     .is_some());
+    /// ```
+    pub safe_mode: bool,
+
+    /// Types of callout blocks, i.e., blockquotes that start with a bold label such as
+    /// `> **Note:** ...` or with a GitHub style alert marker such as `> [!WARNING]`.
+    ///
+    /// Callouts are typeset as colored boxes (`tcolorbox`) with the type's title. All
+    /// other blockquotes are typeset as `quote` environments. Defaults to
+    /// [`CalloutStyle::default_styles`](struct.CalloutStyle.html#method.default_styles).
+    /// Set to an empty `Vec` to typeset all blockquotes as quotes.
+    pub callouts: Vec<CalloutStyle>,
+}
+
+impl ConversionOptions {
+    /// Returns the default options for markdown of the given dialect.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bluepaper_core::{ConversionOptions, InputDialect, MarkdownToLatex};
+    ///
+    /// let markdown = "Line breaks within\na paragraph.".to_string();
+    ///
+    /// let latex = MarkdownToLatex::from_string(markdown.clone()).into_string();
+    /// assert!(latex.find("Line breaks within \\\\\na paragraph.").is_some());
+    ///
+    /// let options = ConversionOptions::for_dialect(InputDialect::CommonMark);
+    /// let latex = MarkdownToLatex::with_options(markdown, options).into_string();
+    /// assert!(latex.find("Line breaks within\na paragraph.").is_some());
+    /// ```
+    pub fn for_dialect(dialect: InputDialect) -> Self {
+        let (math_delimiters, soft_break, extensions, title_from_first_heading) = match dialect {
+            InputDialect::DropboxPaper => (
+                MathDelimiters::DROPBOX_PAPER,
+                SoftBreak::LineBreak,
+                MarkdownExtensions {
+                    strikethrough: true,
+                    tasklists: true,
+                    ..MarkdownExtensions::NONE
+                },
+                true,
+            ),
+            InputDialect::CommonMark => (
+                MathDelimiters::LATEX,
+                SoftBreak::Space,
+                MarkdownExtensions::NONE,
+                true,
+            ),
+            InputDialect::Gfm => (
+                MathDelimiters::LATEX,
+                SoftBreak::Space,
+                MarkdownExtensions {
+                    tables: true,
+                    footnotes: true,
+                    strikethrough: true,
+                    tasklists: true,
+                    heading_attributes: false,
+                    fancy_lists: false,
+                    definition_lists: false,
+                },
+                true,
+            ),
+            InputDialect::Obsidian => (
+                MathDelimiters::LATEX,
+                SoftBreak::Space,
+                MarkdownExtensions {
+                    tables: true,
+                    footnotes: true,
+                    strikethrough: true,
+                    tasklists: true,
+                    heading_attributes: false,
+                    fancy_lists: false,
+                    definition_lists: false,
+                },
+                false,
+            ),
+        };
+
+        Self {
+            broken_math_as_code: false,
+            math_delimiters,
+            soft_break,
+            extensions,
+            title_from_first_heading,
+            section_names: SectionNames::default(),
+            cite_links: false,
+            citation_syntax: false,
+            theorem_environments: true,
+            description_lists: false,
+            todo_summary: false,
+            engine: Engine::PdfLatex,
+            language: None,
+            detect_language: false,
+            smart_quotes: true,
+            escape_unicode: true,
+            unicode_fallback: UnicodeFallback::Warn,
+            safe_mode: false,
+            callouts: CalloutStyle::default_styles(),
+        }
+    }
+
+    /// Returns the argument `unicode` for
+    /// [`escape_str_with`](latex_escape/fn.escape_str_with.html).
+    pub(crate) fn unicode_escaping(&self) -> Option<&UnicodeFallback> {
+        Some(&self.unicode_fallback)
+            .filter(|_| self.escape_unicode && !self.engine.supports_unicode())
+    }
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        Self::for_dialect(InputDialect::default())
+    }
+}
+
+/// A flavor of markdown, used to select default [`ConversionOptions`].
+///
+/// See [`ConversionOptions::for_dialect`].
+///
+/// [`ConversionOptions`]: struct.ConversionOptions.html
+/// [`ConversionOptions::for_dialect`]: struct.ConversionOptions.html#method.for_dialect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputDialect {
+    /// Markdown exported from Dropbox Paper.
+    ///
+    /// Dropbox Paper exports hard line breaks as soft line breaks, and it uses
+    /// `$$...$$` for inline math.
+    #[default]
+    DropboxPaper,
+
+    /// Plain CommonMark with LaTeX-style math delimiters.
+    CommonMark,
+
+    /// GitHub Flavored Markdown with LaTeX-style math delimiters.
+    Gfm,
+
+    /// Markdown written in Obsidian.
+    ///
+    /// Obsidian displays the file name as the title of a note, so a level 1 heading
+    /// at the beginning of the note is typeset as a section heading.
+    Obsidian,
+}
+
+impl FromStr for InputDialect {
+    type Err = String;
+
+    /// Parses a dialect name such as `"paper"`, `"commonmark"`, `"gfm"`, or
+    /// `"obsidian"` (case insensitive).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "paper" | "dropbox-paper" | "dropboxpaper" => Ok(InputDialect::DropboxPaper),
+            "commonmark" | "markdown" => Ok(InputDialect::CommonMark),
+            "gfm" | "github" => Ok(InputDialect::Gfm),
+            "obsidian" => Ok(InputDialect::Obsidian),
+            _ => Err(format!(
+                "Unknown input dialect \"{}\". Expected one of \"paper\", \"commonmark\", \
+                 \"gfm\", or \"obsidian\".",
+                s
+            )),
+        }
+    }
+}
+
+/// A LaTeX engine, used to select packages that are compatible with it.
+///
+/// See [`ConversionOptions::engine`].
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::{ConversionOptions, Engine, MarkdownToLatex};
+///
+/// let options = ConversionOptions {
+///     engine: Engine::XeLatex,
+///     ..Default::default()
+/// };
+/// let markdown = "Unicode: x ≤ y.".to_string();
+/// let latex = MarkdownToLatex::with_options(markdown, options).into_string();
+///
+/// assert!(latex.find("\\usepackage{fontspec}").is_some());
+/// assert!(latex.find("inputenc").is_none());
+/// assert!(latex.find("Unicode: x ≤ y.").is_some());
+/// ```
+///
+/// [`ConversionOptions::engine`]: struct.ConversionOptions.html#structfield.engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// pdfLaTeX, which needs `inputenc` and escaping of most non-ASCII characters.
+    #[default]
+    PdfLatex,
+
+    /// XeLaTeX, e.g., as used by tectonic. Uses `fontspec` and `polyglossia`.
+    XeLatex,
+
+    /// LuaLaTeX. Uses `fontspec` and `polyglossia`, and `lua-ul` for highlighting.
+    LuaLatex,
+}
+
+impl Engine {
+    /// Whether the engine supports Unicode input natively (using `fontspec`).
+    pub fn supports_unicode(self) -> bool {
+        self != Engine::PdfLatex
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    /// Parses an engine name such as `"pdflatex"`, `"xelatex"`, or `"lualatex"` (case
+    /// insensitive).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pdflatex" | "pdftex" => Ok(Engine::PdfLatex),
+            "xelatex" | "xetex" | "tectonic" => Ok(Engine::XeLatex),
+            "lualatex" | "luatex" => Ok(Engine::LuaLatex),
+            _ => Err(format!(
+                "Unknown LaTeX engine \"{}\". Expected one of \"pdflatex\", \"xelatex\", \
+                 or \"lualatex\".",
+                s
+            )),
+        }
+    }
+}
+
+/// How soft line breaks (i.e., single newlines within a paragraph) are typeset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftBreak {
+    /// Typeset soft line breaks as line breaks (`\\`).
+    ///
+    /// This is necessary for markdown exported from Dropbox Paper, which uses soft
+    /// line breaks where it should use hard line breaks.
+    LineBreak,
+
+    /// Typeset soft line breaks as regular spaces between words, as required by the
+    /// CommonMark specification.
+    Space,
+}
+
+/// What to do with non-ASCII characters for which there is no LaTeX replacement
+/// (e.g., emoji).
+///
+/// See [`ConversionOptions::unicode_fallback`].
+///
+/// [`ConversionOptions::unicode_fallback`]: struct.ConversionOptions.html#structfield.unicode_fallback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnicodeFallback {
+    /// Write the character unchanged.
+    Keep,
+
+    /// Write the given string (e.g., `"?"`) instead of the character.
+    Replace(String),
+
+    /// Write the character unchanged and log a warning.
+    Warn,
+}
+
+/// Markdown syntax extensions that can be recognized in addition to CommonMark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarkdownExtensions {
+    /// Recognize GitHub style tables.
+    pub tables: bool,
+
+    /// Recognize footnote references (`[^1]`) and definitions (`[^1]: ...`).
+    pub footnotes: bool,
+
+    /// Recognize strikethrough (`~~text~~`).
+    pub strikethrough: bool,
+
+    /// Recognize task list items (`- [ ] ...` and `- [x] ...`).
+    pub tasklists: bool,
+
+    /// Recognize heading attributes (e.g., `# Heading {#id .class}`) at the end of
+    /// ATX headings.
+    ///
+    /// Heading attributes are removed from the heading text. An identifier (`#id`)
+    /// is turned into a `\label{id}` so that the section can be referenced.
+    pub heading_attributes: bool,
+
+    /// Recognize ordered lists with alphabetic or Roman numbering (e.g., `a.`,
+    /// `B)`, or `iv.`), as in pandoc's `fancy_lists` extension.
+    ///
+    /// Such lists are typeset with the corresponding `enumitem` label (e.g.,
+    /// `label=\alph*.`). As in pandoc, an uppercase letter followed by a period must
+    /// be followed by at least two spaces. Off in all dialect presets because it turns
+    /// a paragraph such as "e. coli is a bacterium." into a list.
+    pub fancy_lists: bool,
+
+    /// Recognize pandoc-style definition lists, i.e., a term on a line of its own,
+    /// followed by one or more definitions that start with `:` or `~`:
+    ///
+    /// ```text
+    /// Term
+    /// :   Definition
+    /// ```
+    ///
+    /// Definition lists are typeset as `description` environments.
+    pub definition_lists: bool,
+}
+
+impl MarkdownExtensions {
+    /// No extensions, i.e., plain CommonMark.
+    pub const NONE: Self = Self {
+        tables: false,
+        footnotes: false,
+        strikethrough: false,
+        tasklists: false,
+        heading_attributes: false,
+        fancy_lists: false,
+        definition_lists: false,
+    };
+
+    pub(crate) fn parser_options(self) -> pulldown_cmark::Options {
+        let mut options = pulldown_cmark::Options::empty();
+        options.set(pulldown_cmark::Options::ENABLE_TABLES, self.tables);
+        options.set(pulldown_cmark::Options::ENABLE_FOOTNOTES, self.footnotes);
+        options.set(
+            pulldown_cmark::Options::ENABLE_STRIKETHROUGH,
+            self.strikethrough,
+        );
+        options.set(pulldown_cmark::Options::ENABLE_TASKLISTS, self.tasklists);
+        options
+    }
+}
+
+/// The conventions for marking up math in the markdown source.
+///
+/// A pair of `$$` always delimits a math span. The fields of this struct control
+/// which additional delimiters are recognized and whether `$$...$$` is typeset as
+/// inline or display math. Math delimiters are ignored in code blocks, code spans,
+/// and link destinations, and after a backslash escape (e.g., `\$`).
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::{ConversionOptions, MarkdownToLatex, MathDelimiters};
+///
+/// let options = ConversionOptions {
+///     math_delimiters: MathDelimiters::LATEX,
+///     ..Default::default()
+/// };
+/// let markdown = "Inline $x^2$ and \\(y\\) for $5 or $10:\n$$\nz\n$$".to_string();
+/// let latex = MarkdownToLatex::with_options(markdown, options).into_string();
+///
+/// assert!(latex
+///     .find("Inline $x^2$ and $y$ for \\$5 or \\$10: \\\\\n\\[z\\]\n")
+///     .is_some());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MathDelimiters {
+    /// Recognize `$...$` as inline math.
+    ///
+    /// To avoid misinterpreting currency amounts such as "$5 or $10" as math, an
+    /// opening `$` must be directly followed by a non-whitespace character, and a
+    /// closing `$` must directly follow a non-whitespace character and must not be
+    /// directly followed by a digit.
+    pub single_dollar: bool,
+
+    /// Typeset `$$...$$` as display math rather than inline math.
+    pub double_dollar_display: bool,
+
+    /// Recognize `\(...\)` as inline math and `\[...\]` as display math.
+    pub backslash: bool,
+}
+
+impl MathDelimiters {
+    /// Conventions of Dropbox Paper: `$$...$$` for inline math, nothing else.
+    pub const DROPBOX_PAPER: Self = Self {
+        single_dollar: false,
+        double_dollar_display: false,
+        backslash: false,
+    };
+
+    /// Conventions of LaTeX (and of tools that follow it, such as Obsidian, Typora,
+    /// Jupyter, and pandoc): `$...$` and `\(...\)` for inline math, `$$...$$` and
+    /// `\[...\]` for display math.
+    pub const LATEX: Self = Self {
+        single_dollar: true,
+        double_dollar_display: true,
+        backslash: true,
+    };
+}
+
+impl Default for MathDelimiters {
+    fn default() -> Self {
+        Self::DROPBOX_PAPER
+    }
+}
+
+/// Names of sections that are typeset in a special way.
+///
+/// A heading matches a name if its text is equal to the name up to case and an
+/// optional trailing colon or period, or if it starts with the name followed by a
+/// colon (e.g., "Appendix: Proofs"). Set a list to empty to turn off the
+/// corresponding special treatment.
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::{ConversionOptions, MarkdownToLatex};
+///
+/// let markdown = concat!(
+///     "# Title\n\n## Abstract\n\nWe study things.\n\n",
+///     "## Introduction\n\nText.\n\n## Appendix\n\nProofs.\n\n",
+///     "## References\n\n1. A paper.\n2. Another paper.",
+/// );
+/// let options = ConversionOptions::default();
+/// let latex = MarkdownToLatex::with_options(markdown.to_string(), options).into_string();
+///
+/// assert!(latex
+///     .find(concat!(
+///         "\\maketitle\n\n\\begin{abstract}\nWe study things.\n\\end{abstract}\n\n\n",
+///         "\\subsection{Introduction}\n\nText.\n\n\n",
+///         "\\appendix\n\\subsection{Appendix}\n\nProofs.\n\n\n",
+///         "\\begin{thebibliography}{99}\n",
+///         "  \\bibitem{ref1} A paper.\n",
+///         "  \\bibitem{ref2} Another paper.\n",
+///         "\\end{thebibliography}\n",
+///     ))
+///     .is_some());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionNames {
+    /// Names of a section that is typeset as the abstract.
+    ///
+    /// Only recognized if it appears before the first regular section and if there
+    /// is no abstract in the front matter (see module
+    /// [`metadata`](metadata/index.html)). The section extends up to the next
+    /// heading.
+    pub abstract_names: Vec<String>,
+
+    /// Names of a section that starts the appendix.
+    ///
+    /// The first such section is preceded by `\appendix`, which switches to
+    /// alphabetic section numbering. The heading itself is kept.
+    pub appendix_names: Vec<String>,
+
+    /// Names of a section that contains the list of references.
+    ///
+    /// The section is typeset as a `thebibliography` environment, which generates
+    /// its own heading. Each top level list item or paragraph in the section becomes
+    /// a `\bibitem`. The section extends up to the next heading.
+    ///
+    /// If the document cites any works (see
+    /// [`ConversionOptions::cite_links`](struct.ConversionOptions.html#structfield.cite_links))
+    /// then the generated bibliography is typeset in place of the section instead.
+    pub bibliography_names: Vec<String>,
+}
+
+impl SectionNames {
+    /// Turns off all special treatment of sections.
+    pub fn none() -> Self {
+        Self {
+            abstract_names: Vec::new(),
+            appendix_names: Vec::new(),
+            bibliography_names: Vec::new(),
+        }
+    }
+
+    pub(crate) fn classify(&self, heading: &str) -> Option<SectionKind> {
+        let heading = heading.trim_matches(|c: char| c == '*' || c == '_' || c.is_whitespace());
+        let matches = |names: &[String]| {
+            names.iter().any(|name| {
+                let name = name.as_str();
+                heading.len() >= name.len()
+                    && heading.is_char_boundary(name.len())
+                    && heading[..name.len()].eq_ignore_ascii_case(name)
+                    && match heading[name.len()..].trim_end() {
+                        "" | ":" | "." => true,
+                        rest => rest.starts_with(':'),
+                    }
+            })
+        };
+
+        if matches(&self.abstract_names) {
+            Some(SectionKind::Abstract)
+        } else if matches(&self.appendix_names) {
+            Some(SectionKind::Appendix)
+        } else if matches(&self.bibliography_names) {
+            Some(SectionKind::Bibliography)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for SectionNames {
+    /// Returns the English names "Abstract", "Summary", "Appendix", "Appendices",
+    /// "References", and "Bibliography".
+    fn default() -> Self {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        Self {
+            abstract_names: names(&["Abstract", "Summary"]),
+            appendix_names: names(&["Appendix", "Appendices"]),
+            bibliography_names: names(&["References", "Bibliography"]),
+        }
+    }
+}
+
+/// A type of callout block and how it is typeset, see
+/// [`ConversionOptions::callouts`](struct.ConversionOptions.html#structfield.callouts).
+///
+/// # Example
+///
+/// ```
+/// use bluepaper_core::{CalloutStyle, ConversionOptions, MarkdownToLatex};
+///
+/// let mut options = ConversionOptions::default();
+/// options.callouts.push(CalloutStyle::new("Question", "Open Question", "teal"));
+///
+/// let markdown = "> **Question:** Does it work?".to_string();
+/// let latex = MarkdownToLatex::with_options(markdown, options).into_string();
+///
+/// assert!(latex
+///     .find("\\newtcolorbox{questionbox}{colback=teal!5!white,colframe=teal!75!black,\
+///            fonttitle=\\bfseries,title={Open Question}}")
+///     .is_some());
+/// assert!(latex
+///     .find("\\begin{questionbox}\n\nDoes it work?\n\n\\end{questionbox}")
+///     .is_some());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalloutStyle {
+    /// The name of the callout type in the markdown source (e.g., "Warning" for
+    /// `**Warning:**` or `[!WARNING]`). Matched case-insensitively.
+    pub name: String,
+
+    /// The title of the box.
+    pub title: String,
+
+    /// The color of the box, as an `xcolor` color name. The background is a light
+    /// tint of this color.
+    pub color: String,
+}
+
+impl CalloutStyle {
+    pub fn new(name: &str, title: &str, color: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            title: title.to_string(),
+            color: color.to_string(),
+        }
+    }
+
+    /// Returns the five types of GitHub alerts "Note", "Tip", "Important",
+    /// "Warning", and "Caution".
+    pub fn default_styles() -> Vec<Self> {
+        vec![
+            Self::new("Note", "Note", "blue"),
+            Self::new("Tip", "Tip", "green"),
+            Self::new("Important", "Important", "violet"),
+            Self::new("Warning", "Warning", "orange"),
+            Self::new("Caution", "Caution", "red"),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SectionKind {
+    Abstract,
+    Appendix,
+    Bibliography,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unicode_escaping() {
+        let options = ConversionOptions::default();
+        assert_eq!(options.unicode_escaping(), Some(&UnicodeFallback::Warn));
+
+        // The engine takes precedence over `escape_unicode`.
+        for &engine in &[Engine::XeLatex, Engine::LuaLatex] {
+            let options = ConversionOptions {
+                engine,
+                ..ConversionOptions::default()
+            };
+            assert_eq!(options.unicode_escaping(), None);
+        }
+
+        let options = ConversionOptions {
+            escape_unicode: false,
+            ..ConversionOptions::default()
+        };
+        assert_eq!(options.unicode_escaping(), None);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!("GitHub".parse(), Ok(InputDialect::Gfm));
+        assert_eq!("paper".parse(), Ok(InputDialect::DropboxPaper));
+        assert!("pandoc".parse::<InputDialect>().is_err());
+
+        assert_eq!("tectonic".parse(), Ok(Engine::XeLatex));
+        assert_eq!("LuaLaTeX".parse(), Ok(Engine::LuaLatex));
+        assert!("context".parse::<Engine>().is_err());
+    }
+
+    #[test]
+    fn section_names() {
+        let names = SectionNames::default();
+        assert_eq!(names.classify("**Abstract:**"), Some(SectionKind::Abstract));
+        assert_eq!(
+            names.classify("Appendix: Proofs"),
+            Some(SectionKind::Appendix)
+        );
+        assert_eq!(
+            names.classify("references."),
+            Some(SectionKind::Bibliography)
+        );
+        assert_eq!(names.classify("Abstraction"), None);
+        assert_eq!(names.classify("Appendix A"), None);
+        assert_eq!(SectionNames::none().classify("Abstract"), None);
+    }
+
+    #[test]
+    fn parser_options() {
+        let options = ConversionOptions::for_dialect(InputDialect::Gfm)
+            .extensions
+            .parser_options();
+        assert_eq!(
+            options,
+            pulldown_cmark::Options::ENABLE_TABLES
+                | pulldown_cmark::Options::ENABLE_FOOTNOTES
+                | pulldown_cmark::Options::ENABLE_STRIKETHROUGH
+                | pulldown_cmark::Options::ENABLE_TASKLISTS
+        );
+        assert!(MarkdownExtensions::NONE.parser_options().is_empty());
+    }
+}
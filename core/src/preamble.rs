@@ -0,0 +1,188 @@
+//! Generation of the LaTeX preamble.
+
+use crate::{Engine, Language};
+
+use std::io::Write;
+
+const TEMPLATE: &str = include_str!("preamble.tex");
+const DOCUMENT_CLASS: &str = "\\documentclass{article}\n\n";
+const BEGIN_DOCUMENT: &str = "\\begin{document}\n";
+
+/// The LaTeX preamble, i.e., everything up to and including `\begin{document}`.
+///
+/// Starts from a fixed template (see `preamble.tex`), into which the packages for
+/// font and input encoding of the target engine and for the document language are
+/// inserted right after `\documentclass`. Commands that are needed only by some
+/// documents (e.g., `\usepackage{natbib}` for documents with citations) are added on
+/// demand and written in a separate block before `\begin{document}`.
+#[derive(Debug, Clone)]
+pub struct Preamble {
+    engine: Engine,
+    language: Option<Language>,
+    lines: Vec<String>,
+}
+
+impl Preamble {
+    pub fn new(engine: Engine, language: Option<Language>) -> Self {
+        Self {
+            engine,
+            language,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Adds `\usepackage[options]{name}`, where `options` is a comma separated list.
+    ///
+    /// If the package is already used then any new options are added to the existing
+    /// `\usepackage` (which keeps its position).
+    pub fn use_package(&mut self, name: &str, options: &str) {
+        let suffix = format!("{{{}}}", name);
+        let existing = self
+            .lines
+            .iter()
+            .position(|line| line.starts_with(r"\usepackage") && line.ends_with(&suffix));
+        let mut all_options = existing.map_or_else(Vec::new, |index| {
+            let line = &self.lines[index];
+            line[r"\usepackage".len()..line.len() - suffix.len()]
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split(',')
+                .filter(|option| !option.is_empty())
+                .map(str::to_string)
+                .collect()
+        });
+        for option in options.split(',').map(str::trim) {
+            if !option.is_empty() && !all_options.iter().any(|existing| existing == option) {
+                all_options.push(option.to_string());
+            }
+        }
+
+        let line = if all_options.is_empty() {
+            format!(r"\usepackage{}", suffix)
+        } else {
+            format!(r"\usepackage[{}]{}", all_options.join(","), suffix)
+        };
+        match existing {
+            Some(index) => self.lines[index] = line,
+            None => self.lines.push(line),
+        }
+    }
+
+    /// Adds an arbitrary line (e.g., a declaration) unless it was already added.
+    pub fn add_line(&mut self, line: String) {
+        if !self.lines.contains(&line) {
+            self.lines.push(line);
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        debug_assert!(TEMPLATE.starts_with(DOCUMENT_CLASS));
+        debug_assert!(TEMPLATE.ends_with(BEGIN_DOCUMENT));
+        writer.write_all(DOCUMENT_CLASS.as_bytes())?;
+        match self.engine {
+            Engine::PdfLatex => {
+                writer.write_all(b"\\usepackage[utf8]{inputenc}\n")?;
+                if let Some(language) = self.language {
+                    if language != Language::English {
+                        // Proper hyphenation of words with accented characters.
+                        writer.write_all(b"\\usepackage[T1]{fontenc}\n\\usepackage{lmodern}\n")?;
+                    }
+                    // Without shorthands, characters such as `"` aren't active.
+                    writeln!(
+                        writer,
+                        r"\usepackage[shorthands=off,{}]{{babel}}",
+                        language.babel_name()
+                    )?;
+                }
+            }
+            Engine::XeLatex | Engine::LuaLatex => {
+                writer.write_all(b"\\usepackage{fontspec}\n\\usepackage{polyglossia}\n")?;
+                let language = self.language.unwrap_or(Language::English);
+                writeln!(
+                    writer,
+                    r"\setmainlanguage{{{}}}",
+                    language.polyglossia_name()
+                )?;
+            }
+        }
+        if self.language.is_some() {
+            writer.write_all(b"\\usepackage[autostyle]{csquotes}\n")?;
+        }
+        writer.write_all(
+            &TEMPLATE.as_bytes()[DOCUMENT_CLASS.len()..TEMPLATE.len() - BEGIN_DOCUMENT.len()],
+        )?;
+        if !self.lines.is_empty() {
+            for line in &self.lines {
+                writer.write_all(line.as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(BEGIN_DOCUMENT.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn preamble(engine: Engine, language: Option<Language>) -> String {
+        let mut latex = Vec::new();
+        Preamble::new(engine, language)
+            .write_to(&mut latex)
+            .unwrap();
+        String::from_utf8(latex).unwrap()
+    }
+
+    #[test]
+    fn engines() {
+        let pdflatex = preamble(Engine::PdfLatex, None);
+        assert!(pdflatex.starts_with("\\documentclass{article}\n\n\\usepackage[utf8]{inputenc}\n"));
+        assert!(!pdflatex.contains("babel"));
+        assert!(!pdflatex.contains("fontspec"));
+        assert!(!pdflatex.contains("csquotes"));
+
+        let pdflatex = preamble(Engine::PdfLatex, Some(Language::German));
+        assert!(pdflatex.contains(
+            "\\usepackage[utf8]{inputenc}\n\\usepackage[T1]{fontenc}\n\\usepackage{lmodern}\n\
+             \\usepackage[shorthands=off,ngerman]{babel}\n\\usepackage[autostyle]{csquotes}\n"
+        ));
+
+        let pdflatex = preamble(Engine::PdfLatex, Some(Language::English));
+        assert!(pdflatex.contains("\\usepackage[shorthands=off,english]{babel}\n"));
+        assert!(!pdflatex.contains("fontenc"));
+
+        for &engine in &[Engine::XeLatex, Engine::LuaLatex] {
+            let latex = preamble(engine, None);
+            assert!(latex.contains(
+                "\\usepackage{fontspec}\n\\usepackage{polyglossia}\n\\setmainlanguage{english}\n"
+            ));
+            assert!(!latex.contains("inputenc"));
+            assert!(!latex.contains("csquotes"));
+
+            let latex = preamble(engine, Some(Language::German));
+            assert!(
+                latex.contains("\\setmainlanguage{german}\n\\usepackage[autostyle]{csquotes}\n")
+            );
+            assert!(!latex.contains("babel"));
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let mut preamble = Preamble::new(Engine::PdfLatex, None);
+        preamble.use_package("enumitem", "");
+        preamble.use_package("natbib", "numbers");
+        preamble.use_package("enumitem", "shortlabels");
+        preamble.use_package("natbib", "sort,numbers");
+        preamble.add_line(r"\setlistdepth{7}".to_string());
+        preamble.add_line(r"\setlistdepth{7}".to_string());
+        let mut latex = Vec::new();
+        preamble.write_to(&mut latex).unwrap();
+
+        assert!(String::from_utf8(latex).unwrap().ends_with(
+            "\n\n\\usepackage[shortlabels]{enumitem}\n\\usepackage[numbers,sort]{natbib}\n\
+                 \\setlistdepth{7}\n\n\\begin{document}\n"
+        ));
+    }
+}
@@ -1,62 +1,143 @@
+use crate::options::MathDelimiters;
+
 use log::warn;
-use pulldown_cmark::CowStr;
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
 
 use std::ops::Range;
 
+#[derive(Clone)]
 pub struct Replacer {
-    positions: Vec<usize>,
+    delimiters: Vec<Delimiter>,
     index: usize,
 }
 
+/// A math delimiter that was replaced by backticks of the same length.
+#[derive(Clone)]
+struct Delimiter {
+    pos: usize,
+    original: &'static str,
+    display: bool,
+}
+
+/// The location of a math span in the preprocessed markdown text.
+pub struct MathSpan {
+    /// The range of the math span's content, i.e., without the delimiters.
+    pub content: Range<usize>,
+
+    /// Whether the math span should be typeset as display math.
+    pub display: bool,
+
+    /// The original opening and closing delimiters.
+    pub delimiters: (&'static str, &'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MathKind {
+    /// `$$...$$`
+    DoubleDollar,
+    /// `$...$`
+    SingleDollar,
+    /// `\(...\)`
+    Parenthesis,
+    /// `\[...\]`
+    Bracket,
+}
+
+impl MathKind {
+    fn opening(self) -> &'static str {
+        match self {
+            MathKind::DoubleDollar => "$$",
+            MathKind::SingleDollar => "$",
+            MathKind::Parenthesis => r"\(",
+            MathKind::Bracket => r"\[",
+        }
+    }
+
+    fn closing(self) -> &'static str {
+        match self {
+            MathKind::DoubleDollar => "$$",
+            MathKind::SingleDollar => "$",
+            MathKind::Parenthesis => r"\)",
+            MathKind::Bracket => r"\]",
+        }
+    }
+
+    fn is_display(self, delimiters: MathDelimiters) -> bool {
+        match self {
+            MathKind::DoubleDollar => delimiters.double_dollar_display,
+            MathKind::SingleDollar | MathKind::Parenthesis => false,
+            MathKind::Bracket => true,
+        }
+    }
+}
+
 /// A Preprocessor for "Dropbox flavoured" markdown to turn it into CommonMark.
 impl Replacer {
-    /// Replace LaTeX deliminators "$$" with "``" and remember replacement points.
+    /// Replace LaTeX math delimiters with backticks and remember replacement points.
+    ///
+    /// Mutates the input string in place. Each math delimiter is replaced with a
+    /// string of backticks of the same length (e.g., "$$" becomes "``"). These
+    /// replacements ensure that that pulldown-cmark treats the content as code and
+    /// does not misinterpret, e.g., underscores "_" or stars "*" in LaTeX code for
+    /// emphasis markers.
+    ///
+    /// The recognized delimiters are controlled by `delimiters`. "$$" is always
+    /// recognized. Depending on `delimiters`, "$...$", "\(...\)", and "\[...\]" are
+    /// recognized as well. A single "$" only opens a math span if it is directly
+    /// followed by a non-whitespace character, and it only closes a math span if it
+    /// directly follows a non-whitespace character and is not directly followed by a
+    /// digit. Further, a math span delimited by single dollar signs must not contain
+    /// any other unescaped dollar signs. These rules prevent currency amounts like
+    /// "$5 or $10" from being interpreted as math.
+    ///
+    /// Only pairs of delimiters that actually delimit a math span are replaced. The
+    /// input is pre-scanned with a simplified model of CommonMark's block and inline
+    /// structure so that delimiters are left alone if they appear:
+    /// - in a fenced code block (delimited by "```" or "~~~");
+    /// - in an inline code span (delimited by backtick strings of equal length);
+    /// - directly after a backslash escape "\$";
+    /// - in a link destination, i.e., inside the parentheses of "[text](...)", or in
+    ///   an autolink such as "<https://...>"; or
+    /// - without a matching closing delimiter in the same paragraph.
     ///
-    /// Mutates the input string in place. These replacements ensure that that
-    /// pulldown-cmark treats the content as code and does not misinterpret, e.g.,
-    /// underscores "_" or stars "*" in LaTeX code for emphasis markers. This
-    /// approach to treating math blocks is not perfect and can be tricked, but
-    /// the possible ambiguities are unlikely to appear unintentionally.
+    /// Inside a math span, a backslash escapes the following character so that, e.g.,
+    /// "$$\$$$" is a math span containing the LaTeX command "\$". A math span whose
+    /// content contains a string of backticks of the same length as its delimiters is
+    /// not replaced because it could not be parsed as a code span. The model is not a
+    /// full CommonMark parser (e.g., it ignores indented code blocks and HTML blocks),
+    /// so pulldown-cmark may occasionally disagree with it. In this case, the
+    /// replacements can be reverted with `un_replace`.
     ///
     /// Returns a struct that internally contains a list of the replacement points.
     /// This serves two purposes:
     /// - It allows to check if a code block emitted by the parser is a regular
     ///   code block or a math span turned into a code block during preprocessing
-    ///   (using `check_if_replacement_point`).
-    /// - It allows to revert preprocessing on substrings in case any unpaired "$$"
-    ///   existed.
+    ///   (using `math_span`).
+    /// - It allows to revert preprocessing on substrings in case pulldown-cmark
+    ///   did not recognize a math span as a code span.
     ///
     /// See unit tests for more.
-    pub fn replace(input: &mut String) -> Self {
+    pub fn replace(input: &mut String, delimiters: MathDelimiters) -> Self {
         let mut replacer = Replacer {
-            positions: Vec::new(),
+            delimiters: find_math_delimiters(input.as_bytes(), delimiters),
             index: 0,
         };
 
-        unsafe {
-            let bytes = input.as_bytes_mut();
-            if bytes.len() >= 5 {
-                // Need at least 5 characters to make a non-empty inline math span: "$$x$$"
-                for i in 0..bytes.len() - 1 {
-                    if *bytes.get_unchecked(i) == b'$'
-                        && *bytes.get_unchecked(i + 1) == b'$'
-                        && (i == 0 || *bytes.get_unchecked(i - 1) != b'`')
-                        && (i == bytes.len() - 1 || *bytes.get_unchecked(i + 2) != b'`')
-                    {
-                        replacer.positions.push(i);
-                        *bytes.get_unchecked_mut(i) = b'`';
-                        *bytes.get_unchecked_mut(i + 1) = b'`';
-                    }
-                }
-            }
-
-            // Push end sentinel so that we don't have to check for out-of-bounds.
-            replacer.positions.push(input.len());
-            replacer
+        for delimiter in &replacer.delimiters {
+            let len = delimiter.original.len();
+            input.replace_range(delimiter.pos..delimiter.pos + len, &"``"[..len]);
         }
+
+        // Push end sentinel so that we don't have to check for out-of-bounds.
+        replacer.delimiters.push(Delimiter {
+            pos: usize::MAX,
+            original: "",
+            display: false,
+        });
+        replacer
     }
 
-    /// Check if, at position `pos` and `pos + 1`, a "$$" was replaced by "``".
+    /// Check if, at position `pos`, a math delimiter was replaced by backticks.
     /// This consumes all memorized replacement points before `pos` so that
     /// it runs in O(n) instead of O(n^2) time. Thus, always call with with
     /// increasing `pos` values.
@@ -64,61 +145,501 @@ impl Replacer {
         self.skip_smaller_than(pos) == pos
     }
 
+    /// Check if `range` is exactly the range of a math span, including its (replaced)
+    /// delimiters, and return the math span if it is.
+    ///
+    /// Like `check_if_replacement_point`, this consumes all memorized replacement
+    /// points before `range.start`.
+    pub fn math_span(&mut self, range: Range<usize>) -> Option<MathSpan> {
+        if !self.check_if_replacement_point(range.start) || !self.index.is_multiple_of(2) {
+            return None;
+        }
+
+        let opening = &self.delimiters[self.index];
+        let closing = &self.delimiters[self.index + 1];
+        if closing.pos + closing.original.len() == range.end {
+            Some(MathSpan {
+                content: opening.pos + opening.original.len()..closing.pos,
+                display: opening.display,
+                delimiters: (opening.original, closing.original),
+            })
+        } else {
+            None
+        }
+    }
+
     /// Revert any possible replacements performed within a given substring `s`
     /// of the preprocessed markdown text. `range` must be the correct range,
     /// i.e., if the `Replacer` was created from an input string `input`, then
     /// `s` must be the preprocessed portion of `input[range]`. Consumes all
     /// memorized replacement points up to the end of the range, consecutive
-    /// calls must not jump back. Allocates only
+    /// calls must not jump back. Allocates only if there is something to revert.
     pub fn un_replace<'a>(&mut self, s: CowStr<'a>, range: Range<usize>) -> CowStr<'a> {
         if s.len() != range.len() {
             warn!(
-                "Ambiguous use of \"$$\"$. Some generated text may contain spurious \"`\" \
-                 characters instead of \"$\"."
+                "Ambiguous use of math delimiters. Some generated text may contain \
+                 spurious \"`\" characters instead of \"$\"."
             );
             return s;
         }
 
-        let mut pos = self.skip_smaller_than(range.start);
-        if s.len() >= 2 && pos < range.end - 1 {
-            unsafe {
-                let mut s = s.into_string();
-                let bytes = s.as_bytes_mut();
-                loop {
-                    let b1 = bytes.get_unchecked_mut(pos - range.start);
-                    assert_eq!(*b1, b'`');
-                    *b1 = b'$';
-
-                    let b2 = bytes.get_unchecked_mut(pos - range.start + 1);
-                    assert_eq!(*b2, b'`');
-                    *b2 = b'$';
-
-                    self.index += 1;
-                    pos = self.positions[self.index]; // Cannot fail because of end sentinel.
-                    if pos >= range.end - 1 {
-                        return s.into();
+        let pos = self.skip_smaller_than(range.start);
+        if pos >= range.end {
+            // Nothing to replace.
+            return s;
+        }
+
+        let mut s = s.into_string();
+        loop {
+            let delimiter = &self.delimiters[self.index]; // Cannot fail because of end sentinel.
+            let len = delimiter.original.len();
+            if delimiter.pos >= range.end || delimiter.pos + len > range.end {
+                return s.into();
+            }
+
+            let start = delimiter.pos - range.start;
+            assert!(s.as_bytes()[start..start + len].iter().all(|&b| b == b'`'));
+            s.replace_range(start..start + len, delimiter.original);
+            self.index += 1;
+        }
+    }
+
+    fn skip_smaller_than(&mut self, pos: usize) -> usize {
+        let num_skip = self.delimiters[self.index..]
+            .iter()
+            .position(|delimiter| delimiter.pos >= pos)
+            .unwrap(); // Cannot fail because of end sentinel.
+        self.index += num_skip;
+        self.delimiters[self.index].pos
+    }
+}
+
+/// Removes heading attributes (e.g., "{#id .class}") from the end of ATX headings.
+///
+/// Mutates the input string in place by replacing each block of heading attributes
+/// with spaces so that the byte offsets of the remaining text don't change (trailing
+/// whitespace in ATX headings is ignored by the parser). Headings in fenced code blocks
+/// are left alone.
+///
+/// Returns the position of each removed block of heading attributes together with the
+/// identifier specified in it (without the leading "#"). Blocks of heading attributes
+/// without an identifier are removed but not returned.
+pub fn strip_heading_attributes(input: &mut String) -> Vec<(usize, String)> {
+    let mut blocks = Vec::new();
+    let bytes = input.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let line_end = find_line_end(bytes, i);
+        let line = &bytes[i..line_end];
+        if let Some(fence) = code_fence(line) {
+            i = skip_fenced_code_block(bytes, line_end, fence);
+            continue;
+        }
+
+        let content = strip_line_prefix(line);
+        let level = content.iter().take_while(|&&b| b == b'#').count();
+        let is_atx_heading =
+            (1..=6).contains(&level) && content.get(level).is_none_or(|&b| b == b' ' || b == b'\t');
+        if is_atx_heading {
+            let trimmed_len = line.len()
+                - line
+                    .iter()
+                    .rev()
+                    .take_while(|b| b.is_ascii_whitespace())
+                    .count();
+            let trimmed = &line[..trimmed_len];
+            if trimmed.ends_with(b"}") {
+                if let Some(open) = trimmed.iter().rposition(|&b| b == b'{') {
+                    // Cannot fail because the slice is delimited by ASCII characters.
+                    let attributes = std::str::from_utf8(&trimmed[open + 1..trimmed_len - 1]);
+                    if let Ok(Some(id)) = attributes.map(parse_heading_attributes) {
+                        blocks.push((i + open, trimmed_len - open, id.map(str::to_string)));
+                    }
+                }
+            }
+        }
+
+        i = line_end + 1;
+    }
+
+    blocks
+        .into_iter()
+        .filter_map(|(pos, len, id)| {
+            input.replace_range(pos..pos + len, &" ".repeat(len));
+            id.map(|id| (pos, id))
+        })
+        .collect()
+}
+
+/// Parses the content of a block of heading attributes (without the braces).
+///
+/// Returns `None` if `attributes` is not a valid block of heading attributes, and
+/// `Some(id)` otherwise, where `id` is the identifier, if any.
+fn parse_heading_attributes(attributes: &str) -> Option<Option<&str>> {
+    let mut id = None;
+    let mut any = false;
+    for attribute in attributes.split_ascii_whitespace() {
+        any = true;
+        if let Some(identifier) = attribute.strip_prefix('#') {
+            if identifier.is_empty() {
+                return None;
+            }
+            id = Some(identifier);
+        } else if !attribute.starts_with('.') && !attribute.contains('=') {
+            return None;
+        }
+    }
+
+    if any {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Returns the byte ranges of all runs of consecutive text events (e.g., "[", "@a",
+/// and "]") in the preprocessed markdown text, skipping code blocks, links, and
+/// images. Used to find citations and other syntax that pulldown-cmark doesn't
+/// know about.
+pub fn text_runs(markdown: &str, parser_options: Options) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut run: Option<Range<usize>> = None;
+    let mut excluded_depth = 0;
+
+    for (event, range) in Parser::new_ext(markdown, parser_options).into_offset_iter() {
+        match event {
+            Event::Text(_) if excluded_depth == 0 => {
+                run = Some(run.map_or(range.clone(), |run| run.start..range.end));
+                continue;
+            }
+            Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::Link(..))
+            | Event::Start(Tag::Image(..)) => excluded_depth += 1,
+            Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::Link(..))
+            | Event::End(Tag::Image(..)) => excluded_depth -= 1,
+            _ => {}
+        }
+        runs.extend(run.take());
+    }
+    runs.extend(run);
+
+    runs
+}
+
+/// Returns all opening and closing math delimiters in `bytes`.
+///
+/// The returned delimiters are sorted and come in pairs of opening and closing
+/// delimiters. See `Replacer::replace` for the rules.
+fn find_math_delimiters(bytes: &[u8], delimiters: MathDelimiters) -> Vec<Delimiter> {
+    let mut result = Vec::new();
+    let mut open_math: Option<(usize, MathKind)> = None;
+    let mut line_start = true;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if line_start {
+            line_start = false;
+            let line_end = find_line_end(bytes, i);
+            let line = &bytes[i..line_end];
+            if is_blank_line(line) {
+                // Math spans cannot cross paragraph boundaries.
+                open_math = None;
+                i = line_end + 1;
+                line_start = true;
+                continue;
+            } else if let Some(fence) = code_fence(line) {
+                open_math = None;
+                i = skip_fenced_code_block(bytes, line_end, fence);
+                line_start = true;
+                continue;
+            }
+        }
+
+        if bytes[i] == b'\n' {
+            line_start = true;
+            i += 1;
+            continue;
+        }
+
+        if let Some((start, kind)) = open_math {
+            let closing = kind.closing();
+            let closes = match bytes[i] {
+                b'$' if kind == MathKind::SingleDollar => {
+                    if !can_close_single_dollar(bytes, i) {
+                        // Math spans delimited by single dollar signs cannot contain any
+                        // other dollar signs. Reconsider this one as an opening delimiter.
+                        open_math = None;
+                        continue;
                     }
+                    true
+                }
+                b'$' => kind == MathKind::DoubleDollar && is_double_dollar(bytes, i),
+                b'\\' => bytes[i..].starts_with(closing.as_bytes()),
+                _ => false,
+            };
+
+            if closes {
+                open_math = None;
+                let len = closing.len();
+                if !contains_backtick_string(&bytes[start + len..i], len) {
+                    let display = kind.is_display(delimiters);
+                    result.push(Delimiter {
+                        pos: start,
+                        original: kind.opening(),
+                        display,
+                    });
+                    result.push(Delimiter {
+                        pos: i,
+                        original: closing,
+                        display,
+                    });
                 }
+                i += len;
+            } else if bytes[i] == b'\\' && i + 1 < bytes.len() && bytes[i + 1] != b'\n' {
+                i += 2;
+            } else {
+                i += 1;
+            }
+            continue;
+        }
+
+        match bytes[i] {
+            b'\\' if delimiters.backslash && bytes.get(i + 1) == Some(&b'(') => {
+                open_math = Some((i, MathKind::Parenthesis));
+                i += 2;
+            }
+            b'\\' if delimiters.backslash && bytes.get(i + 1) == Some(&b'[') => {
+                open_math = Some((i, MathKind::Bracket));
+                i += 2;
+            }
+            b'\\' if bytes.get(i + 1).is_some_and(u8::is_ascii_punctuation) => i += 2,
+            b'$' if is_double_dollar(bytes, i) => {
+                open_math = Some((i, MathKind::DoubleDollar));
+                i += 2;
+            }
+            b'$' if delimiters.single_dollar && can_open_single_dollar(bytes, i) => {
+                open_math = Some((i, MathKind::SingleDollar));
+                i += 1;
             }
+            b'$' => {
+                // Skip all dollar signs in a row so that, e.g., the last "$" in "$$$"
+                // is not mistaken for an opening delimiter.
+                i += bytes[i..].iter().take_while(|&&b| b == b'$').count();
+            }
+            b'`' => i = skip_code_span(bytes, i),
+            b']' if bytes.get(i + 1) == Some(&b'(') => i = skip_link_destination(bytes, i + 2),
+            b'<' => i = skip_autolink(bytes, i),
+            _ => i += 1,
+        }
+    }
+
+    result
+}
+
+fn can_open_single_dollar(bytes: &[u8], i: usize) -> bool {
+    (i == 0 || bytes[i - 1] != b'`')
+        && bytes
+            .get(i + 1)
+            .is_some_and(|&b| !b.is_ascii_whitespace() && b != b'$' && b != b'`')
+}
+
+fn can_close_single_dollar(bytes: &[u8], i: usize) -> bool {
+    !bytes[i - 1].is_ascii_whitespace()
+        && bytes[i - 1] != b'`'
+        && bytes
+            .get(i + 1)
+            .is_none_or(|&b| !b.is_ascii_digit() && b != b'$' && b != b'`')
+}
+
+/// Checks if `bytes` contains a string of exactly `len` backticks.
+fn contains_backtick_string(bytes: &[u8], len: usize) -> bool {
+    bytes
+        .split(|&b| b != b'`')
+        .any(|backticks| backticks.len() == len)
+}
+
+fn is_double_dollar(bytes: &[u8], i: usize) -> bool {
+    bytes.get(i + 1) == Some(&b'$')
+        && (i == 0 || bytes[i - 1] != b'`')
+        && bytes.get(i + 2) != Some(&b'`')
+}
+
+/// Returns the position of the next newline character at or after `start`, or the
+/// length of `bytes` if there is none.
+pub fn find_line_end(bytes: &[u8], start: usize) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |len| start + len)
+}
+
+/// Strips indentation and block quote markers from the beginning of a line.
+pub fn strip_line_prefix(line: &[u8]) -> &[u8] {
+    let len = line
+        .iter()
+        .position(|&b| b != b' ' && b != b'\t' && b != b'>')
+        .unwrap_or(line.len());
+    &line[len..]
+}
+
+pub fn is_blank_line(line: &[u8]) -> bool {
+    strip_line_prefix(line)
+        .iter()
+        .all(|b| b.is_ascii_whitespace())
+}
+
+/// Checks if `line` opens a fenced code block and, if so, returns the fence
+/// character and the length of the fence.
+pub fn code_fence(line: &[u8]) -> Option<(u8, usize)> {
+    let line = strip_line_prefix(line);
+    let fence_char = *line.first()?;
+    if fence_char != b'`' && fence_char != b'~' {
+        return None;
+    }
+
+    let len = line.iter().take_while(|&&b| b == fence_char).count();
+    if len < 3 || (fence_char == b'`' && line[len..].contains(&b'`')) {
+        None
+    } else {
+        Some((fence_char, len))
+    }
+}
+
+/// Returns the position after the line that closes a fenced code block, or the
+/// length of `bytes` if the code block is never closed.
+///
+/// The argument `start` has to point to the end of the line that opened the code
+/// block.
+pub fn skip_fenced_code_block(bytes: &[u8], start: usize, (fence_char, len): (u8, usize)) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        let line_end = find_line_end(bytes, i);
+        let line = strip_line_prefix(&bytes[i..line_end]);
+        let fence_len = line.iter().take_while(|&&b| b == fence_char).count();
+        if fence_len >= len && line[fence_len..].iter().all(|b| b.is_ascii_whitespace()) {
+            return line_end + 1;
+        }
+        i = line_end + 1;
+    }
+    bytes.len()
+}
+
+/// Returns the byte ranges of all fenced code blocks in the sense of `code_fence`
+/// and `skip_fenced_code_block`, i.e., as the preprocessing steps see them.
+///
+/// This may differ from what the markdown parser sees (e.g., a code block in a
+/// block quote ends with the block quote for the parser but not here).
+pub fn fenced_code_blocks(bytes: &[u8]) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let line_end = find_line_end(bytes, i);
+        if let Some(fence) = code_fence(&bytes[i..line_end]) {
+            let end = skip_fenced_code_block(bytes, line_end, fence);
+            blocks.push(i..end);
+            i = end;
         } else {
-            // Nothing to replace.
-            s
+            i = line_end + 1;
         }
     }
+    blocks
+}
 
-    fn skip_smaller_than(&mut self, pos: usize) -> usize {
-        unsafe {
-            let remainder = self.positions.get_unchecked(self.index..);
-            let (num_skip, p) = remainder
-                .iter()
-                .cloned()
-                .enumerate()
-                .find(|(_, x)| *x >= pos)
-                .unwrap();
-            self.index += num_skip;
-            p
+/// Returns the position after a code span that starts at `start`, or the position
+/// after the opening backtick string if no closing backtick string of the same length
+/// exists in the same paragraph.
+fn skip_code_span(bytes: &[u8], start: usize) -> usize {
+    let len = bytes[start..].iter().take_while(|&&b| b == b'`').count();
+    let mut i = start + len;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'`' => {
+                let closing_len = bytes[i..].iter().take_while(|&&b| b == b'`').count();
+                if closing_len == len {
+                    return i + len;
+                }
+                i += closing_len;
+            }
+            b'\n' => {
+                let line_end = find_line_end(bytes, i + 1);
+                if is_blank_line(&bytes[i + 1..line_end]) {
+                    break;
+                }
+                i += 1;
+            }
+            _ => i += 1,
         }
     }
+
+    start + len
+}
+
+/// Returns the position after a link destination that starts at `start` (i.e., just
+/// after the opening parenthesis).
+fn skip_link_destination(bytes: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i < bytes.len() && (bytes[i] == b' ' || bytes[i] == b'\t') {
+        i += 1;
+    }
+
+    if bytes.get(i) == Some(&b'<') {
+        while i < bytes.len() && bytes[i] != b'\n' {
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'>' => return i + 1,
+                _ => i += 1,
+            }
+        }
+        return start;
+    }
+
+    let mut depth = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if bytes.get(i + 1).is_some_and(u8::is_ascii_punctuation) => i += 2,
+            b'(' => {
+                depth += 1;
+                i += 1;
+            }
+            b')' if depth == 0 => break,
+            b')' => {
+                depth -= 1;
+                i += 1;
+            }
+            b if b.is_ascii_whitespace() || b.is_ascii_control() => break,
+            _ => i += 1,
+        }
+    }
+    i
+}
+
+/// Returns the position after an autolink such as "<https://example.com>" that starts
+/// at `start`, or `start + 1` if there is no autolink at `start`.
+fn skip_autolink(bytes: &[u8], start: usize) -> usize {
+    let scheme_len = bytes[start + 1..]
+        .iter()
+        .take_while(|&&b| b.is_ascii_alphanumeric() || b == b'+' || b == b'.' || b == b'-')
+        .count();
+    let colon = start + 1 + scheme_len;
+    if !(2..=32).contains(&scheme_len)
+        || !bytes[start + 1].is_ascii_alphabetic()
+        || bytes.get(colon) != Some(&b':')
+    {
+        return start + 1;
+    }
+
+    for (i, &b) in bytes.iter().enumerate().skip(colon + 1) {
+        match b {
+            b'>' => return i + 1,
+            b'<' => break,
+            b if b.is_ascii_whitespace() || b.is_ascii_control() => break,
+            _ => {}
+        }
+    }
+    start + 1
 }
 
 #[cfg(test)]
@@ -128,15 +649,19 @@ mod test {
     #[test]
     fn replacer() {
         let mut markdown = "Text $$math$$ text ``code in double carets`` text.\n\
-                            A costs $1 and B costs $3 and ``it's all $$ expensive''."
+                            A costs $1 and B costs $3 and ``it's all $$ expensive''.\n\
+                            \n\
+                            <span title=\"$$x$$\">HTML</span>"
             .to_string();
-        let mut replacer = Replacer::replace(&mut markdown);
+        let mut replacer = Replacer::replace(&mut markdown, MathDelimiters::DROPBOX_PAPER);
 
-        // `Replacer::replace` replaces all occurrences of $$, even ones that don't pair up.
+        // `Replacer::replace` replaces only occurrences of $$ that pair up.
         assert_eq!(
             markdown,
             "Text ``math`` text ``code in double carets`` text.\n\
-             A costs $1 and B costs $3 and ``it's all `` expensive''."
+             A costs $1 and B costs $3 and ``it's all $$ expensive''.\n\
+             \n\
+             <span title=\"``x``\">HTML</span>"
         );
 
         // Use `check_if_replacement_point` to figure out if a code block is genuine
@@ -153,10 +678,243 @@ mod test {
 
         // `un_replace` recovers the original text of substrings provided that
         // `check_if_replacement_point` has never been called beyond the starting
-        // point of the substring.
+        // point of the substring. This is useful if pulldown-cmark does not interpret
+        // a math span as a code span, e.g., because it is part of an HTML tag.
         assert_eq!(
             replacer.un_replace(CowStr::Borrowed(&markdown[51..]), 51..markdown.len()),
-            CowStr::Borrowed("A costs $1 and B costs $3 and ``it's all $$ expensive''.")
+            CowStr::Borrowed(
+                "A costs $1 and B costs $3 and ``it's all $$ expensive''.\n\
+                 \n\
+                 <span title=\"$$x$$\">HTML</span>"
+            )
+        );
+    }
+
+    fn replaced(markdown: &str) -> String {
+        replaced_with(markdown, MathDelimiters::DROPBOX_PAPER)
+    }
+
+    fn replaced_with(markdown: &str, delimiters: MathDelimiters) -> String {
+        let mut markdown = markdown.to_string();
+        Replacer::replace(&mut markdown, delimiters);
+        markdown
+    }
+
+    #[test]
+    fn pairs_up_delimiters() {
+        assert_eq!(replaced("$$a$$ and $$b$$"), "``a`` and ``b``");
+        assert_eq!(replaced("$$a$$$$b$$"), "``a````b``");
+        assert_eq!(replaced("$$a$$ and $$b"), "``a`` and $$b");
+        assert_eq!(replaced("$$a\nb$$"), "``a\nb``");
+        assert_eq!(replaced("> $$a\n> b$$"), "> ``a\n> b``");
+
+        // Math spans cannot cross paragraph boundaries.
+        assert_eq!(replaced("$$a\n\nb $$c$$"), "$$a\n\nb ``c``");
+        assert_eq!(replaced("> $$a\n>\n> b$$"), "> $$a\n>\n> b$$");
+
+        // "$$" directly next to a backtick is never a delimiter.
+        assert_eq!(replaced("`$$a$$`"), "`$$a$$`");
+        assert_eq!(replaced("$$`a`$$"), "$$`a`$$");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(replaced(r"\$$a$$"), r"\$$a$$");
+        assert_eq!(replaced(r"\$$$a$$"), r"\$``a``");
+        assert_eq!(replaced(r"\\$$a$$"), r"\\``a``");
+
+        // Inside of math spans, backslashes escape any character.
+        assert_eq!(replaced(r"$$\$$$"), r"``\$``");
+        assert_eq!(replaced(r"$$\\$$"), r"``\\``");
+        assert_eq!(replaced(r"$$a\$$ b$$"), r"``a\$$ b``");
+    }
+
+    #[test]
+    fn code_spans() {
+        assert_eq!(replaced("`$$a$$` $$b$$"), "`$$a$$` ``b``");
+        assert_eq!(replaced("`` $$a$$ `` $$b$$"), "`` $$a$$ `` ``b``");
+        assert_eq!(
+            replaced("`code\nspan $$a$$` $$b$$"),
+            "`code\nspan $$a$$` ``b``"
+        );
+
+        // Backtick strings of different lengths don't match.
+        assert_eq!(replaced("``a` $$b$$ ``"), "``a` $$b$$ ``");
+
+        // Unmatched backtick strings are literal text.
+        assert_eq!(replaced("`` $$a$$"), "`` ``a``");
+        assert_eq!(replaced("` $$a$$\n\n`"), "` ``a``\n\n`");
+
+        // Escaped backticks don't start code spans.
+        assert_eq!(replaced(r"\` $$a$$ `"), r"\` ``a`` `");
+    }
+
+    #[test]
+    fn fenced_code_blocks() {
+        assert_eq!(
+            replaced("$$a$$\n```\n$$b$$\n```\n$$c$$"),
+            "``a``\n```\n$$b$$\n```\n``c``"
+        );
+        assert_eq!(
+            replaced("~~~~ rust\n$$a$$\n~~~\n$$b$$\n~~~~~\n$$c$$"),
+            "~~~~ rust\n$$a$$\n~~~\n$$b$$\n~~~~~\n``c``"
+        );
+        assert_eq!(
+            replaced("> ```\n> $$a$$\n> ```\n> $$b$$"),
+            "> ```\n> $$a$$\n> ```\n> ``b``"
+        );
+
+        // Unclosed code blocks extend to the end of the document.
+        assert_eq!(replaced("```\n$$a$$"), "```\n$$a$$");
+
+        // Fenced code blocks interrupt paragraphs and therefore math spans.
+        assert_eq!(replaced("$$a\n```\nb$$\n```\n$$"), "$$a\n```\nb$$\n```\n$$");
+
+        // Not a code fence because the info string contains a backtick.
+        assert_eq!(replaced("``` ` $$a$$"), "``` ` ``a``");
+    }
+
+    #[test]
+    fn links() {
+        assert_eq!(
+            replaced("[$$a$$](https://example.com/$$b$$) $$c$$"),
+            "[``a``](https://example.com/$$b$$) ``c``"
+        );
+        assert_eq!(
+            replaced("[a](<https://example.com/$$b$$ x>) $$c$$"),
+            "[a](<https://example.com/$$b$$ x>) ``c``"
+        );
+        assert_eq!(
+            replaced("[a](https://example.com/(x$$)y$$) $$c$$"),
+            "[a](https://example.com/(x$$)y$$) ``c``"
+        );
+        assert_eq!(
+            replaced("<https://example.com/$$b$$> $$c$$"),
+            "<https://example.com/$$b$$> ``c``"
+        );
+
+        // Not link destinations or autolinks.
+        assert_eq!(replaced("[a] ($$b$$)"), "[a] (``b``)");
+        assert_eq!(replaced("[a](b $$c$$)"), "[a](b ``c``)");
+        assert_eq!(replaced("<a $$b$$>"), "<a ``b``>");
+        assert_eq!(replaced("$$a<b$$"), "``a<b``");
+    }
+
+    #[test]
+    fn single_dollar() {
+        let replaced = |markdown| replaced_with(markdown, MathDelimiters::LATEX);
+
+        assert_eq!(replaced("$a$ and $b_1$."), "`a` and `b_1`.");
+        assert_eq!(replaced("$$a$$ and $b$"), "``a`` and `b`");
+        assert_eq!(replaced(r"$a\$b$"), r"`a\$b`");
+        assert_eq!(replaced(r"\$a$ $b$"), r"\$a$ `b`");
+
+        // Currency amounts.
+        assert_eq!(
+            replaced("A costs $5 and B costs $10."),
+            "A costs $5 and B costs $10."
+        );
+        assert_eq!(replaced("From $5-$10 each."), "From $5-$10 each.");
+        assert_eq!(replaced("$5 or $10, i.e., $x$."), "$5 or $10, i.e., `x`.");
+        assert_eq!(replaced("Costs $5, so $x$"), "Costs $5, so `x`");
+        assert_eq!(replaced("$ a$ and $a $ and $$$"), "$ a$ and $a $ and $$$");
+        assert_eq!(replaced("$5$"), "`5`");
+
+        // Not recognized in Dropbox Paper.
+        assert_eq!(
+            replaced_with("$a$ and $$b$$", MathDelimiters::DROPBOX_PAPER),
+            "$a$ and ``b``"
+        );
+    }
+
+    #[test]
+    fn backslash_delimiters() {
+        let replaced = |markdown| replaced_with(markdown, MathDelimiters::LATEX);
+
+        assert_eq!(replaced(r"\(a\) and \[b\]"), "``a`` and ``b``");
+        assert_eq!(
+            replaced(r"\(a$b\) and \(\frac{a}{b}\)"),
+            "``a$b`` and ``\\frac{a}{b}``"
+        );
+        assert_eq!(replaced(r"\(a\] and \)"), r"``a\] and ``");
+        assert_eq!(replaced(r"\\(a\)"), r"\\(a\)");
+        assert_eq!(replaced("`\\(a\\)`"), "`\\(a\\)`");
+
+        // Not recognized in Dropbox Paper.
+        assert_eq!(
+            replaced_with(r"\(a\) and \[b\]", MathDelimiters::DROPBOX_PAPER),
+            r"\(a\) and \[b\]"
+        );
+    }
+
+    #[test]
+    fn backticks_in_math() {
+        let replaced = |markdown| replaced_with(markdown, MathDelimiters::LATEX);
+
+        assert_eq!(replaced("$$a`b$$ $a``b$"), "``a`b`` `a``b`");
+        assert_eq!(replaced("$$a``b$$ $a`b$"), "$$a``b$$ $a`b$");
+    }
+
+    #[test]
+    fn math_spans() {
+        let mut markdown = r"$a$ and $$b$$ and \[c\]".to_string();
+        let mut replacer = Replacer::replace(&mut markdown, MathDelimiters::LATEX);
+
+        let span = replacer.math_span(0..3).unwrap();
+        assert_eq!(span.content, 1..2);
+        assert!(!span.display);
+        assert_eq!(span.delimiters, ("$", "$"));
+
+        assert!(replacer.math_span(8..12).is_none());
+        let span = replacer.math_span(8..13).unwrap();
+        assert_eq!(span.content, 10..11);
+        assert!(span.display);
+        assert_eq!(span.delimiters, ("$$", "$$"));
+
+        assert!(replacer.math_span(18..22).is_none());
+        let span = replacer.math_span(18..23).unwrap();
+        assert_eq!(span.content, 20..21);
+        assert!(span.display);
+        assert_eq!(span.delimiters, (r"\[", r"\]"));
+    }
+
+    #[test]
+    fn heading_attributes() {
+        let mut markdown = "# Title {#title}\n\
+                            Text {#not-a-heading}\n\
+                            ```\n\
+                            ## Code {#code}\n\
+                            ```\n\
+                            > ## Section {.unnumbered #sec:quote}  \n\
+                            ### Subsection {not attributes}\n\
+                            ####### Not a heading {#h7}"
+            .to_string();
+        let labels = strip_heading_attributes(&mut markdown);
+
+        assert_eq!(
+            markdown,
+            "# Title         \n\
+             Text {#not-a-heading}\n\
+             ```\n\
+             ## Code {#code}\n\
+             ```\n\
+             > ## Section                           \n\
+             ### Subsection {not attributes}\n\
+             ####### Not a heading {#h7}"
+        );
+        assert_eq!(
+            labels,
+            [(8, "title".to_string()), (76, "sec:quote".to_string())]
+        );
+    }
+
+    #[test]
+    fn fenced_code_block_ranges() {
+        let markdown = "Text\n```\ncode\n```\n> ~~~\nnot closed by the end of the quote\n";
+        assert_eq!(
+            super::fenced_code_blocks(markdown.as_bytes()),
+            [5..18, 18..markdown.len()]
         );
+        assert!(super::fenced_code_blocks(b"``` not a fence ```\n").is_empty());
     }
 }
@@ -0,0 +1,297 @@
+//! Conversion of straight quotes (`"..."` and `'...'`) into typographic quotes.
+
+use crate::latex_escape::LatexEscaper;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::io::Write;
+use std::iter::Peekable;
+
+/// A straight opening or closing quote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quote {
+    /// Byte offset of the `"` or `'` in the markdown source.
+    pub pos: usize,
+
+    /// Whether the quote is a double quote (`"`) rather than a single quote (`'`).
+    pub double: bool,
+
+    pub opening: bool,
+
+    /// Whether the quote is part of a pair whose quotes are both within the same span
+    /// of emphasis (or the same link etc.), so that the pair can be typeset as
+    /// `\enquote{...}`.
+    pub grouped: bool,
+}
+
+/// Finds opening and closing straight quotes, sorted by position.
+///
+/// A quote opens if it follows whitespace, an opening bracket, or another opening
+/// quote and precedes a non-whitespace character, and it closes if it follows a
+/// non-whitespace character. Single quotes between two letters (e.g., "don't"),
+/// before a digit (e.g., "the '90s"), or in closing position without a matching
+/// opening quote (e.g., "the students' books") are apostrophes and not included.
+/// Quotes in code and escaped quotes (`\"`) are ignored too.
+pub fn find_quotes(markdown: &str, parser_options: Options) -> Vec<Quote> {
+    let mut quotes: Vec<Quote> = Vec::new();
+    if !markdown.contains(['"', '\'']) {
+        return quotes;
+    }
+    // Indices into `quotes` and the groups of opening quotes that aren't closed yet.
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut groups = vec![0];
+    let mut next_group = 1;
+    let mut previous = None;
+    let mut in_code_block = false;
+
+    for (event, range) in Parser::new_ext(markdown, parser_options).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Start(tag) if is_inline(&tag) => {
+                groups.push(next_group);
+                next_group += 1;
+            }
+            Event::End(tag) if is_inline(&tag) => {
+                groups.pop();
+            }
+            Event::Start(_) | Event::End(_) | Event::Rule => {
+                // Block boundary.
+                open.clear();
+                previous = None;
+            }
+            Event::Text(text) if !in_code_block => {
+                if text.len() != range.len() {
+                    previous = text.chars().last();
+                    continue;
+                }
+                let group = *groups.last().expect("balanced inline tags");
+                let mut chars = text.char_indices().peekable();
+                while let Some((index, c)) = chars.next() {
+                    let escaped = index == 0 && markdown[..range.start].ends_with('\\');
+                    if (c != '"' && c != '\'') || escaped {
+                        previous = Some(c);
+                        continue;
+                    }
+                    let double = c == '"';
+                    let next = chars.peek().map(|&(_, next)| next);
+                    let pos = range.start + index;
+
+                    if previous.is_none_or(opens_quote) {
+                        let apostrophe = !double && next.is_some_and(|c| c.is_ascii_digit());
+                        if !next.is_some_and(char::is_whitespace) && !apostrophe {
+                            open.push((quotes.len(), group));
+                            quotes.push(Quote {
+                                pos,
+                                double,
+                                opening: true,
+                                grouped: false,
+                            });
+                            // Allows for nested quotes such as `"'Hi,' she said."`.
+                            previous = None;
+                            continue;
+                        }
+                    } else if double || !next.is_some_and(char::is_alphanumeric) {
+                        let opening = open
+                            .iter()
+                            .rposition(|&(index, _)| quotes[index].double == double);
+                        if opening.is_some() || double {
+                            let grouped = opening.is_some_and(|opening| {
+                                let (index, opening_group) = open[opening];
+                                open.truncate(opening);
+                                quotes[index].grouped = opening_group == group;
+                                quotes[index].grouped
+                            });
+                            quotes.push(Quote {
+                                pos,
+                                double,
+                                opening: false,
+                                grouped,
+                            });
+                        }
+                    }
+                    previous = Some(c);
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => previous = Some(' '),
+            Event::Html(_) => previous = None,
+            _ => previous = Some('x'), // E.g., inline code or math.
+        }
+    }
+
+    quotes
+}
+
+fn is_inline(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+    )
+}
+
+/// Checks if a quote that follows the character `previous` may be an opening quote.
+fn opens_quote(previous: char) -> bool {
+    previous.is_whitespace() || "([{-\u{2013}\u{2014}/".contains(previous)
+}
+
+/// Writes text with straight quotes replaced by typographic quotes.
+///
+/// Uses `\enquote{...}` (or `\enquote*{...}` for single quotes) from the `csquotes`
+/// package if enabled, which produces the quotation marks of the document language.
+/// Otherwise, and for quotes that aren't grouped (see [`Quote::grouped`]), uses
+/// ``` ``...'' ``` and `` `...' ``.
+///
+/// [`Quote::grouped`]: struct.Quote.html#structfield.grouped
+#[derive(Debug)]
+pub struct QuoteWriter {
+    quotes: Peekable<std::vec::IntoIter<Quote>>,
+    csquotes: bool,
+
+    /// Number of `\enquote{` that are not yet closed.
+    open: usize,
+}
+
+impl QuoteWriter {
+    pub fn new(quotes: Vec<Quote>, csquotes: bool) -> Self {
+        Self {
+            quotes: quotes.into_iter().peekable(),
+            csquotes,
+            open: 0,
+        }
+    }
+
+    /// Escapes and writes `text`, which is a verbatim copy of the markdown source
+    /// starting at byte offset `pos`.
+    pub fn write_text(
+        &mut self,
+        text: &str,
+        pos: usize,
+        escaper: &mut LatexEscaper<impl Write>,
+    ) -> std::io::Result<()> {
+        let end = pos + text.len();
+        let mut written = 0;
+        while let Some(quote) = self.quotes.next_if(|quote| quote.pos < end) {
+            if quote.pos < pos {
+                // Not written (e.g., part of a citation).
+                continue;
+            }
+            let offset = quote.pos - pos;
+            debug_assert!(matches!(text.as_bytes()[offset], b'"' | b'\''));
+            escaper.write_all(&text.as_bytes()[written..offset])?;
+            escaper.finish()?;
+            let enquote = self.csquotes && quote.grouped;
+            let latex: &[u8] = match (quote.opening, quote.double) {
+                (true, double) if enquote => {
+                    self.open += 1;
+                    if double {
+                        br"\enquote{"
+                    } else {
+                        br"\enquote*{"
+                    }
+                }
+                (false, _) if enquote && self.open != 0 => {
+                    self.open -= 1;
+                    b"}"
+                }
+                (true, true) => b"``",
+                (true, false) => b"`",
+                (false, true) => b"''",
+                (false, false) => b"'",
+            };
+            escaper.get_mut().write_all(latex)?;
+            written = offset + 1;
+        }
+        escaper.write_all(&text.as_bytes()[written..])
+    }
+
+    /// Closes all open quotes, e.g., at the end of a paragraph.
+    ///
+    /// This is only necessary if the closing quote of a pair wasn't written.
+    pub fn close_all(&mut self, writer: &mut impl Write) -> std::io::Result<()> {
+        for _ in 0..std::mem::take(&mut self.open) {
+            writer.write_all(b"}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn convert(markdown: &str, csquotes: bool) -> String {
+        let quotes = find_quotes(markdown, Options::empty());
+        let mut writer = QuoteWriter::new(quotes, csquotes);
+        let mut escaper = LatexEscaper::with_unicode(Vec::new(), None);
+        for (event, range) in Parser::new(markdown).into_offset_iter() {
+            if let Event::Text(text) = event {
+                writer.write_text(&text, range.start, &mut escaper).unwrap();
+                continue;
+            }
+            escaper.finish().unwrap();
+            let latex = escaper.get_mut();
+            match event {
+                Event::Start(Tag::Emphasis) => latex.extend_from_slice(br"\emph{"),
+                Event::End(Tag::Emphasis) => latex.extend_from_slice(b"}"),
+                Event::SoftBreak => latex.push(b'\n'),
+                Event::End(Tag::Paragraph) => {
+                    writer.close_all(latex).unwrap();
+                    latex.push(b'\n');
+                }
+                _ => {}
+            }
+        }
+        let latex = escaper.into_inner().unwrap();
+        String::from_utf8(latex).unwrap().trim_end().to_string()
+    }
+
+    #[test]
+    fn csquotes() {
+        let convert = |markdown| convert(markdown, true);
+        assert_eq!(
+            convert(r#"He said "hello" and ("bye")."#),
+            r"He said \enquote{hello} and (\enquote{bye})."
+        );
+        assert_eq!(
+            convert(r#""A *"nested"* quote" with 5" unpaired"#),
+            r"\enquote{A \emph{\enquote{nested}} quote} with 5'' unpaired"
+        );
+        assert_eq!(
+            convert(r#""'Hi,' she said, 'it's fine.'""#),
+            r"\enquote{\enquote*{Hi,} she said, \enquote*{it's fine.}}"
+        );
+        assert_eq!(
+            convert("\"Crossing *emphasis\" boundary*"),
+            "``Crossing \\emph{emphasis'' boundary}"
+        );
+        assert_eq!(
+            convert("*Different* \"spans *of\" emphasis*"),
+            "\\emph{Different} ``spans \\emph{of'' emphasis}"
+        );
+        assert_eq!(
+            convert("\"Across\nlines\" but \"not\n\nparagraphs\""),
+            "\\enquote{Across\nlines} but ``not\nparagraphs''"
+        );
+        assert_eq!(convert(r#"Escaped \"quotes\""#), r#"Escaped "quotes""#);
+    }
+
+    #[test]
+    fn ligatures() {
+        let convert = |markdown| convert(markdown, false);
+        assert_eq!(
+            convert(r#"He said "hello" and ("bye")."#),
+            "He said ``hello'' and (``bye'')."
+        );
+        assert_eq!(
+            convert("Don't say 'yes', say \"*it's* the '90s\"."),
+            "Don't say `yes', say ``\\emph{it's} the '90s''."
+        );
+        assert_eq!(
+            convert("\"Crossing *emphasis\" boundary*"),
+            "``Crossing \\emph{emphasis'' boundary}"
+        );
+        assert_eq!(
+            convert("The students' books, a ' lonely \" quote"),
+            "The students' books, a ' lonely \" quote"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Task list items (`- [ ] ...` and `- [x] ...`) and the summary of open TODOs.
+
+use crate::format::WhitespaceFormatter;
+use crate::{write_inline_markdown, ConversionOptions};
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::io::Write;
+
+/// An item of a task list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskItem {
+    /// Byte offset of the list item in the markdown source.
+    pub pos: usize,
+
+    pub checked: bool,
+
+    /// The markdown source of the item's text (without nested blocks) on a single line.
+    pub text: String,
+}
+
+/// Finds all task list items, sorted by position.
+///
+/// Has to run before math delimiters are replaced so that `TaskItem::text` contains
+/// the original markdown.
+pub fn find_task_items(markdown: &str, parser_options: Options) -> Vec<TaskItem> {
+    let mut items = Vec::new();
+    if !["[ ]", "[x]", "[X]"]
+        .iter()
+        .any(|marker| markdown.contains(marker))
+    {
+        return items;
+    }
+    let mut parser = Parser::new_ext(markdown, parser_options).into_offset_iter();
+    let mut item_start = None;
+    while let Some((event, range)) = parser.next() {
+        match event {
+            Event::Start(Tag::Item) => item_start = Some(range.start),
+            Event::TaskListMarker(checked) => {
+                let pos = match item_start.take() {
+                    Some(pos) => pos,
+                    None => continue,
+                };
+                let mut text_end = range.end;
+                for (event, range) in parser.by_ref() {
+                    match event {
+                        Event::End(Tag::Paragraph) | Event::End(Tag::Item) => break,
+                        _ if is_block(&event) => break,
+                        _ => text_end = std::cmp::max(text_end, range.end),
+                    }
+                }
+                let text = markdown[range.end..text_end]
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                items.push(TaskItem { pos, checked, text });
+            }
+            Event::Start(Tag::Paragraph) => {}
+            _ => item_start = None,
+        }
+    }
+    items
+}
+
+/// Checks if `event` starts a block that can follow the text of a list item.
+fn is_block(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Rule
+            | Event::Start(Tag::List(_))
+            | Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::BlockQuote)
+            | Event::Start(Tag::Heading(_))
+            | Event::Start(Tag::Table(_))
+    )
+}
+
+/// Returns the label of the `index`-th (0-based) open task.
+pub fn label(index: usize) -> String {
+    format!("todo:{}", index + 1)
+}
+
+/// Writes a section that lists the given open tasks with references to their pages.
+///
+/// The `i`-th task has to be labeled with `label(i)`.
+pub fn write_summary<W: Write>(
+    items: &[&TaskItem],
+    options: &ConversionOptions,
+    writer: &mut WhitespaceFormatter<W>,
+    image_callback: &mut dyn FnMut(&str) -> Option<String>,
+) -> std::io::Result<()> {
+    let options = ConversionOptions {
+        todo_summary: false,
+        ..options.clone()
+    };
+
+    writer.add_newlines(3);
+    writer.write_on_single_line(r"\section*{Open TODOs}")?;
+    writer.add_newlines(2);
+    writer.write_on_single_line(r"\begin{itemize}")?;
+    writer.increase_indent();
+    for (index, item) in items.iter().enumerate() {
+        writer.add_newlines(1);
+        writer.write_all(br"\item[\uncheckedbox] ")?;
+        write_inline_markdown(&item.text, &options, writer, image_callback)?;
+        write!(writer, r" (p.~\pageref{{{}}})", label(index))?;
+    }
+    writer.decrease_indent();
+    writer.write_on_single_line(r"\end{itemize}")?;
+    writer.add_newlines(2);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn task_items() {
+        fn find(markdown: &str) -> Vec<(usize, bool, String)> {
+            find_task_items(markdown, Options::ENABLE_TASKLISTS)
+                .into_iter()
+                .map(|item| (item.pos, item.checked, item.text))
+                .collect()
+        }
+        let s = |s: &str| s.to_string();
+
+        assert_eq!(
+            find("- [ ] Buy *milk*\n  and $x$ eggs\n- [x] Done\n- Not a task"),
+            vec![
+                (0, false, s("Buy *milk* and $x$ eggs")),
+                (32, true, s("Done")),
+            ]
+        );
+        assert_eq!(
+            find("1. [ ] Outer\n   - [x] Inner\n\n   More text\n"),
+            vec![(0, false, s("Outer")), (16, true, s("Inner"))]
+        );
+        assert_eq!(
+            find("- [ ] Loose\n\n- [ ] list\n"),
+            vec![(0, false, s("Loose")), (13, false, s("list"))]
+        );
+        assert!(find("Text [ ] with brackets").is_empty());
+    }
+}
@@ -0,0 +1,444 @@
+//! Theorem-like environments from paragraphs that start with a bold label, e.g.,
+//! "**Theorem 1 (Pythagoras).** ..." or "**Proof.** ...".
+
+use crate::latex_escape::escape_str_and_unicode;
+
+use log::warn;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::io::Write;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TheoremKind {
+    Theorem,
+    Lemma,
+    Proposition,
+    Corollary,
+    Definition,
+    Remark,
+    Example,
+    Proof,
+}
+
+/// All kinds, in the order in which their declarations appear in the preamble.
+pub const THEOREM_KINDS: [TheoremKind; 8] = [
+    TheoremKind::Theorem,
+    TheoremKind::Lemma,
+    TheoremKind::Proposition,
+    TheoremKind::Corollary,
+    TheoremKind::Definition,
+    TheoremKind::Remark,
+    TheoremKind::Example,
+    TheoremKind::Proof,
+];
+
+impl TheoremKind {
+    fn from_name(name: &str) -> Option<Self> {
+        THEOREM_KINDS
+            .iter()
+            .copied()
+            .find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TheoremKind::Theorem => "Theorem",
+            TheoremKind::Lemma => "Lemma",
+            TheoremKind::Proposition => "Proposition",
+            TheoremKind::Corollary => "Corollary",
+            TheoremKind::Definition => "Definition",
+            TheoremKind::Remark => "Remark",
+            TheoremKind::Example => "Example",
+            TheoremKind::Proof => "Proof",
+        }
+    }
+
+    pub fn environment(self) -> &'static str {
+        match self {
+            TheoremKind::Theorem => "theorem",
+            TheoremKind::Lemma => "lemma",
+            TheoremKind::Proposition => "proposition",
+            TheoremKind::Corollary => "corollary",
+            TheoremKind::Definition => "definition",
+            TheoremKind::Remark => "remark",
+            TheoremKind::Example => "example",
+            TheoremKind::Proof => "proof",
+        }
+    }
+
+    fn label_prefix(self) -> &'static str {
+        match self {
+            TheoremKind::Theorem => "thm",
+            TheoremKind::Lemma => "lem",
+            TheoremKind::Proposition => "prop",
+            TheoremKind::Corollary => "cor",
+            TheoremKind::Definition => "def",
+            TheoremKind::Remark => "rem",
+            TheoremKind::Example => "ex",
+            TheoremKind::Proof => "proof",
+        }
+    }
+
+    /// Returns the `\newtheorem` declaration for the preamble, or `None` for proofs
+    /// (the `proof` environment is predefined by `amsthm`).
+    pub fn declaration(self) -> Option<String> {
+        let style = match self {
+            TheoremKind::Theorem
+            | TheoremKind::Lemma
+            | TheoremKind::Proposition
+            | TheoremKind::Corollary => "plain",
+            TheoremKind::Definition | TheoremKind::Example => "definition",
+            TheoremKind::Remark => "remark",
+            TheoremKind::Proof => return None,
+        };
+        Some(format!(
+            r"\theoremstyle{{{}}}\newtheorem{{{}}}{{{}}}",
+            style,
+            self.environment(),
+            self.name()
+        ))
+    }
+}
+
+/// The bold label at the start of a paragraph that is typeset as a theorem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TheoremHead {
+    pub kind: TheoremKind,
+
+    /// The optional title in parentheses (for proofs: the entire label, e.g.,
+    /// "Proof of Lemma 2").
+    pub title: Option<String>,
+
+    /// A label derived from the manual number, e.g., "lem:2" for "Lemma 2".
+    pub label: Option<String>,
+
+    /// Byte offset in the markdown source where the body of the theorem starts.
+    pub end: usize,
+}
+
+/// A reference to a labeled theorem in running text, e.g., "Lemma 2".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TheoremReference {
+    /// The name as written in the text (e.g., "lemma" or "Lemma").
+    pub name: String,
+    pub label: String,
+}
+
+/// Finds all paragraphs that start with a theorem head.
+///
+/// Returns the heads together with the byte offset of their paragraph, sorted by
+/// position. Manual numbers that are used more than once only get a label the first
+/// time.
+pub fn find_theorems(markdown: &str, parser_options: Options) -> Vec<(usize, TheoremHead)> {
+    let mut heads: Vec<(usize, TheoremHead)> = Vec::new();
+    if !markdown.contains("**") && !markdown.contains("__") {
+        // No strong emphasis, so no theorem heads.
+        return heads;
+    }
+    let mut parser = Parser::new_ext(markdown, parser_options)
+        .into_offset_iter()
+        .peekable();
+
+    while let Some((event, range)) = parser.next() {
+        if let Event::Start(Tag::Paragraph) = event {
+            if !matches!(parser.peek(), Some((Event::Start(Tag::Strong), _))) {
+                continue;
+            }
+            let (_, strong_range) = parser.next().expect("peeked");
+            let mut label_text = String::new();
+            let mut valid = true;
+            for (event, _) in parser.by_ref() {
+                match event {
+                    Event::Text(text) => label_text.push_str(&text),
+                    Event::End(Tag::Strong) => break,
+                    _ => valid = false,
+                }
+            }
+
+            let following = match parser.peek() {
+                Some((Event::Text(text), text_range)) if text.len() == text_range.len() => {
+                    Some((text.as_ref(), text_range.start))
+                }
+                _ => None,
+            };
+            let head = if valid {
+                parse_head(&label_text, strong_range.end, following)
+            } else {
+                None
+            };
+
+            if let Some(mut head) = head {
+                if let Some(label) = &head.label {
+                    if heads
+                        .iter()
+                        .any(|(_, other)| other.label.as_ref() == Some(label))
+                    {
+                        warn!(
+                            "{} appears more than once. Only the first one can be referenced.",
+                            label_text.trim().trim_end_matches(['.', ':'])
+                        );
+                        head.label = None;
+                    }
+                }
+                heads.push((range.start, head));
+            }
+        }
+    }
+
+    heads
+}
+
+/// Parses the bold label of a paragraph and the text that immediately follows it
+/// (which may contain the title and the final punctuation, as in
+/// "**Theorem 1** (Pythagoras). ...").
+fn parse_head(
+    label: &str,
+    label_end: usize,
+    following: Option<(&str, usize)>,
+) -> Option<TheoremHead> {
+    let label = label.trim();
+    let (label, mut punctuated) = match label.strip_suffix(['.', ':']) {
+        Some(label) => (label.trim_end(), true),
+        None => (label, false),
+    };
+    let (name, rest) = label.split_once(char::is_whitespace).unwrap_or((label, ""));
+    let kind = TheoremKind::from_name(name)?;
+    let rest = rest.trim();
+
+    let (number, mut title) = if kind == TheoremKind::Proof {
+        (None, Some(label).filter(|_| !rest.is_empty()))
+    } else {
+        let (number, rest) = match rest.split_once([' ', '(']) {
+            _ if rest.starts_with('(') => (None, rest),
+            Some((number, _)) => (Some(number), rest[number.len()..].trim_start()),
+            None if rest.is_empty() => (None, rest),
+            None => (Some(rest), ""),
+        };
+        if number.is_some_and(|n| {
+            !n.contains(|c: char| c.is_ascii_digit())
+                || !n.chars().all(|c| c.is_alphanumeric() || c == '.')
+        }) {
+            return None;
+        }
+        let title = if rest.is_empty() {
+            None
+        } else {
+            Some(rest.strip_prefix('(')?.strip_suffix(')')?.trim())
+        };
+        (number, title)
+    };
+
+    let mut end = label_end;
+    if let Some((text, start)) = following {
+        let mut rest = text;
+        if !punctuated && title.is_none() {
+            let trimmed = rest.trim_start();
+            if let Some(close) = trimmed.strip_prefix('(').and_then(|t| t.find(')')) {
+                title = Some(trimmed[1..close + 1].trim());
+                rest = &trimmed[close + 2..];
+            }
+        }
+        if !punctuated {
+            if let Some(after) = rest.strip_prefix(['.', ':']) {
+                punctuated = true;
+                rest = after;
+            }
+        }
+        end = start + (text.len() - rest.trim_start().len());
+    }
+
+    if !punctuated && title.is_none() && number.is_none() {
+        return None;
+    }
+
+    Some(TheoremHead {
+        kind,
+        title: title.map(str::to_string),
+        label: number.map(|number| format!("{}:{}", kind.label_prefix(), number)),
+        end,
+    })
+}
+
+/// Finds references to labeled theorems (e.g., "Lemma 2") in the given runs of text
+/// (see `preprocess::text_runs`).
+pub fn find_references(
+    markdown: &str,
+    runs: &[Range<usize>],
+    heads: &[(usize, TheoremHead)],
+) -> Vec<(Range<usize>, TheoremReference)> {
+    let mut references = Vec::new();
+    if heads.iter().all(|(_, head)| head.label.is_none()) {
+        return references;
+    }
+
+    for run in runs {
+        let text = &markdown[run.clone()];
+        let mut previous = None;
+        for (pos, c) in text.char_indices() {
+            let at_word_start = previous.is_none_or(|p: char| !p.is_alphanumeric());
+            previous = Some(c);
+            if !at_word_start || !c.is_alphabetic() {
+                continue;
+            }
+
+            if let Some((len, reference)) = parse_reference(&text[pos..], heads) {
+                if references
+                    .last()
+                    .is_none_or(|(last, _): &(Range<usize>, _)| last.end <= run.start + pos)
+                {
+                    references.push((run.start + pos..run.start + pos + len, reference));
+                }
+            }
+        }
+    }
+
+    references
+}
+
+fn parse_reference(s: &str, heads: &[(usize, TheoremHead)]) -> Option<(usize, TheoremReference)> {
+    let name_len = s.find(|c: char| !c.is_alphabetic())?;
+    let name = &s[..name_len];
+    let kind = TheoremKind::from_name(name)?;
+    let after_name = &s[name_len..];
+    let separator_len = after_name
+        .chars()
+        .next()
+        .filter(|&c| c == ' ' || c == '\u{a0}')?
+        .len_utf8();
+
+    let number = &after_name[separator_len..];
+    let number_len = number
+        .find(|c: char| !c.is_alphanumeric() && c != '.')
+        .unwrap_or(number.len());
+    let number = number[..number_len].trim_end_matches('.');
+    let label = format!("{}:{}", kind.label_prefix(), number);
+
+    if heads
+        .iter()
+        .any(|(_, head)| head.label.as_ref() == Some(&label))
+    {
+        Some((
+            name_len + separator_len + number.len(),
+            TheoremReference {
+                name: name.to_string(),
+                label,
+            },
+        ))
+    } else {
+        None
+    }
+}
+
+/// Writes `\begin{environment}[{title}]\label{label}`.
+///
+/// The braces around the title allow it to contain brackets.
+pub fn write_begin(head: &TheoremHead, writer: &mut impl Write) -> std::io::Result<()> {
+    write!(writer, r"\begin{{{}}}", head.kind.environment())?;
+    if let Some(title) = &head.title {
+        writer.write_all(b"[{")?;
+        escape_str_and_unicode(title, writer)?;
+        writer.write_all(b"}]")?;
+    }
+    if let Some(label) = &head.label {
+        write!(writer, r"\label{{{}}}", label)?;
+    }
+    Ok(())
+}
+
+/// Writes a reference as, e.g., `Lemma~\ref{lem:2}`.
+pub fn write_reference(
+    reference: &TheoremReference,
+    writer: &mut impl Write,
+) -> std::io::Result<()> {
+    escape_str_and_unicode(&reference.name, writer)?;
+    write!(writer, r"~\ref{{{}}}", reference.label)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::preprocess::text_runs;
+
+    fn heads(markdown: &str) -> Vec<(Option<String>, Option<String>, String)> {
+        find_theorems(markdown, Options::empty())
+            .into_iter()
+            .map(|(_, head)| {
+                (
+                    head.title,
+                    head.label,
+                    markdown[head.end..]
+                        .lines()
+                        .next()
+                        .unwrap_or("")
+                        .to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn theorem_heads() {
+        let s = |s: &str| Some(s.to_string());
+
+        assert_eq!(
+            heads("**Theorem 1.** If $$a$$ then $$b$$."),
+            [(None, s("thm:1"), "If $$a$$ then $$b$$.".to_string())]
+        );
+        assert_eq!(
+            heads("**Lemma 2.1 (Key Lemma).** Text"),
+            [(s("Key Lemma"), s("lem:2.1"), "Text".to_string())]
+        );
+        assert_eq!(
+            heads("**Definition** (Group): A group"),
+            [(s("Group"), None, "A group".to_string())]
+        );
+        assert_eq!(
+            heads("**Proof of Lemma 2.1.** Trivial."),
+            [(s("Proof of Lemma 2.1"), None, "Trivial.".to_string())]
+        );
+        assert_eq!(
+            heads("**Proof.**\n\n**Remark:**"),
+            [(None, None, "".to_string()), (None, None, "".to_string())]
+        );
+
+        assert!(heads("**Theorem** proving is hard.").is_empty());
+        assert!(heads("**Theorem one.** Text").is_empty());
+        assert!(heads("Text **Theorem 1.** Text").is_empty());
+        assert!(heads("**Theorem *1*.** Text").is_empty());
+    }
+
+    #[test]
+    fn bracketed_title() {
+        let markdown = "**Theorem 1 (see [3]).** Text";
+        let (_, head) = find_theorems(markdown, Options::empty())
+            .pop()
+            .expect("theorem head");
+        let mut latex = Vec::new();
+        write_begin(&head, &mut latex).unwrap();
+
+        assert_eq!(
+            String::from_utf8(latex).unwrap(),
+            r"\begin{theorem}[{see [3]}]\label{thm:1}"
+        );
+    }
+
+    #[test]
+    fn references() {
+        let markdown = "By Lemma 2, lemma 2.\nAlso Lemma 3, Lemmas 2, and\n\n\
+                        **Lemma 2.** Text with Theorem 1.";
+        let heads = find_theorems(markdown, Options::empty());
+        let references = find_references(markdown, &text_runs(markdown, Options::empty()), &heads)
+            .into_iter()
+            .map(|(range, reference)| (&markdown[range], reference.label))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            references,
+            [
+                ("Lemma 2", "lem:2".to_string()),
+                ("lemma 2", "lem:2".to_string()),
+                ("Lemma 2", "lem:2".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Strategies and helpers that are shared by the property tests.
+
+#![allow(dead_code)] // Not every test uses every helper.
+
+use bluepaper_core::{ConversionOptions, Engine, InputDialect, MarkdownExtensions};
+
+use proptest::prelude::*;
+
+/// Snippets of markdown and TeX syntax from which test documents are assembled.
+const FRAGMENTS: &[&str] = &[
+    // Attempts to smuggle TeX code into the output.
+    "\\input{/etc/passwd}",
+    "\\immediate\\write18{rm -rf /}",
+    "\\openout1=x.tex",
+    "\\catcode`\\@=11",
+    "\\def\\x{y}",
+    "\\csname input\\endcsname",
+    "^^5cinput",
+    "\\end{verbatim}",
+    "\\begin{verbatim}",
+    "\\verb|x|",
+    " {#\\input}",
+    "[@{\\input x}]",
+    "](https://example.com/^^5c{}%#)",
+    "---\ntitle: \\input{x}\nbluepaper:\n  dialect: commonmark\n---\n",
+    // Math.
+    "$",
+    "$$",
+    "$x$",
+    "$$\\frac{a}{b}$$",
+    "\\(",
+    "\\)",
+    "\\[",
+    "\\]",
+    "%",
+    "{",
+    "}",
+    "\\",
+    // Block structure.
+    "\n",
+    "\n\n",
+    "# ",
+    "## ",
+    " {#sec:a}",
+    "> ",
+    "> **Note:** ",
+    "> [!WARNING]\n> ",
+    "- ",
+    "1. ",
+    "a) ",
+    "v. ",
+    "vi. ",
+    "C.  ",
+    "- [ ] ",
+    "- [x] ",
+    "Term\n: Definition\n",
+    "```\n",
+    "    indented code\n",
+    "| a | b |\n|---|---|\n",
+    "***\n",
+    "**Theorem 1.** ",
+    "**Proof.** ",
+    "# References\n",
+    "# Appendix\n",
+    // Inline syntax.
+    "`",
+    "*",
+    "**",
+    "~~",
+    "[link](",
+    "](",
+    ")",
+    "![image](",
+    "![](",
+    "<https://example.com>",
+    "<mail@example.com>",
+    "[ref]",
+    "[ref]: https://example.com\n",
+    "[^1]",
+    "[^1]: A footnote.\n",
+    "[@",
+    "@key",
+    "]",
+    "[",
+    "https://doi.org/10.1000/182",
+    "Lemma 2",
+    "<b>",
+    "</b>",
+    "<br>",
+    "<mark>",
+    "<b",
+    "<!--",
+    "-->",
+    "&amp;",
+    "&#0;",
+    "&#10;",
+    "&#13;",
+    "\"",
+    "'",
+    "...",
+    "  ",
+    "\t",
+    "\r\n",
+    "\u{0}",
+    "\u{a0}",
+    "\u{2026}",
+    "é",
+    "α ≤ β",
+    "🙂",
+];
+
+/// Returns a strategy for documents that are assembled from `FRAGMENTS` and
+/// random text.
+pub fn markdown() -> impl Strategy<Value = String> {
+    let fragment = prop_oneof![
+        3 => proptest::sample::select(FRAGMENTS).prop_map(str::to_string),
+        1 => "\\PC{0,8}",
+    ];
+    proptest::collection::vec(fragment, 0..24).prop_map(|fragments| fragments.concat())
+}
+
+/// Returns a strategy for options of all input dialects with most optional features
+/// turned on.
+pub fn options() -> impl Strategy<Value = ConversionOptions> {
+    let dialect = proptest::sample::select(
+        &[
+            InputDialect::DropboxPaper,
+            InputDialect::CommonMark,
+            InputDialect::Gfm,
+            InputDialect::Obsidian,
+        ][..],
+    );
+    let engine = proptest::sample::select(&[Engine::PdfLatex, Engine::LuaLatex][..]);
+    (dialect, engine, any::<bool>(), any::<bool>()).prop_map(
+        |(dialect, engine, all_extensions, smart_quotes)| {
+            let mut options = ConversionOptions::for_dialect(dialect);
+            if all_extensions {
+                options.extensions = MarkdownExtensions {
+                    tables: true,
+                    footnotes: true,
+                    strikethrough: true,
+                    tasklists: true,
+                    heading_attributes: true,
+                    fancy_lists: true,
+                    definition_lists: true,
+                };
+            }
+            options.engine = engine;
+            options.smart_quotes = smart_quotes;
+            options.cite_links = true;
+            options.citation_syntax = true;
+            options.description_lists = true;
+            options.todo_summary = true;
+            options
+        },
+    )
+}
+
+/// Removes the contents of `verbatim` environments and `\verb` commands as well as
+/// comments from generated LaTeX code, i.e., everything that TeX doesn't tokenize.
+pub fn without_verbatim(latex: &str) -> String {
+    let mut result = String::with_capacity(latex.len());
+    let mut rest = latex;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix(r"\begin{verbatim}") {
+            result.push_str(r"\begin{verbatim}");
+            rest = &after[after.find(r"\end{verbatim}").unwrap_or(after.len())..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix(r"\verb") {
+            if let Some(delimiter) = after.chars().next().filter(|c| !c.is_ascii_alphabetic()) {
+                let verb = &after[delimiter.len_utf8()..];
+                let end = verb
+                    .find(delimiter)
+                    .map_or(verb.len(), |end| end + delimiter.len_utf8());
+                rest = &verb[end..];
+                continue;
+            }
+        }
+        let len = match c {
+            '%' => rest.find('\n').unwrap_or(rest.len()),
+            '\\' => 1 + rest[1..].chars().next().map_or(0, char::len_utf8),
+            _ => c.len_utf8(),
+        };
+        if c != '%' {
+            result.push_str(&rest[..len]);
+        }
+        rest = &rest[len..];
+    }
+    result
+}
@@ -0,0 +1,98 @@
+//! Differential tests for `IncrementalConverter`: after any sequence of edits, its
+//! output must be identical to a full conversion of the edited document.
+
+mod common;
+
+use bluepaper_core::{ConversionOptions, IncrementalConverter, MarkdownToLatex};
+
+use proptest::prelude::*;
+
+/// Returns a strategy for documents with several top-level blocks.
+fn document() -> impl Strategy<Value = String> {
+    proptest::collection::vec(common::markdown(), 1..8).prop_map(|blocks| blocks.join("\n\n"))
+}
+
+/// An edit given by the relative position of its start and end in the document and
+/// the replacement text.
+fn edit() -> impl Strategy<Value = (f64, f64, String)> {
+    (0.0..=1.0, 0.0..=1.0, common::markdown())
+}
+
+/// Returns the byte offset of the character boundary closest to `fraction` of the
+/// length of `s`.
+fn char_boundary(s: &str, fraction: f64) -> usize {
+    let mut pos = (s.len() as f64 * fraction) as usize;
+    while !s.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    pos
+}
+
+fn full_conversion(markdown: &str, options: &ConversionOptions) -> String {
+    MarkdownToLatex::with_options(markdown.to_string(), options.clone()).into_string()
+}
+
+proptest! {
+    #[test]
+    fn edits_match_full_conversion(
+        markdown in document(),
+        mut options in common::options(),
+        global_features in any::<bool>(),
+        edits in proptest::collection::vec(edit(), 1..6),
+    ) {
+        // Citations and the summary of open tasks make every edit convert the whole
+        // document, so they are turned off half of the time.
+        options.cite_links = global_features;
+        options.citation_syntax = global_features;
+        options.todo_summary = global_features;
+
+        let full = full_conversion(&markdown, &options);
+        let mut converter = IncrementalConverter::new(markdown.clone(), options.clone());
+        prop_assert_eq!(converter.latex(), full);
+
+        for (start, end, replacement) in edits {
+            let markdown = converter.markdown();
+            let (start, end) = (char_boundary(markdown, start), char_boundary(markdown, end));
+            let range = std::cmp::min(start, end)..std::cmp::max(start, end);
+            let mut edited = markdown.to_string();
+            edited.replace_range(range.clone(), &replacement);
+            let full = full_conversion(&edited, &options);
+            converter.edit(range, &replacement);
+            prop_assert_eq!(converter.latex(), full, "{:?}", edited);
+        }
+    }
+}
+
+#[test]
+fn paragraph_edits() {
+    let mut markdown = String::from("# Title\n\n");
+    for i in 0..20 {
+        markdown.push_str(&format!(
+            "## Section {}\n\nText with $$x_{}$$ and <b>bold\n\n- a list\n- with items\n\n",
+            i, i
+        ));
+    }
+    let options = ConversionOptions::default();
+    let mut converter = IncrementalConverter::new(markdown, options.clone());
+
+    let pos = converter.markdown().find("Section 7").unwrap();
+    converter.edit(pos..pos, "\n\nInserted paragraph.\n\n## ");
+    assert_eq!(
+        converter.latex(),
+        full_conversion(converter.markdown(), &options)
+    );
+
+    let pos = converter
+        .markdown()
+        .find("- with items\n\n## Section 12")
+        .unwrap();
+    converter.edit(pos..pos + 14, "");
+    assert_eq!(
+        converter.latex(),
+        full_conversion(converter.markdown(), &options)
+    );
+
+    let len = converter.markdown().len();
+    converter.edit(0..len, "");
+    assert_eq!(converter.latex(), full_conversion("", &options));
+}
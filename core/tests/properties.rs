@@ -0,0 +1,101 @@
+//! Property tests: the conversion never panics, and it always produces valid UTF-8
+//! with balanced braces.
+
+mod common;
+
+use bluepaper_core::math::{Token, Tokenizer};
+use bluepaper_core::{ConversionOptions, InputDialect, MarkdownToLatex, MathDelimiters};
+
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn latex_has_balanced_braces(markdown in common::markdown(), mut options in common::options()) {
+        // Otherwise, broken math spans are copied verbatim.
+        options.broken_math_as_code = true;
+        let latex = MarkdownToLatex::with_options(markdown, options)
+            .write_to(Vec::new())
+            .unwrap();
+        let latex = String::from_utf8(latex).expect("valid UTF-8");
+
+        let mut depth = 0;
+        for (_, token) in Tokenizer::new(&common::without_verbatim(&latex)) {
+            match token {
+                Token::BeginGroup => depth += 1,
+                Token::EndGroup => {
+                    prop_assert!(depth > 0, "{}", latex);
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        prop_assert_eq!(depth, 0, "{}", latex);
+    }
+
+    #[test]
+    fn arbitrary_text_never_panics(markdown in any::<String>(), options in common::options()) {
+        MarkdownToLatex::with_options(markdown, options).into_string();
+    }
+}
+
+/// Converts `markdown` with LaTeX math delimiters and returns the document body.
+fn body(markdown: &str) -> String {
+    let options = ConversionOptions {
+        math_delimiters: MathDelimiters::LATEX,
+        ..Default::default()
+    };
+    let latex = MarkdownToLatex::with_options(markdown.to_string(), options).into_string();
+    let start = latex.find("\\begin{document}").unwrap() + "\\begin{document}".len();
+    let end = latex.find("\\end{document}").unwrap();
+    latex[start..end].trim().to_string()
+}
+
+// Regression tests for bugs that were found by the property tests above.
+
+#[test]
+fn autolinks_and_reference_links() {
+    assert_eq!(body("<https://example.com>"), "https://example.com");
+    assert_eq!(body("[ref]\n\n[ref]: https://example.com"), "ref");
+}
+
+#[test]
+fn image_with_alt_text() {
+    assert_eq!(
+        body("![An *image*](image.png)"),
+        "%\\includegraphics[width=\\textwidth]{image.png}"
+    );
+}
+
+#[test]
+fn code_block_is_verbatim() {
+    assert_eq!(
+        body("```\n\\input{x} 50% ~\n```"),
+        "\\begin{verbatim}\n\\input{x} 50% ~\n\\end{verbatim}"
+    );
+    assert_eq!(
+        body("```\n\\end{verbatim}\n\n|x|\n```"),
+        "\\begin{flushleft}\n\\verb|\\end{verbatim}| \\\\\n\\mbox{} \\\\\n\\verb!|x|!\n\\end{flushleft}"
+    );
+}
+
+#[test]
+fn math_ending_in_comment() {
+    assert_eq!(body("$x % comment$"), "$x % comment\n$");
+    assert_eq!(body("$$x % comment$$"), "\\[x % comment\n\\]");
+}
+
+#[test]
+fn inline_html_continued_in_block_quote() {
+    let dialects = [
+        InputDialect::DropboxPaper,
+        InputDialect::CommonMark,
+        InputDialect::Gfm,
+        InputDialect::Obsidian,
+    ];
+    for &dialect in &dialects {
+        for markdown in &["\"><b\n", "> \"><b\n", "> x <b\n>", "> x <b\n> c=d>"] {
+            let options = ConversionOptions::for_dialect(dialect);
+            MarkdownToLatex::with_options(markdown.to_string(), options).into_string();
+        }
+    }
+}
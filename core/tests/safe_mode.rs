@@ -0,0 +1,56 @@
+//! Property tests for `ConversionOptions::safe_mode`: no markdown input may smuggle
+//! TeX code that reads or writes files, runs shell commands, or redefines macros into
+//! the generated LaTeX code.
+
+mod common;
+
+use bluepaper_core::math::{Token, Tokenizer};
+use bluepaper_core::MarkdownToLatex;
+
+use proptest::prelude::*;
+
+/// Control words that must never appear in the LaTeX code generated from
+/// untrusted input. The converter itself never emits any of them.
+const FORBIDDEN: &[&str] = &[
+    "catcode",
+    "csname",
+    "def",
+    "directlua",
+    "edef",
+    "endinput",
+    "gdef",
+    "immediate",
+    "include",
+    "input",
+    "let",
+    "newcommand",
+    "openin",
+    "openout",
+    "read",
+    "renewcommand",
+    "special",
+    "usepackage",
+    "write",
+    "xdef",
+];
+
+proptest! {
+    #[test]
+    fn safe_mode_blocks_tex_injection(
+        markdown in common::markdown(),
+        mut options in common::options(),
+        broken_math_as_code in any::<bool>(),
+    ) {
+        options.safe_mode = true;
+        options.broken_math_as_code = broken_math_as_code;
+        let latex = MarkdownToLatex::with_options(markdown, options).into_string();
+        let body = common::without_verbatim(&latex[latex.find("\\begin{document}").unwrap()..]);
+
+        prop_assert!(!body.contains("^^"), "{}", body);
+        for (_, token) in Tokenizer::new(&body) {
+            if let Token::ControlWord(name) = token {
+                prop_assert!(!FORBIDDEN.contains(&name), "\\{} in {}", name, body);
+            }
+        }
+    }
+}
@@ -75,7 +75,8 @@ const Converter = (function () {
             latex = htmlToLatex(input, wasm_module, false);
         }
 
-        if (fileNames.length !== 0) {
+        // Citations require a separate BibTeX file, which is only included in the zip file.
+        if (fileNames.length !== 0 || latex.includes("\\bibliography{")) {
             document.getElementById("wait-zip").style.display = "inline";
             const originalGeneration = generation;
             Promise.all(Object.values(queue)).then(function () {
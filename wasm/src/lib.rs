@@ -1,4 +1,7 @@
-use bluepaper_core::{format::WhitespaceFormatter, latex_escape::escape_str, MarkdownToLatex};
+use bluepaper_core::{
+    bibliography::BIBTEX_FILE_NAME, format::WhitespaceFormatter, latex_escape::LatexEscaper,
+    MarkdownToLatex,
+};
 
 use js_sys;
 use lazy_static::lazy_static;
@@ -16,13 +19,15 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 mod globals {
     #![allow(non_upper_case_globals)]
-    use super::{lazy_static, HashMap, Mutex, WhitespaceFormatter};
+    use super::{lazy_static, HashMap, LatexEscaper, Mutex, WhitespaceFormatter};
 
     lazy_static! {
         pub static ref images: Mutex<HashMap<String, (String, Vec<u8>)>> =
             Mutex::new(HashMap::new());
-        pub static ref latex_formatter: Mutex<WhitespaceFormatter<Vec<u8>>> =
-            Mutex::new(WhitespaceFormatter::new_latex_formatter(Vec::new()).unwrap());
+        pub static ref latex_formatter: Mutex<LatexEscaper<WhitespaceFormatter<Vec<u8>>>> =
+            Mutex::new(LatexEscaper::new(
+                WhitespaceFormatter::new_latex_formatter(Vec::new()).unwrap()
+            ));
     }
 }
 
@@ -90,65 +95,94 @@ pub fn markdown_to_zipped_latex(markdown: String) -> Vec<u8> {
         .compression_method(zip::CompressionMethod::Stored)
         .unix_permissions(0o755);
 
-    let latex =
-        MarkdownToLatex::from_string(markdown).into_string_with_image_callback(&mut |url| {
-            if let Some((filename, data)) = images_guard.get(url) {
-                let path = format!("figures/{}", filename);
-                zip_writer.start_file(&path, zip_options).unwrap();
-                zip_writer.write_all(data).unwrap();
-                Some(path)
-            } else {
-                None
-            }
-        });
+    let converter = MarkdownToLatex::from_string(markdown);
+    let bibtex = converter.bibtex();
+    let latex = converter.into_string_with_image_callback(&mut |url| {
+        if let Some((filename, data)) = images_guard.get(url) {
+            let path = format!("figures/{}", filename);
+            zip_writer.start_file(&path, zip_options).unwrap();
+            zip_writer.write_all(data).unwrap();
+            Some(path)
+        } else {
+            None
+        }
+    });
 
     zip_writer.start_file("main.tex", zip_options).unwrap();
     zip_writer.write_all(latex.as_bytes()).unwrap();
 
+    if let Some(bibtex) = bibtex {
+        zip_writer
+            .start_file(BIBTEX_FILE_NAME, zip_options)
+            .unwrap();
+        zip_writer.write_all(bibtex.as_bytes()).unwrap();
+    }
+
     zip_writer.finish().unwrap().into_inner()
 }
 
 #[wasm_bindgen]
 pub fn clear_output() -> String {
-    let mut formatter = globals::latex_formatter.lock().unwrap();
-    let output = unsafe { std::str::from_utf8_unchecked(formatter.get_mut().unwrap()) };
-    let output = output.to_string();
+    let mut escaper = globals::latex_formatter.lock().unwrap();
+    escaper.finish().unwrap();
+    let formatter = escaper.get_mut();
+    let output = std::str::from_utf8(formatter.get_mut().unwrap())
+        .expect("LaTeX output is valid UTF-8")
+        .to_string();
     formatter.reset_latex_formatter();
     output
 }
 
 #[wasm_bindgen]
 pub fn write_raw(s: &str, newlines_before: u32, newlines_after: u32) {
-    let mut formatter = globals::latex_formatter.lock().unwrap();
+    let mut escaper = globals::latex_formatter.lock().unwrap();
+    escaper.finish().unwrap();
+    let formatter = escaper.get_mut();
     formatter.add_newlines(newlines_before);
     formatter.write_all(s.as_bytes()).unwrap();
     formatter.add_newlines(newlines_after);
 }
 
+/// Escapes and writes `s`. Consecutive calls without any newlines in between are
+/// escaped as a single run of text (e.g., an ellipsis may be split across calls).
 #[wasm_bindgen]
 pub fn write_escaped(s: &str, newlines_before: u32, newlines_after: u32) {
-    let mut formatter = globals::latex_formatter.lock().unwrap();
-    formatter.add_newlines(newlines_before);
-    escape_str(s, formatter.get_mut().unwrap()).unwrap();
-    formatter.add_newlines(newlines_after);
+    let mut escaper = globals::latex_formatter.lock().unwrap();
+    if newlines_before != 0 {
+        escaper.finish().unwrap();
+        escaper.get_mut().add_newlines(newlines_before);
+    }
+    escaper.write_all(s.as_bytes()).unwrap();
+    if newlines_after != 0 {
+        escaper.finish().unwrap();
+        escaper.get_mut().add_newlines(newlines_after);
+    }
 }
 
 #[wasm_bindgen]
 pub fn add_newlines(num: u32) {
-    globals::latex_formatter.lock().unwrap().add_newlines(num);
+    let mut escaper = globals::latex_formatter.lock().unwrap();
+    escaper.finish().unwrap();
+    escaper.get_mut().add_newlines(num);
 }
 
 #[wasm_bindgen]
 pub fn increase_indent() {
-    globals::latex_formatter.lock().unwrap().increase_indent();
+    let mut escaper = globals::latex_formatter.lock().unwrap();
+    escaper.finish().unwrap();
+    escaper.get_mut().increase_indent();
 }
 
 #[wasm_bindgen]
 pub fn decrease_indent() {
-    globals::latex_formatter.lock().unwrap().decrease_indent();
+    let mut escaper = globals::latex_formatter.lock().unwrap();
+    escaper.finish().unwrap();
+    escaper.get_mut().decrease_indent();
 }
 
 #[wasm_bindgen]
 pub fn limit_newlines(num: u32) {
-    globals::latex_formatter.lock().unwrap().limit_newlines(num);
+    let mut escaper = globals::latex_formatter.lock().unwrap();
+    escaper.finish().unwrap();
+    escaper.get_mut().limit_newlines(num);
 }